@@ -1,19 +1,312 @@
+use img2pdf::{Img2Pdf, Margins, PageSize};
+use std::cmp::Ordering;
 use std::env;
-use img2pdf::img2pdf_from_file;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// The file extensions picked up from directories, compared case-insensitively.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "tif", "tiff", "gif", "bmp", "webp"];
+
+/// Compares file names so that runs of digits are ordered by their numeric
+/// value, putting `page2.png` before `page10.png`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        let ordering = if x.is_ascii_digit() && y.is_ascii_digit() {
+            let a_end = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+            let b_end = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+            let a_digits = a[..a_end].trim_start_matches('0');
+            let b_digits = b[..b_end].trim_start_matches('0');
+            let ordering = a_digits
+                .len()
+                .cmp(&b_digits.len())
+                .then(a_digits.cmp(b_digits));
+            (a, b) = (&a[a_end..], &b[b_end..]);
+            ordering
+        } else {
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+            x.cmp(&y)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Matches a file name against a pattern where `*` matches any run of
+/// characters and `?` any single character.
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| glob_match(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && glob_match(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && glob_match(rest, &name[1..]),
+    }
+}
+
+/// Expands an input argument into image paths.
+///
+/// A directory yields the images in it, and a pattern with `*` or `?` in
+/// its file name the files matching it, both in natural order. Anything else,
+/// including `-`, is taken as it is.
+fn expand_input(arg: &str) -> io::Result<Vec<String>> {
+    let path = Path::new(arg);
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    // `None` selects images by extension, `Some` by the file name pattern.
+    let (dir, pattern) = if path.is_dir() {
+        (path, None)
+    } else if file_name.contains(['*', '?']) {
+        let dir = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        (dir, Some(file_name.chars().collect::<Vec<_>>()))
+    } else {
+        return Ok(vec![arg.to_string()]);
+    };
+    let matches = |entry: &Path| match &pattern {
+        None => entry
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                IMAGE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+            }),
+        Some(pattern) => {
+            let name: Vec<char> = entry
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("")
+                .chars()
+                .collect();
+            glob_match(pattern, &name)
+        }
+    };
+
+    let mut paths = Vec::new();
+    let entries =
+        fs::read_dir(dir).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", arg, e)))?;
+    for entry in entries {
+        let entry = entry?.path();
+        if entry.is_file() && matches(&entry) {
+            paths.push(entry.to_string_lossy().into_owned());
+        }
+    }
+    if paths.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{}: no images found", arg),
+        ));
+    }
+    paths.sort_by(|a, b| natural_cmp(a, b));
+    Ok(paths)
+}
+
+/// Reads an input image, with `-` meaning standard input.
+fn read_input(path: &str) -> io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut data = Vec::new();
+        io::stdin().lock().read_to_end(&mut data)?;
+        Ok(data)
+    } else {
+        fs::read(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+    }
+}
+
+/// Shows how many of several images have been converted.
+fn show_progress(done: usize, total: usize) {
+    if total > 1 {
+        eprint!("\rConverting {}/{}", done, total);
+        if done == total {
+            eprintln!();
+        }
+    }
+}
+
+/// Converts each image in `input_dir` to its own PDF in `output_dir`,
+/// listing the files skipped, and exits on failure.
+fn split(converter: &Img2Pdf, input_dir: &str, output_dir: &str, recursive: bool) {
+    match converter.convert_directory(input_dir, output_dir, recursive) {
+        Ok(report) => {
+            for skipped in &report.skipped {
+                eprintln!("Skipped {}: not an image", skipped.display());
+            }
+            for (_, output) in &report.converted {
+                println!("PDF created successfully: {}", output.display());
+            }
+        }
+        Err(e) => {
+            eprintln!("Error creating PDF: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Prints the usage text to stderr.
+fn print_usage(program: &str) {
+    eprintln!(
+        "Usage: {} [options] <input_image>... [<output_pdf>]",
+        program
+    );
+    eprintln!(
+        "       {} [options] --split <input_dir> <output_dir>",
+        program
+    );
+    eprintln!("Use - to read an image from stdin or to write the PDF to stdout.");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!(
+        "  --page <size>      Page size: a4, letter, legal, fit or <width>x<height> in points"
+    );
+    eprintln!("  --margin <pt>      Margin on every side of the page, in points");
+    eprintln!("  --fit              Size each page to its image (the default)");
+    eprintln!("  --quality <1-100>  Re-encode images as JPEG at this quality");
+    eprintln!("  --flatten          Composite transparent images onto white");
+    eprintln!("  --all-frames       Add every frame of animated GIFs and multi-page TIFFs");
+    eprintln!("  --no-clobber       Fail instead of overwriting an existing output file");
+    eprintln!(
+        "  --split            Convert each image in <input_dir> to its own PDF in <output_dir>"
+    );
+    eprintln!("  --recursive        With --split, also convert images in subdirectories");
+}
+
+/// Parses a `--page` value.
+fn parse_page_size(value: &str) -> Option<PageSize> {
+    match value.to_ascii_lowercase().as_str() {
+        "a4" => Some(PageSize::A4),
+        "letter" => Some(PageSize::Letter),
+        "legal" => Some(PageSize::Legal),
+        "fit" => Some(PageSize::Fit),
+        custom => {
+            let (width, height) = custom.split_once('x')?;
+            Some(PageSize::Custom {
+                width_pt: width.parse().ok()?,
+                height_pt: height.parse().ok()?,
+            })
+        }
+    }
+}
+
+/// The options that choose what the program does rather than how images
+/// are converted.
+#[derive(Default)]
+struct Flags {
+    split: bool,
+    recursive: bool,
+}
+
+/// Splits the arguments into the configured converter, the positional
+/// arguments and the other flags given.
+fn parse_args(args: &[String]) -> Result<(Img2Pdf, Vec<String>, Flags), String> {
+    let mut converter = Img2Pdf::new();
+    let mut positional = Vec::new();
+    let mut flags = Flags::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--page" => {
+                let value = value()?;
+                let page_size = parse_page_size(value)
+                    .ok_or_else(|| format!("invalid page size: {}", value))?;
+                converter = converter.page_size(page_size);
+            }
+            "--margin" => {
+                let value = value()?;
+                let margin = value
+                    .parse()
+                    .map_err(|_| format!("invalid margin: {}", value))?;
+                converter = converter.margins(Margins::uniform(margin));
+            }
+            "--fit" => converter = converter.page_size(PageSize::Fit),
+            "--quality" => {
+                let value = value()?;
+                let quality = value
+                    .parse()
+                    .ok()
+                    .filter(|quality| (1..=100).contains(quality))
+                    .ok_or_else(|| format!("invalid quality, expected 1 to 100: {}", value))?;
+                converter = converter.jpeg_quality(quality);
+            }
+            "--flatten" => converter = converter.flatten([255, 255, 255]),
+            "--all-frames" => converter = converter.all_frames(true),
+            "--no-clobber" => converter = converter.overwrite(false),
+            "--split" => flags.split = true,
+            "--recursive" => flags.recursive = true,
+            flag if flag.starts_with("--") => return Err(format!("unknown option: {}", flag)),
+            _ => positional.push(arg.clone()),
+        }
+    }
+    Ok((converter, positional, flags))
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    
-    if args.len() != 3 {
-        eprintln!("Usage: {} <input_image> <output_pdf>", args[0]);
+
+    let (converter, positional, flags) = match parse_args(&args[1..]) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            print_usage(&args[0]);
+            std::process::exit(1);
+        }
+    };
+
+    if positional.is_empty() || (flags.split && positional.len() != 2) {
+        print_usage(&args[0]);
         std::process::exit(1);
     }
 
-    let input_path = &args[1];
-    let output_path = &args[2];
+    if flags.split {
+        split(&converter, &positional[0], &positional[1], flags.recursive);
+        return;
+    }
+
+    // A lone argument is the input, and the PDF goes to stdout.
+    let (output_path, input_paths) = if positional.len() == 1 {
+        ("-", &positional[..])
+    } else {
+        let (output_path, input_paths) = positional.split_last().unwrap();
+        (output_path.as_str(), input_paths)
+    };
+    let result = input_paths
+        .iter()
+        .map(|arg| expand_input(arg))
+        .collect::<io::Result<Vec<_>>>()
+        .and_then(|paths| {
+            paths
+                .concat()
+                .iter()
+                .map(|path| read_input(path))
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .map_err(img2pdf::Img2PdfError::from)
+        .and_then(|images| {
+            let images: Vec<&[u8]> = images.iter().map(Vec::as_slice).collect();
+            if output_path == "-" {
+                // Stdout is written as bytes, without any newline translation.
+                let mut stdout = io::stdout().lock();
+                converter.convert_multiple_to_writer(&images, &mut stdout, show_progress)
+            } else {
+                // The PDF replaces an existing file only once it is complete,
+                // and with `--no-clobber` not at all.
+                converter.convert_multiple_bytes_to_file(&images, output_path, show_progress)
+            }
+        });
 
-    match img2pdf_from_file(input_path, output_path) {
+    match result {
+        // Keep stdout clean when the PDF is written there.
+        Ok(_) if output_path == "-" => {}
         Ok(_) => println!("PDF created successfully: {}", output_path),
-        Err(e) => eprintln!("Error creating PDF: {}", e),
+        Err(e) => {
+            eprintln!("Error creating PDF: {}", e);
+            std::process::exit(1);
+        }
     }
 }
@@ -0,0 +1,313 @@
+//! The standard security handler of PDF (ISO 32000-1, 7.6.3): password-based
+//! RC4 and AES-128 encryption of strings and streams.
+//!
+//! Only what writing an encrypted file needs is here: deriving the keys and
+//! the `/O` and `/U` entries from the passwords, and encrypting data. RC4 and
+//! AES are small enough to implement directly, like MD5.
+
+use crate::md5::{md5, Md5};
+
+/// The bytes passwords are padded with to 32 bytes.
+const PADDING: [u8; 32] = [
+    0x28, 0xBF, 0x4E, 0x5E, 0x4E, 0x75, 0x8A, 0x41, 0x64, 0x00, 0x4E, 0x56, 0xFF, 0xFA, 0x01, 0x08,
+    0x2E, 0x2E, 0x00, 0xB6, 0xD0, 0x68, 0x3E, 0x80, 0x2F, 0x0C, 0xA9, 0xFE, 0x64, 0x53, 0x69, 0x7A,
+];
+
+/// Every permission granted. The two lowest bits must be zero.
+const PERMISSIONS: i32 = -4;
+
+/// Encrypts the strings and streams of a document with a file key derived
+/// from the passwords and the first file identifier.
+pub(crate) struct Encryptor {
+    key: [u8; 16],
+    aes: bool,
+    owner_entry: Vec<u8>,
+    user_entry: Vec<u8>,
+    /// Mixed into the AES initialization vectors.
+    iv_seed: [u8; 16],
+}
+
+impl Encryptor {
+    /// Derives the keys for a document with the given passwords and `/ID`,
+    /// using revision 3 (RC4) or 4 (AES-128) of the security handler.
+    pub(crate) fn new(
+        user_password: &str,
+        owner_password: &str,
+        aes: bool,
+        id: &[[u8; 16]; 2],
+    ) -> Self {
+        let user = pad_password(user_password);
+        let owner = if owner_password.is_empty() {
+            user
+        } else {
+            pad_password(owner_password)
+        };
+
+        // Algorithm 3: the owner entry is the user password encrypted with a
+        // key derived from the owner password.
+        let mut owner_key = md5(&owner);
+        for _ in 0..50 {
+            owner_key = md5(&owner_key);
+        }
+        let owner_entry = rc4_rounds(&owner_key, &user);
+
+        // Algorithm 2: the file key.
+        let mut hasher = Md5::new();
+        hasher.update(&user);
+        hasher.update(&owner_entry);
+        hasher.update(&PERMISSIONS.to_le_bytes());
+        hasher.update(&id[0]);
+        let mut key = hasher.finalize();
+        for _ in 0..50 {
+            key = md5(&key);
+        }
+
+        // Algorithm 5: the user entry lets readers check a password.
+        let mut hasher = Md5::new();
+        hasher.update(&PADDING);
+        hasher.update(&id[0]);
+        let mut user_entry = rc4_rounds(&key, &hasher.finalize());
+        user_entry.resize(32, 0);
+
+        Encryptor {
+            key,
+            aes,
+            owner_entry,
+            user_entry,
+            iv_seed: id[1],
+        }
+    }
+
+    /// The entries of the `/Encrypt` dictionary.
+    pub(crate) fn dictionary(&self) -> String {
+        let filter = if self.aes {
+            "/V 4 /R 4 /Length 128 /CF << /StdCF << /AuthEvent /DocOpen /CFM /AESV2 /Length 16 >> >> /StmF /StdCF /StrF /StdCF"
+        } else {
+            "/V 2 /R 3 /Length 128"
+        };
+        format!(
+            "/Filter /Standard {} /O {} /U {} /P {}",
+            filter,
+            crate::pdf_hex_string(&self.owner_entry),
+            crate::pdf_hex_string(&self.user_entry),
+            PERMISSIONS
+        )
+    }
+
+    /// Encrypts a string or stream of object `id`.
+    pub(crate) fn encrypt(&self, id: u32, data: &[u8]) -> Vec<u8> {
+        // Algorithm 1: each object has its own key.
+        let mut hasher = Md5::new();
+        hasher.update(&self.key);
+        hasher.update(&id.to_le_bytes()[..3]);
+        hasher.update(&[0, 0]);
+        if !self.aes {
+            return rc4(&hasher.finalize(), data);
+        }
+        hasher.update(b"sAlT");
+        let key = hasher.finalize();
+
+        // The initialization vector needs to be unpredictable, not secret.
+        let mut hasher = Md5::new();
+        hasher.update(&key);
+        hasher.update(&self.iv_seed);
+        hasher.update(data);
+        let iv = hasher.finalize();
+        let mut encrypted = iv.to_vec();
+        encrypted.extend(aes128_cbc(&key, &iv, data));
+        encrypted
+    }
+}
+
+/// Pads or truncates a password to 32 bytes. Characters outside Latin-1,
+/// which PDFDocEncoding mostly matches, become `?`.
+fn pad_password(password: &str) -> [u8; 32] {
+    let mut padded = PADDING;
+    let bytes = password
+        .chars()
+        .map(|c| u8::try_from(c).unwrap_or(b'?'))
+        .chain(PADDING)
+        .take(32);
+    for (slot, byte) in padded.iter_mut().zip(bytes) {
+        *slot = byte;
+    }
+    padded
+}
+
+/// Encrypts `data` with RC4 under `key`, then 19 more times under `key` with
+/// each byte XORed with the round number, as revision 3 requires.
+fn rc4_rounds(key: &[u8; 16], data: &[u8]) -> Vec<u8> {
+    let mut data = rc4(key, data);
+    for round in 1..=19u8 {
+        let round_key: Vec<u8> = key.iter().map(|b| b ^ round).collect();
+        data = rc4(&round_key, &data);
+    }
+    data
+}
+
+/// RC4, which encrypts and decrypts alike.
+fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut state: [u8; 256] = std::array::from_fn(|i| i as u8);
+    let mut j = 0u8;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[i as usize]);
+            state.swap(i as usize, j as usize);
+            byte ^ state[state[i as usize].wrapping_add(state[j as usize]) as usize]
+        })
+        .collect()
+}
+
+/// Multiplies two elements of GF(2^8) modulo the AES polynomial.
+const fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1B } else { 0 };
+        b >>= 1;
+    }
+    product
+}
+
+/// Builds the AES S-box: the multiplicative inverse followed by the affine
+/// transformation.
+const fn sbox() -> [u8; 256] {
+    let mut sbox = [0; 256];
+    let mut x = 0;
+    while x < 256 {
+        // x^254 is the inverse of x, and 0 maps to 0.
+        let mut inverse = 1;
+        let mut i = 0;
+        while i < 254 {
+            inverse = gf_mul(inverse, x as u8);
+            i += 1;
+        }
+        if x == 0 {
+            inverse = 0;
+        }
+        sbox[x] = inverse
+            ^ inverse.rotate_left(1)
+            ^ inverse.rotate_left(2)
+            ^ inverse.rotate_left(3)
+            ^ inverse.rotate_left(4)
+            ^ 0x63;
+        x += 1;
+    }
+    sbox
+}
+
+const SBOX: [u8; 256] = sbox();
+
+/// Expands an AES-128 key into the 11 round keys.
+fn aes128_round_keys(key: &[u8; 16]) -> [[u8; 16]; 11] {
+    let mut words = [[0u8; 4]; 44];
+    for (word, chunk) in words.iter_mut().zip(key.chunks_exact(4)) {
+        word.copy_from_slice(chunk);
+    }
+    let mut round_constant = 1u8;
+    for i in 4..44 {
+        let mut word = words[i - 1];
+        if i % 4 == 0 {
+            word.rotate_left(1);
+            word = word.map(|b| SBOX[b as usize]);
+            word[0] ^= round_constant;
+            round_constant = gf_mul(round_constant, 2);
+        }
+        for (byte, previous) in word.iter_mut().zip(words[i - 4]) {
+            *byte ^= previous;
+        }
+        words[i] = word;
+    }
+
+    let mut round_keys = [[0u8; 16]; 11];
+    for (round_key, round_words) in round_keys.iter_mut().zip(words.chunks_exact(4)) {
+        for (chunk, word) in round_key.chunks_exact_mut(4).zip(round_words) {
+            chunk.copy_from_slice(word);
+        }
+    }
+    round_keys
+}
+
+/// Encrypts one 16-byte block with AES-128.
+fn aes128_block(round_keys: &[[u8; 16]; 11], block: &mut [u8; 16]) {
+    let add_round_key = |block: &mut [u8; 16], round_key: &[u8; 16]| {
+        for (byte, key) in block.iter_mut().zip(round_key) {
+            *byte ^= key;
+        }
+    };
+
+    add_round_key(block, &round_keys[0]);
+    for (round, round_key) in round_keys.iter().enumerate().skip(1) {
+        // SubBytes and ShiftRows, with the block stored column by column.
+        let state = *block;
+        for column in 0..4 {
+            for row in 0..4 {
+                block[4 * column + row] = SBOX[state[4 * ((column + row) % 4) + row] as usize];
+            }
+        }
+        // MixColumns, skipped in the last round.
+        if round < 10 {
+            for column in block.chunks_exact_mut(4) {
+                let [a, b, c, d] = [column[0], column[1], column[2], column[3]];
+                column[0] = gf_mul(a, 2) ^ gf_mul(b, 3) ^ c ^ d;
+                column[1] = a ^ gf_mul(b, 2) ^ gf_mul(c, 3) ^ d;
+                column[2] = a ^ b ^ gf_mul(c, 2) ^ gf_mul(d, 3);
+                column[3] = gf_mul(a, 3) ^ b ^ c ^ gf_mul(d, 2);
+            }
+        }
+        add_round_key(block, round_key);
+    }
+}
+
+/// Encrypts `data` with AES-128 in CBC mode, padded as in PKCS #7.
+fn aes128_cbc(key: &[u8; 16], iv: &[u8; 16], data: &[u8]) -> Vec<u8> {
+    let round_keys = aes128_round_keys(key);
+    let padding = 16 - data.len() % 16;
+    let mut encrypted = Vec::with_capacity(data.len() + padding);
+    let mut previous = *iv;
+    let padded = data
+        .iter()
+        .copied()
+        .chain(std::iter::repeat_n(padding as u8, padding));
+    let mut block = [0u8; 16];
+    for (i, byte) in padded.enumerate() {
+        block[i % 16] = byte ^ previous[i % 16];
+        if i % 16 == 15 {
+            aes128_block(&round_keys, &mut block);
+            encrypted.extend_from_slice(&block);
+            previous = block;
+        }
+    }
+    encrypted
+}
+
+#[test]
+fn test_known_ciphertexts() {
+    // The well-known RC4 example, and the AES-128 example of FIPS-197,
+    // appendix C.1.
+    assert_eq!(
+        rc4(b"Key", b"Plaintext"),
+        [0xBB, 0xF3, 0x16, 0xE8, 0xD9, 0x40, 0xAF, 0x0A, 0xD3]
+    );
+    let key: [u8; 16] = std::array::from_fn(|i| i as u8);
+    let mut block: [u8; 16] = std::array::from_fn(|i| (i as u8) * 0x11);
+    aes128_block(&aes128_round_keys(&key), &mut block);
+    assert_eq!(
+        block,
+        [
+            0x69, 0xC4, 0xE0, 0xD8, 0x6A, 0x7B, 0x04, 0x30, 0xD8, 0xCD, 0xB7, 0x80, 0x70, 0xB4,
+            0xC5, 0x5A
+        ]
+    );
+    // A whole block of padding follows data of a multiple of 16 bytes.
+    assert_eq!(aes128_cbc(&key, &[0; 16], &[0; 16]).len(), 32);
+}
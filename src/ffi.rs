@@ -0,0 +1,107 @@
+//! A C interface to the conversion, for callers in other languages, enabled
+//! with the `ffi` feature.
+//!
+//! The crate is built as a Rust library only; the shared library is built
+//! with `cargo rustc --lib --crate-type cdylib --features ffi`.
+//!
+//! The functions never unwind into the caller: errors, panics included, are
+//! returned as the `IMG2PDF_*` codes below.
+//!
+//! Memory is owned by the side that allocated it. The input stays the
+//! caller's and is only read during the call. The PDF is allocated by this
+//! library and handed to the caller, who must release it with
+//! [`img2pdf_free`], passing back the pointer and length it was given, and
+//! must not free it any other way.
+
+use crate::{Img2Pdf, Img2PdfError};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// The conversion succeeded.
+pub const IMG2PDF_OK: i32 = 0;
+/// A pointer argument was null.
+pub const IMG2PDF_ERROR_NULL_POINTER: i32 = 1;
+/// Reading the input or writing the output failed.
+pub const IMG2PDF_ERROR_IO: i32 = 2;
+/// The input could not be decoded as an image.
+pub const IMG2PDF_ERROR_DECODE: i32 = 3;
+/// The input is not supported.
+pub const IMG2PDF_ERROR_UNSUPPORTED: i32 = 4;
+/// The conversion panicked, which is a bug.
+pub const IMG2PDF_ERROR_PANIC: i32 = 5;
+
+/// Converts the image of `in_len` bytes at `in_ptr` to a PDF with the
+/// default options, like [`img2pdf_from_bytes`](crate::img2pdf_from_bytes).
+///
+/// On success, stores a pointer to the PDF in `*out_ptr` and its length in
+/// `*out_len_ptr`, and returns [`IMG2PDF_OK`]. The PDF must be released with
+/// [`img2pdf_free`]. On error, returns one of the other codes and stores a
+/// null pointer and a length of 0, if the output pointers are not null.
+///
+/// # Safety
+///
+/// `in_ptr` must point to `in_len` readable bytes, and `out_ptr` and
+/// `out_len_ptr` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn img2pdf_convert(
+    in_ptr: *const u8,
+    in_len: usize,
+    out_ptr: *mut *mut u8,
+    out_len_ptr: *mut usize,
+) -> i32 {
+    if out_ptr.is_null() || out_len_ptr.is_null() {
+        return IMG2PDF_ERROR_NULL_POINTER;
+    }
+    *out_ptr = ptr::null_mut();
+    *out_len_ptr = 0;
+    if in_ptr.is_null() {
+        return IMG2PDF_ERROR_NULL_POINTER;
+    }
+
+    let img_data = std::slice::from_raw_parts(in_ptr, in_len);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| Img2Pdf::new().convert_bytes(img_data)));
+    match result {
+        Ok(Ok(pdf_data)) => {
+            let pdf_data = pdf_data.into_boxed_slice();
+            *out_len_ptr = pdf_data.len();
+            *out_ptr = Box::into_raw(pdf_data).cast::<u8>();
+            IMG2PDF_OK
+        }
+        Ok(Err(Img2PdfError::Io(_))) => IMG2PDF_ERROR_IO,
+        Ok(Err(Img2PdfError::Decode(_))) => IMG2PDF_ERROR_DECODE,
+        Ok(Err(Img2PdfError::Unsupported(_))) => IMG2PDF_ERROR_UNSUPPORTED,
+        Err(_) => IMG2PDF_ERROR_PANIC,
+    }
+}
+
+/// Releases a PDF returned by [`img2pdf_convert`]. A null `ptr` is ignored.
+///
+/// # Safety
+///
+/// `ptr` and `len` must be a pointer and length stored by
+/// [`img2pdf_convert`], and the PDF must not be used or released again.
+#[no_mangle]
+pub unsafe extern "C" fn img2pdf_free(ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len)));
+    }
+}
+
+#[test]
+fn test_convert_and_free() {
+    let img_data = std::fs::read("examples/sample_image.jpg").expect("Failed to read image");
+    let (mut out, mut out_len) = (ptr::null_mut(), 0);
+    let code =
+        unsafe { img2pdf_convert(img_data.as_ptr(), img_data.len(), &mut out, &mut out_len) };
+    assert_eq!(code, IMG2PDF_OK);
+    let pdf_data = unsafe { std::slice::from_raw_parts(out, out_len) };
+    assert!(pdf_data.starts_with(b"%PDF-"));
+    unsafe { img2pdf_free(out, out_len) };
+
+    let code = unsafe { img2pdf_convert(b"not an image".as_ptr(), 12, &mut out, &mut out_len) };
+    assert_eq!(code, IMG2PDF_ERROR_DECODE);
+    assert!(out.is_null());
+    assert_eq!(out_len, 0);
+    let code = unsafe { img2pdf_convert(ptr::null(), 0, &mut out, &mut out_len) };
+    assert_eq!(code, IMG2PDF_ERROR_NULL_POINTER);
+}
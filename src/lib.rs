@@ -1,9 +1,93 @@
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
-use image::{DynamicImage, GenericImageView};
+use image::{ColorType, DynamicImage, GenericImageView, ImageFormat};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
 use std::fs::File;
-use std::io::{self, Read, Write};
-use std::path::Path;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Logs a message through the `log` crate when the `log` feature is enabled.
+/// Without it, the arguments are still checked but nothing is formatted.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::debug!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)*);
+    };
+}
+
+/// Like `debug!`, at the info level.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::info!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)*);
+    };
+}
+
+mod encrypt;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod linearize;
+mod md5;
+mod parse;
+mod srgb;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod wasm;
+mod xmp;
+
+/// The error type returned by the conversion functions.
+#[derive(Debug)]
+pub enum Img2PdfError {
+    /// Reading the input or writing the output failed.
+    Io(io::Error),
+    /// The input could not be decoded as an image.
+    Decode(image::ImageError),
+    /// The input or the requested options are not supported.
+    Unsupported(String),
+}
+
+impl fmt::Display for Img2PdfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Img2PdfError::Io(e) => write!(f, "{}", e),
+            Img2PdfError::Decode(e) => write!(f, "Failed to decode image: {}", e),
+            Img2PdfError::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Img2PdfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Img2PdfError::Io(e) => Some(e),
+            Img2PdfError::Decode(e) => Some(e),
+            Img2PdfError::Unsupported(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Img2PdfError {
+    fn from(e: io::Error) -> Self {
+        Img2PdfError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for Img2PdfError {
+    fn from(e: image::ImageError) -> Self {
+        Img2PdfError::Decode(e)
+    }
+}
+
+/// A `Result` alias with `Img2PdfError` as the error type.
+pub type Result<T> = std::result::Result<T, Img2PdfError>;
 
 /// Converts an image from binary data to a PDF as binary data.
 ///
@@ -13,181 +97,9763 @@ use std::path::Path;
 ///
 /// # Returns
 ///
-/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `io::Error` on failure.
-pub fn img2pdf_from_bytes(img_data: &[u8]) -> io::Result<Vec<u8>> {
-    let img = image::load_from_memory(img_data).expect("Failed to open image");
-    let (width, height) = img.dimensions();
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_from_bytes(img_data: &[u8]) -> Result<Vec<u8>> {
+    Img2Pdf::new().convert_bytes(img_data)
+}
+
+/// Converts an image from binary data to a PDF as binary data, and describes
+/// the PDF produced.
+///
+/// # Arguments
+///
+/// * `img_data` - A slice of bytes representing the image data.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data and a [`ConversionReport`] on success,
+/// or an `Img2PdfError` on failure.
+pub fn img2pdf_from_bytes_with_report(img_data: &[u8]) -> Result<(Vec<u8>, ConversionReport)> {
+    Img2Pdf::new().convert_multiple_bytes_with_report(&[img_data])
+}
 
-    let (rgb_img, mask_img) = separate_rgb_and_alpha(img);
+/// Computes the size of the PDF an image converts to, without keeping the
+/// PDF.
+///
+/// The image goes through the same encoding and compression as in
+/// [`img2pdf_from_bytes`], so the size is exact, but the bytes are counted
+/// and dropped rather than stored.
+///
+/// # Arguments
+///
+/// * `img_data` - A slice of bytes representing the image data.
+///
+/// # Returns
+///
+/// A `Result` containing the size of the PDF in bytes on success, or an
+/// `Img2PdfError` on failure.
+pub fn estimate_pdf_size(img_data: &[u8]) -> Result<usize> {
+    Img2Pdf::new().estimate_size(img_data)
+}
 
-    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
-    encoder.write_all(&rgb_img)?;
-    let rgb_data = encoder.finish()?;
+/// Converts an image from binary data to a PDF in which the pixels of one
+/// colour are transparent.
+///
+/// The image gets a `/Mask` colour-key range instead of an `/SMask`, like the
+/// single transparent colour of a GIF. An alpha channel in the image is
+/// ignored.
+///
+/// # Arguments
+///
+/// * `img_data` - A slice of bytes representing the image data.
+/// * `key` - The RGB colour to make transparent.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_with_color_key(img_data: &[u8], key: [u8; 3]) -> Result<Vec<u8>> {
+    Img2Pdf::new().color_key(key).convert_bytes(img_data)
+}
 
-    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
-    encoder.write_all(&mask_img)?;
-    let mask_data = encoder.finish()?;
+/// Converts an image from binary data to a PDF, streaming the PDF to `out`.
+///
+/// The PDF is written object by object as it is generated, so it is never
+/// held in memory as a whole.
+///
+/// # Arguments
+///
+/// * `img_data` - A slice of bytes representing the image data.
+/// * `out` - The writer to send the PDF to.
+///
+/// # Returns
+///
+/// A `Result` indicating success or failure.
+pub fn img2pdf_to_writer<W: Write>(img_data: &[u8], out: &mut W) -> Result<()> {
+    Img2Pdf::new().convert_to_writer(img_data, out)
+}
 
-    let mut pdf_data = Vec::new();
+/// Converts an image from binary data to a PDF written into `file`, a file
+/// the caller has already opened, for instance with particular permissions.
+///
+/// The PDF is buffered and written from the current position of `file`,
+/// which is neither truncated nor synced. Unlike the functions that create
+/// the output themselves, a failed conversion may leave a partial PDF.
+///
+/// # Arguments
+///
+/// * `img_data` - A slice of bytes representing the image data.
+/// * `file` - The open file to write the PDF to.
+///
+/// # Returns
+///
+/// A `Result` indicating success or failure.
+pub fn img2pdf_write_to_file(img_data: &[u8], file: &mut File) -> Result<()> {
+    let mut out = BufWriter::new(file);
+    img2pdf_to_writer(img_data, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
 
-    writeln!(pdf_data, "%PDF-1.4")?;
+/// Converts an image read from `reader` to a PDF as binary data.
+///
+/// # Arguments
+///
+/// * `reader` - The source of the image data, read to the end.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_from_reader<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    Img2Pdf::new().convert_reader(reader)
+}
 
-    let image_object_id = 2;
-    let image_object_pos = pdf_data.len();
-    writeln!(
-        pdf_data,
-        "{} 0 obj\n<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /FlateDecode /Length {} /SMask {} 0 R >>",
-        image_object_id,
-        width,
-        height,
-        rgb_data.len(),
-        image_object_id + 1
-    )?;
-    writeln!(pdf_data, "stream")?;
-    pdf_data.extend(&rgb_data);
-    writeln!(pdf_data, "endstream\nendobj")?;
+/// The size of the pages in the generated PDF.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PageSize {
+    /// Each page is exactly as large as its image, one point per pixel.
+    #[default]
+    Fit,
+    /// ISO A4, 210 × 297 mm.
+    A4,
+    /// US Letter, 8.5 × 11 in.
+    Letter,
+    /// US Legal, 8.5 × 14 in.
+    Legal,
+    /// An arbitrary page size in points (1/72 in).
+    Custom { width_pt: f32, height_pt: f32 },
+}
 
-    let mask_object_id = image_object_id + 1;
-    let mask_object_pos = pdf_data.len();
-    writeln!(
-        pdf_data,
-        "{} 0 obj\n<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceGray /BitsPerComponent 8 /Filter /FlateDecode /Length {} >>",
-        mask_object_id,
-        width,
-        height,
-        mask_data.len()
-    )?;
-    writeln!(pdf_data, "stream")?;
-    pdf_data.extend(&mask_data);
-    writeln!(pdf_data, "endstream\nendobj")?;
+impl PageSize {
+    /// Returns the page dimensions in points, or `None` for `Fit`.
+    fn dimensions(&self) -> Option<(f32, f32)> {
+        match *self {
+            PageSize::Fit => None,
+            PageSize::A4 => Some((595.28, 841.89)),
+            PageSize::Letter => Some((612.0, 792.0)),
+            PageSize::Legal => Some((612.0, 1008.0)),
+            PageSize::Custom {
+                width_pt,
+                height_pt,
+            } => Some((width_pt, height_pt)),
+        }
+    }
+}
 
-    let content_stream_object_id = 5;
-    let content_stream_pos = pdf_data.len();
-    let content = format!(
-        "q\n{} 0 0 {} 0 0 cm\n/Im{} Do\nQ",
-        width, height, image_object_id
-    );
-    writeln!(
-        pdf_data,
-        "{} 0 obj\n<< /Length {} >>",
-        content_stream_object_id,
-        content.len()
-    )?;
-    writeln!(pdf_data, "stream\n{}\nendstream\nendobj", content)?;
+/// Which way up the pages in the generated PDF are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Orientation {
+    /// Pages keep the shape of their page size, or of their image for
+    /// `PageSize::Fit`.
+    #[default]
+    Auto,
+    /// Every page is at least as tall as it is wide.
+    Portrait,
+    /// Every page is at least as wide as it is tall.
+    Landscape,
+}
 
-    let page_object_id = 4;
-    let page_object_pos = pdf_data.len();
-    writeln!(
-        pdf_data,
-        "{} 0 obj\n<< /Type /Page /Parent 1 0 R /MediaBox [0 0 {} {}] /Contents {} 0 R /Resources << /XObject << /Im{} {} 0 R >> >> >>",
-        page_object_id, width, height, content_stream_object_id, image_object_id, image_object_id
-    )?;
-    writeln!(pdf_data, "endobj")?;
+/// How the colour channels of images with an alpha channel relate to alpha.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlphaMode {
+    /// The colour channels are independent of alpha, as in PNG.
+    #[default]
+    Straight,
+    /// The colour channels are already multiplied by alpha, as some
+    /// compositing pipelines write them. They are divided by alpha before
+    /// writing, since an `/SMask` expects straight colours.
+    Premultiplied,
+}
 
-    let pages_object_pos = pdf_data.len();
-    writeln!(
-        pdf_data,
-        "1 0 obj\n<< /Type /Pages /Kids [ {} 0 R ] /Count 1 >>",
-        page_object_id
-    )?;
-    writeln!(pdf_data, "endobj")?;
+/// How colours outside the output device's gamut are mapped, written as the
+/// image's `/Intent`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingIntent {
+    /// Reproduces in-gamut colours exactly, relative to the medium's white.
+    AbsoluteColorimetric,
+    /// Reproduces in-gamut colours exactly, adjusted to the medium's white.
+    RelativeColorimetric,
+    /// Keeps colours saturated, for business graphics.
+    Saturation,
+    /// Keeps the relationships between colours, for photographs.
+    Perceptual,
+}
+
+impl RenderingIntent {
+    /// The PDF name of the intent.
+    fn name(&self) -> &'static str {
+        match self {
+            RenderingIntent::AbsoluteColorimetric => "/AbsoluteColorimetric",
+            RenderingIntent::RelativeColorimetric => "/RelativeColorimetric",
+            RenderingIntent::Saturation => "/Saturation",
+            RenderingIntent::Perceptual => "/Perceptual",
+        }
+    }
+}
+
+/// A colour space that samples are embedded in as they are, by
+/// [`Img2Pdf::convert_samples`], for inputs the image decoders cannot keep,
+/// such as Lab or spot colour artwork.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PassthroughColorSpace {
+    /// CIE L*a*b*, with samples of L* scaled from 0–100 and of a* and b*
+    /// scaled from their ranges.
+    Lab {
+        /// The diffuse white point in CIE XYZ, such as `[0.9642, 1.0, 0.8249]`
+        /// for D50.
+        white_point: [f32; 3],
+        /// The ranges of a* and b*, as `[a_min, a_max, b_min, b_max]`.
+        range: [f32; 4],
+    },
+    /// Spot colours, with one sample per colorant from no ink at 0 to full
+    /// tint at 255. Each colorant is named and has the CMYK colour that
+    /// stands in for its ink on devices without it.
+    DeviceN {
+        /// The names of the colorants, such as `"PANTONE 185 C"`, with their
+        /// CMYK equivalents from 0 to 1.
+        colorants: Vec<(String, [f32; 4])>,
+    },
+}
+
+impl PassthroughColorSpace {
+    /// The number of samples per pixel.
+    fn components(&self) -> usize {
+        match self {
+            PassthroughColorSpace::Lab { .. } => 3,
+            PassthroughColorSpace::DeviceN { colorants } => colorants.len(),
+        }
+    }
+
+    /// The PDF colour space array. DeviceN needs `tint_transform`, the id of
+    /// the function object that maps its tints to CMYK.
+    fn to_pdf(&self, tint_transform: Option<u32>) -> String {
+        match self {
+            PassthroughColorSpace::Lab { white_point, range } => format!(
+                "[/Lab << /WhitePoint [{} {} {}] /Range [{} {} {} {}] >>]",
+                white_point[0],
+                white_point[1],
+                white_point[2],
+                range[0],
+                range[1],
+                range[2],
+                range[3]
+            ),
+            PassthroughColorSpace::DeviceN { colorants } => format!(
+                "[/DeviceN [{}] /DeviceCMYK {} 0 R]",
+                colorants
+                    .iter()
+                    .map(|(name, _)| pdf_name(name))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                tint_transform.expect("DeviceN needs a tint transform")
+            ),
+        }
+    }
 
-    let catalog_object_pos = pdf_data.len();
-    writeln!(pdf_data, "6 0 obj\n<< /Type /Catalog /Pages 1 0 R >>")?;
-    writeln!(pdf_data, "endobj")?;
+    /// The PostScript calculator function of DeviceN, which adds up the CMYK
+    /// equivalents of the colorants in proportion to their tints, clipped to
+    /// full ink.
+    fn tint_transform(colorants: &[(String, [f32; 4])]) -> String {
+        let n = colorants.len();
+        let mut code = String::from("{");
+        for component in 0..4 {
+            code.push_str(" 0");
+            for (i, (_, cmyk)) in colorants.iter().enumerate() {
+                // Below the tints are the components done so far and the sum.
+                code.push_str(&format!(
+                    " {} index {} mul add",
+                    n - i + component,
+                    cmyk[component]
+                ));
+            }
+            code.push_str(" dup 1 gt { pop 1 } if");
+        }
+        // Drops the tints from under the four components.
+        code.push_str(&format!(" {} 4 roll", n + 4));
+        code.push_str(&" pop".repeat(n));
+        code.push_str(" }");
+        code
+    }
+}
+
+/// The layout of pixels passed in memory, with one byte per channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Gray.
+    Gray8,
+    /// Gray and alpha.
+    GrayA8,
+    /// Red, green and blue.
+    Rgb8,
+    /// Red, green, blue and alpha.
+    Rgba8,
+}
+
+impl PixelFormat {
+    /// The number of channels, and so bytes, of a pixel.
+    fn channels(&self) -> usize {
+        match self {
+            PixelFormat::Gray8 => 1,
+            PixelFormat::GrayA8 => 2,
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Rgba8 => 4,
+        }
+    }
+}
+
+/// Where the image of a page comes from, for [`build_pdf`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PageSource {
+    /// An image file, read when the PDF is built.
+    File(PathBuf),
+    /// Image data, such as the contents of a PNG or JPEG file.
+    Bytes(Vec<u8>),
+    /// Pixels already decoded in memory, as for [`img2pdf_from_raw`].
+    Raw {
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+        format: PixelFormat,
+    },
+}
+
+/// A range of page labels, the page numbers viewers show, from a page up to
+/// the first page of the next range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageLabel {
+    /// The index of the first page of the range, from 0.
+    pub first_page: usize,
+    /// How the pages are numbered, or `None` for the prefix alone.
+    pub style: Option<NumberingStyle>,
+    /// Put before the number of each page, such as `A-`. Without a style,
+    /// this is the whole label.
+    pub prefix: String,
+    /// The number of the first page of the range, from 1.
+    pub start: u32,
+}
+
+impl PageLabel {
+    /// Numbers the pages from `first_page` on in `style`, starting at 1.
+    pub fn numbered(first_page: usize, style: NumberingStyle) -> Self {
+        PageLabel {
+            first_page,
+            style: Some(style),
+            prefix: String::new(),
+            start: 1,
+        }
+    }
 
-    let xref_start = pdf_data.len();
-    writeln!(pdf_data, "xref")?;
-    writeln!(pdf_data, "0 7")?;
-    writeln!(pdf_data, "0000000000 65535 f ")?;
-    writeln!(pdf_data, "{:010} 00000 n ", pages_object_pos)?;
-    writeln!(pdf_data, "{:010} 00000 n ", image_object_pos)?;
-    writeln!(pdf_data, "{:010} 00000 n ", mask_object_pos)?;
-    writeln!(pdf_data, "{:010} 00000 n ", page_object_pos)?;
-    writeln!(pdf_data, "{:010} 00000 n ", content_stream_pos)?;
-    writeln!(pdf_data, "{:010} 00000 n ", catalog_object_pos)?;
+    /// Labels the page `page` with `text`, such as the name of its file.
+    pub fn literal(page: usize, text: impl Into<String>) -> Self {
+        PageLabel {
+            first_page: page,
+            style: None,
+            prefix: text.into(),
+            start: 1,
+        }
+    }
+}
 
-    writeln!(pdf_data, "trailer\n<< /Size 7 /Root 6 0 R >>")?;
-    writeln!(pdf_data, "startxref\n{}", xref_start)?;
-    writeln!(pdf_data, "%%EOF")?;
+/// How the pages of a [`PageLabel`] range are numbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberingStyle {
+    /// 1, 2, 3.
+    Decimal,
+    /// I, II, III.
+    UpperRoman,
+    /// i, ii, iii.
+    LowerRoman,
+    /// A to Z, then AA to ZZ, and so on.
+    UpperLetters,
+    /// a to z, then aa to zz, and so on.
+    LowerLetters,
+}
 
-    Ok(pdf_data)
+impl NumberingStyle {
+    /// The `/S` name of the style.
+    fn to_pdf(self) -> &'static str {
+        match self {
+            NumberingStyle::Decimal => "/D",
+            NumberingStyle::UpperRoman => "/R",
+            NumberingStyle::LowerRoman => "/r",
+            NumberingStyle::UpperLetters => "/A",
+            NumberingStyle::LowerLetters => "/a",
+        }
+    }
 }
 
-/// Separates the RGB and alpha channels of an image.
+/// Converts an image from binary data to a PDF with the given page size.
+///
+/// For anything other than `PageSize::Fit` the image is scaled to fit inside
+/// the page while preserving its aspect ratio, and centered on it.
 ///
 /// # Arguments
 ///
-/// * `img` - The `DynamicImage` to be processed.
+/// * `img_data` - A slice of bytes representing the image data.
+/// * `page` - The size of the page.
 ///
 /// # Returns
 ///
-/// A tuple containing the RGB data and the alpha channel data.
-fn separate_rgb_and_alpha(img: DynamicImage) -> (Vec<u8>, Vec<u8>) {
-    let rgba = img.to_rgba8();
-    let mut rgb = Vec::with_capacity(rgba.len() / 4 * 3);
-    let mut alpha = Vec::with_capacity(rgba.len() / 4);
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_from_bytes_with_page_size(img_data: &[u8], page: PageSize) -> Result<Vec<u8>> {
+    Img2Pdf::new().page_size(page).convert_bytes(img_data)
+}
 
-    for pixel in rgba.pixels() {
-        rgb.push(pixel[0]);
-        rgb.push(pixel[1]);
-        rgb.push(pixel[2]);
-        alpha.push(pixel[3]);
+/// Blank space kept free around the image on each side of the page, in points.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Margins {
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub left: f32,
+}
+
+impl Margins {
+    /// Creates margins that are the same on all four sides.
+    pub fn uniform(margin_pt: f32) -> Self {
+        Margins {
+            top: margin_pt,
+            right: margin_pt,
+            bottom: margin_pt,
+            left: margin_pt,
+        }
     }
+}
 
-    (rgb, alpha)
+/// A keyline stroked around each placed image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Border {
+    /// The width of the line, in points.
+    pub width_pt: f32,
+    /// The colour of the line, as RGB.
+    pub color: [u8; 3],
+    /// Whether the line sits entirely outside the image. Otherwise it is
+    /// centered on the image edge, covering its outermost pixels.
+    pub outside: bool,
 }
 
-/// Converts an image from a file to a PDF file.
+/// Password protection with the standard security handler of PDF.
+///
+/// Every string and stream of the document is encrypted, and readers ask for
+/// the user password to open it. All permissions are granted.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Encryption {
+    /// The password needed to open the document.
+    pub user_password: String,
+    /// The password granting full access. When empty, the user password is
+    /// used.
+    pub owner_password: String,
+    pub method: EncryptionMethod,
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Passwords stay out of logs.
+        f.debug_struct("Encryption")
+            .field("method", &self.method)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The cipher used by [`Encryption`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncryptionMethod {
+    /// RC4 with a 128-bit key, readable by PDF 1.4 readers.
+    Rc4,
+    /// AES with a 128-bit key, which needs PDF 1.6.
+    #[default]
+    Aes128,
+}
+
+/// Converts an image from binary data to a PDF with the given page size and margins.
+///
+/// The image is scaled to fit inside the area left by the margins, preserving
+/// its aspect ratio, and centered within it. With `PageSize::Fit` the page is
+/// the image size plus the margins.
 ///
 /// # Arguments
 ///
-/// * `input_path` - The path to the input image file.
-/// * `output_path` - The path to the output PDF file.
+/// * `img_data` - A slice of bytes representing the image data.
+/// * `page` - The size of the page.
+/// * `margins` - The margins around the image.
 ///
 /// # Returns
 ///
-/// An `io::Result` indicating success or failure.
-pub fn img2pdf_from_file<P: AsRef<Path>>(input_path: P, output_path: P) -> io::Result<()> {
-    let mut input_file = File::open(input_path)?;
-    let mut img_data = Vec::new();
-    input_file.read_to_end(&mut img_data)?;
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_from_bytes_with_margins(
+    img_data: &[u8],
+    page: PageSize,
+    margins: Margins,
+) -> Result<Vec<u8>> {
+    Img2Pdf::new()
+        .page_size(page)
+        .margins(margins)
+        .convert_bytes(img_data)
+}
 
-    let pdf_data = img2pdf_from_bytes(&img_data)?;
+/// Converts an image from binary data to a PDF, sizing the page from the
+/// image's resolution.
+///
+/// The resolution is read from the JPEG JFIF header, the PNG `pHYs` chunk or
+/// the TIFF resolution tags. `dpi_override`, if given, is used instead.
+/// Without either the page has one point per pixel, i.e. 72 DPI.
+///
+/// # Arguments
+///
+/// * `img_data` - A slice of bytes representing the image data.
+/// * `dpi_override` - The resolution to assume, in dots per inch.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_from_bytes_with_dpi(img_data: &[u8], dpi_override: Option<f32>) -> Result<Vec<u8>> {
+    let mut builder = Img2Pdf::new();
+    if let Some(dpi) = dpi_override {
+        builder = builder.dpi(dpi);
+    }
+    builder.convert_bytes(img_data)
+}
 
-    let mut output_file = File::create(output_path)?;
-    output_file.write_all(&pdf_data)?;
+/// Converts an image from binary data to a PDF, compositing any transparency
+/// onto a solid background.
+///
+/// The resulting image XObject has no `/SMask`, which keeps the PDF smaller
+/// and avoids soft masks in viewers that render them poorly.
+///
+/// # Arguments
+///
+/// * `img_data` - A slice of bytes representing the image data.
+/// * `background` - The RGB colour to blend transparent pixels over.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_from_bytes_flatten(img_data: &[u8], background: [u8; 3]) -> Result<Vec<u8>> {
+    Img2Pdf::new().flatten(background).convert_bytes(img_data)
+}
 
-    Ok(())
+/// Converts an image from binary data to a PDF with the given zlib compression level.
+///
+/// The level ranges from 0, which stores the samples uncompressed inside the
+/// zlib stream, to 9. The other functions use 9, i.e. `Compression::best()`,
+/// so pass 9 to get the same output as `img2pdf_from_bytes`. Lower levels are
+/// faster at the cost of larger files.
+///
+/// # Arguments
+///
+/// * `img_data` - A slice of bytes representing the image data.
+/// * `level` - The compression level, 0 to 9.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_from_bytes_with_compression(img_data: &[u8], level: u32) -> Result<Vec<u8>> {
+    Img2Pdf::new().compression(level).convert_bytes(img_data)
 }
 
-#[test]
-fn test_img2pdf_from_bytes() {
-    let mut img_file =
-        File::open("examples/sample_image.jpg").expect("Failed to open sample image");
-    let mut img_data = Vec::new();
-    img_file
-        .read_to_end(&mut img_data)
-        .expect("Failed to read image data");
+/// Converts several images from binary data to a single multi-page PDF.
+///
+/// Each image becomes its own page, sized to the image, in the order given.
+///
+/// # Arguments
+///
+/// * `images` - A slice of byte slices, one per image.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_from_multiple_bytes(images: &[&[u8]]) -> Result<Vec<u8>> {
+    Img2Pdf::new().convert_multiple_bytes(images)
+}
 
-    let pdf_data = img2pdf_from_bytes(&img_data).expect("Failed to convert image to PDF");
+/// Converts an image from binary data to a PDF, downscaling it if it is too
+/// large.
+///
+/// Images with a side longer than `max_px` pixels are resampled with a
+/// Lanczos filter to fit, keeping their aspect ratio and the size of their
+/// page. Smaller images are embedded as they are.
+///
+/// # Arguments
+///
+/// * `img_data` - A slice of bytes representing the image data.
+/// * `max_px` - The longest side allowed, in pixels.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_from_bytes_with_max_dimension(img_data: &[u8], max_px: u32) -> Result<Vec<u8>> {
+    Img2Pdf::new().max_dimension(max_px).convert_bytes(img_data)
+}
 
-    assert!(pdf_data.starts_with(b"%PDF"));
-    assert!(pdf_data.ends_with(b"%%EOF\n"));
+/// Adds an image as a new last page of an existing PDF.
+///
+/// The page is written as an incremental update: the existing PDF is kept
+/// byte for byte, and the new objects, an updated page tree and a new
+/// cross-reference section pointing back to the old one with `/Prev` are
+/// appended. Supports the PDFs this crate writes, and files laid out the
+/// same way.
+///
+/// # Arguments
+///
+/// * `existing_pdf` - The PDF to add the page to.
+/// * `img_data` - A slice of bytes representing the image data.
+///
+/// # Returns
+///
+/// A `Result` containing the updated PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn append_image_to_pdf(existing_pdf: &[u8], img_data: &[u8]) -> Result<Vec<u8>> {
+    Img2Pdf::new().append_bytes(existing_pdf, img_data)
 }
 
-#[test]
-fn test_img2pdf_file() {
-    let input_path = "examples/sample_image.jpg";
-    let output_path = "examples/sample_image.pdf";
-    img2pdf_from_file(input_path, output_path).expect("Failed to convert image to PDF");
+/// Converts a large image from binary data to a PDF with one page per tile.
+///
+/// The image is cut into a grid of `tile_width` × `tile_height` pixel tiles,
+/// in row-major order. Tiles on the right and bottom edges are smaller when
+/// the image does not divide evenly, and so are their pages.
+///
+/// # Arguments
+///
+/// * `img_data` - A slice of bytes representing the image data.
+/// * `tile_width` - The width of a tile in pixels.
+/// * `tile_height` - The height of a tile in pixels.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_tiled(img_data: &[u8], tile_width: u32, tile_height: u32) -> Result<Vec<u8>> {
+    Img2Pdf::new().convert_tiled(img_data, tile_width, tile_height, 0)
+}
 
-    let mut pdf_file = File::open(output_path).expect("Failed to open output PDF");
-    let mut pdf_data = Vec::new();
-    pdf_file
-        .read_to_end(&mut pdf_data)
-        .expect("Failed to read PDF data");
+/// Lays several images out on a contact sheet: a grid of `cols` columns of
+/// square cells, one image per cell, on a page sized to fit the grid.
+///
+/// # Arguments
+///
+/// * `images` - The image data of each cell, in row-major order.
+/// * `cols` - The number of columns of the grid.
+/// * `cell_size_pt` - The width and height of a cell in points. Images are
+///   scaled to fit their cell and centered in it.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_contact_sheet(images: &[&[u8]], cols: usize, cell_size_pt: f32) -> Result<Vec<u8>> {
+    Img2Pdf::new().convert_contact_sheet(images, cols, cell_size_pt)
+}
 
-    assert!(pdf_data.starts_with(b"%PDF"));
-    assert!(pdf_data.ends_with(b"%%EOF\n"));
+/// Packs several images onto as few A4 pages as they fit on at their natural
+/// size, in rows from the top left, to save paper on small scans such as
+/// receipts.
+///
+/// # Arguments
+///
+/// * `images` - The image data, in the order they are placed.
+/// * `spacing_pt` - The space left between neighbouring images, in points.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_packed(images: &[&[u8]], spacing_pt: f32) -> Result<Vec<u8>> {
+    Img2Pdf::new().convert_packed(images, spacing_pt)
+}
+
+/// Converts samples in a colour space the image decoders cannot produce,
+/// such as Lab or spot colours, to a single-page PDF, embedding them as they
+/// are.
+///
+/// # Arguments
+///
+/// * `samples` - The pixels, row by row, with one byte per component.
+/// * `width` - The width of the image in pixels.
+/// * `height` - The height of the image in pixels.
+/// * `color_space` - The colour space the samples are in.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_from_samples(
+    samples: &[u8],
+    width: u32,
+    height: u32,
+    color_space: &PassthroughColorSpace,
+) -> Result<Vec<u8>> {
+    Img2Pdf::new().convert_samples(samples, width, height, color_space)
+}
+
+/// Converts pixels already decoded in memory to a single-page PDF, without
+/// encoding them as an image file first.
+///
+/// # Arguments
+///
+/// * `width` - The width of the image in pixels.
+/// * `height` - The height of the image in pixels.
+/// * `pixels` - The pixels, row by row, in `format`.
+/// * `format` - The channels of each pixel.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_from_raw(
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+    format: PixelFormat,
+) -> Result<Vec<u8>> {
+    Img2Pdf::new().convert_raw(width, height, pixels, format)
+}
+
+/// Converts an image already decoded in memory to a single-page PDF, without
+/// the round trip through an encoded image file.
+///
+/// Unlike [`img2pdf_from_bytes`], which embeds JPEG data as it is, the pixels
+/// are always compressed anew.
+///
+/// # Arguments
+///
+/// * `img` - The decoded image.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+pub fn img2pdf_from_image(img: DynamicImage) -> Result<Vec<u8>> {
+    Img2Pdf::new().convert_image(img)
+}
+
+/// Builds a multi-page PDF with a page for each source, in the order given,
+/// mixing image files, image data and pixels in memory.
+///
+/// # Arguments
+///
+/// * `pages` - Where the image of each page comes from.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an `Img2PdfError` on failure.
+/// If a file cannot be read, the error message names its path.
+pub fn build_pdf(pages: &[PageSource]) -> Result<Vec<u8>> {
+    Img2Pdf::new().convert_pages(pages)
+}
+
+/// Checks that `pdf_data` is a well-formed PDF, as far as its file structure
+/// goes.
+///
+/// This verifies the `%PDF-` header, that `startxref` points at a
+/// cross-reference table or stream, that every object listed there starts
+/// with its `N 0 obj` line at the recorded offset, and that the trailer's
+/// `/Size` matches the objects listed. Incremental updates are followed
+/// through `/Prev`. The contents of the objects are not checked.
+///
+/// # Returns
+///
+/// `Ok(())` for a well-formed PDF, or a description of the first problem
+/// found.
+pub fn validate_pdf(pdf_data: &[u8]) -> std::result::Result<(), String> {
+    parse::validate(pdf_data)
+}
+
+/// Describes an image XObject serialized by [`image_xobject`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageMeta {
+    /// The width of the image in pixels.
+    pub width: u32,
+    /// The height of the image in pixels.
+    pub height: u32,
+    /// The `/ColorSpace` of the image, such as `/DeviceRGB`.
+    pub color_space: String,
+    /// Whether the image has a soft mask for its transparency.
+    pub has_mask: bool,
+    /// The offset of the soft mask object within the serialized bytes.
+    pub mask_offset: Option<usize>,
+}
+
+/// Serializes an image as an image XObject, for placing it on pages built
+/// outside this crate.
+///
+/// The image is encoded the way [`img2pdf_from_bytes`] encodes decoded
+/// images: gray when it has no colour, compressed with zlib, and with a soft
+/// mask when it has transparency.
+///
+/// # Arguments
+///
+/// * `img` - The image to serialize.
+/// * `object_id` - The object id of the XObject. Images with transparency
+///   also use `object_id + 1`, for the soft mask.
+///
+/// # Returns
+///
+/// A `Result` containing the serialized objects, starting with the image
+/// XObject at offset 0, and a description of the image. The objects' offsets
+/// still have to be added to the cross-reference table of the PDF they are
+/// written into.
+pub fn image_xobject(img: &DynamicImage, object_id: u32) -> Result<(Vec<u8>, ImageMeta)> {
+    let options = Options::default();
+    let image = encode_pixels(img, &options, true)?;
+    let mut pdf = PdfWriter::objects(Vec::new(), object_id);
+    let (_, mask_object_id) = write_image_xobject(&mut pdf, &image, &options)?;
+    let meta = ImageMeta {
+        width: image.width,
+        height: image.height,
+        color_space: image.color_space.to_pdf(None),
+        has_mask: mask_object_id.is_some(),
+        mask_offset: mask_object_id.and_then(|id| pdf.offset_of(id)),
+    };
+    Ok((pdf.out, meta))
+}
+
+/// Facts about a PDF produced by a conversion, for checking its output without
+/// parsing it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConversionReport {
+    /// The number of pages.
+    pub page_count: usize,
+    /// The width and height of each page in points, in page order.
+    pub page_sizes: Vec<(f32, f32)>,
+    /// The size of the PDF in bytes.
+    pub byte_size: usize,
+    /// Whether any image was compressed with zlib.
+    pub compressed: bool,
+    /// Whether any JPEG was embedded as it is, without decoding it.
+    pub passthrough: bool,
+}
+
+/// The document properties written to the PDF's `/Info` dictionary.
+///
+/// Fields left as `None` are omitted, except `producer`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Info {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    /// The application that created the original content.
+    pub creator: Option<String>,
+    /// The application that converted it to PDF. Defaults to this crate and
+    /// its version, such as `img2pdf (rust) 0.1.0`.
+    pub producer: Option<String>,
+}
+
+/// The `/Producer` written unless another is set.
+const PRODUCER: &str = concat!("img2pdf (rust) ", env!("CARGO_PKG_VERSION"));
+
+impl Info {
+    /// The `/Producer` to write.
+    pub(crate) fn producer(&self) -> &str {
+        self.producer.as_deref().unwrap_or(PRODUCER)
+    }
+}
+
+/// A configurable image-to-PDF converter.
+///
+/// Start from `Img2Pdf::new()`, chain the setters for the options you need,
+/// then call one of the `convert_*` methods. The free functions in this crate
+/// are shorthands for a default-configured `Img2Pdf`.
+///
+/// ```no_run
+/// use img2pdf::{Img2Pdf, Margins, PageSize};
+///
+/// let image_data = std::fs::read("scan.png").unwrap();
+/// let pdf_data = Img2Pdf::new()
+///     .page_size(PageSize::A4)
+///     .margins(Margins::uniform(36.0))
+///     .title("Scan")
+///     .convert_bytes(&image_data)
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Img2Pdf {
+    options: Options,
+}
+
+impl Img2Pdf {
+    /// Creates a converter with the default options: pages sized to their
+    /// images, no margins, best compression and no metadata.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the size of every page.
+    pub fn page_size(mut self, page_size: PageSize) -> Self {
+        self.options.page_size = page_size;
+        self
+    }
+
+    /// Forces every page to be portrait or landscape, turning the page size
+    /// if needed while keeping the images upright.
+    pub fn orientation(mut self, orientation: Orientation) -> Self {
+        self.options.orientation = orientation;
+        self
+    }
+
+    /// Sets the margins kept free around each image.
+    pub fn margins(mut self, margins: Margins) -> Self {
+        self.options.margins = margins;
+        self
+    }
+
+    /// Keeps the aspect ratio of images scaled to a fixed page size, leaving
+    /// space on two sides of the image. When disabled, images are stretched
+    /// to fill the space inside the margins exactly. Enabled by default.
+    pub fn preserve_aspect(mut self, preserve_aspect: bool) -> Self {
+        self.options.preserve_aspect = preserve_aspect;
+        self
+    }
+
+    /// Strokes a border around each image where it is placed on the page. No
+    /// border is drawn by default. Borders outside the image need margins to
+    /// show on pages sized to fit.
+    pub fn border(mut self, border: Border) -> Self {
+        self.options.border = Some(border);
+        self
+    }
+
+    /// Fills each page with a solid RGB colour before the image is drawn, so
+    /// margins and the space around fitted images are not left blank. Pages
+    /// have no background by default.
+    pub fn background(mut self, color: [u8; 3]) -> Self {
+        self.options.background = Some(color);
+        self
+    }
+
+    /// Makes each page a transparency group, so that everything but the
+    /// image is transparent when the page is placed over other content, as
+    /// an overlay. Disabled by default, and not available for PDF/A or
+    /// together with a [`background`](Self::background).
+    pub fn transparent_page(mut self, transparent_page: bool) -> Self {
+        self.options.transparent_page = transparent_page;
+        self
+    }
+
+    /// Attaches a thumbnail, a copy of the image at most 106 pixels on a
+    /// side, to each page, so viewers can show their page panels without
+    /// rendering the pages. Transparency is composited onto the background,
+    /// or white. Only pages with a single image get one. Disabled by default.
+    pub fn thumbnails(mut self, thumbnails: bool) -> Self {
+        self.options.thumbnails = thumbnails;
+        self
+    }
+
+    /// Sets the zlib compression level, from 0 (store) to 9 (best, the default).
+    pub fn compression(mut self, level: u32) -> Self {
+        self.options.compression = level;
+        self
+    }
+
+    /// Filters the rows of decoded images with the PNG predictors before
+    /// compressing them, and writes the `/DecodeParms` that undo it. Photos
+    /// and scans usually compress much better this way, at the cost of
+    /// slower encoding. JPEGs embedded as they are do not change. Disabled
+    /// by default, and without effect on uncompressed conversions.
+    pub fn png_predictor(mut self, png_predictor: bool) -> Self {
+        self.options.png_predictor = png_predictor;
+        self
+    }
+
+    /// Drops the metadata of JPEGs embedded as they are, such as EXIF
+    /// (with any GPS position), XMP, comments and embedded thumbnails, so
+    /// that none of it ends up in the PDF. Only the marker segments are
+    /// removed: the compressed data is kept, so the image loses no quality
+    /// and the file only gets smaller. The JFIF header, without its
+    /// thumbnail, and the Adobe segment, which tells readers how to decode
+    /// the colours, stay. Decoded images carry no metadata to strip.
+    /// Disabled by default.
+    pub fn strip_metadata(mut self, strip_metadata: bool) -> Self {
+        self.options.strip_metadata = strip_metadata;
+        self
+    }
+
+    /// Re-encodes images as JPEG at `quality`, from 1 to 100, instead of
+    /// compressing their samples losslessly. Images with transparency must be
+    /// flattened first, since JPEG has no alpha channel. CMYK TIFFs are
+    /// always compressed losslessly, and [`uncompressed`](Self::uncompressed)
+    /// takes precedence.
+    pub fn jpeg_quality(mut self, quality: u8) -> Self {
+        self.options.jpeg_quality = Some(quality);
+        self
+    }
+
+    /// Sets the PDF version, such as `(1, 7)`, written in the header. The
+    /// default is 1.4, or 1.5 with [`xref_stream`](Self::xref_stream) or
+    /// [`object_streams`](Self::object_streams).
+    ///
+    /// Versions that lack a requested feature are rejected: cross-reference
+    /// and object streams need 1.5. When appending to an older PDF, the catalog's
+    /// `/Version` is raised to this version instead, since the header of the
+    /// existing file is kept.
+    pub fn pdf_version(mut self, major: u8, minor: u8) -> Self {
+        self.options.pdf_version = Some((major, minor));
+        self
+    }
+
+    /// Writes every frame of animated GIFs and every page of multi-page TIFFs
+    /// as its own page, in order, instead of only the first. Disabled by
+    /// default.
+    pub fn all_frames(mut self, all_frames: bool) -> Self {
+        self.options.all_frames = all_frames;
+        self
+    }
+
+    /// Encodes and compresses the images of a multi-page conversion on all
+    /// cores, then writes the pages in order. Disabled by default.
+    #[cfg(feature = "rayon")]
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.options.parallel = parallel;
+        self
+    }
+
+    /// Sets whether images carry `/Interpolate true`, asking viewers to smooth
+    /// them when scaling, which suits photographs. Disabled by default.
+    pub fn interpolate(mut self, interpolate: bool) -> Self {
+        self.options.interpolate = interpolate;
+        self
+    }
+
+    /// Sets the name images are given in the resources of their page, for
+    /// tools that merge or rename resources. The images of a page are named
+    /// with it followed by their position on the page, from 0, so with the
+    /// default `Img`, a page with one image draws `/Img0`. Names may hold
+    /// ASCII letters, digits, `_` and `-`, and a leading `/` is ignored.
+    pub fn image_resource_name(mut self, name: &str) -> Self {
+        self.options.image_resource_name = name.strip_prefix('/').unwrap_or(name).to_string();
+        self
+    }
+
+    /// Sets how the colours of images with an alpha channel are stored.
+    /// Premultiplied images embedded as straight alpha get dark edges, so
+    /// their colours are divided by alpha first. The default is
+    /// [`AlphaMode::Straight`].
+    pub fn alpha_mode(mut self, alpha_mode: AlphaMode) -> Self {
+        self.options.alpha_mode = alpha_mode;
+        self
+    }
+
+    /// Sets the `/Decode` array of the images, which gives for each colour
+    /// component the values its lowest and highest samples stand for. A
+    /// single pair applies to every component, so `[1.0, 0.0]` inverts any
+    /// image, such as a white-on-black scan, without editing its pixels.
+    /// Otherwise the array needs a pair per component, as `[1 0 1 0 1 0]`
+    /// for RGB; images with another number of components fail to convert.
+    /// Palette images have their palette mapped instead, and transparency
+    /// is left as it is. None is set by default.
+    pub fn decode(mut self, decode: &[f32]) -> Self {
+        self.options.decode = decode.to_vec();
+        self
+    }
+
+    /// Sets the rendering intent written as each image's `/Intent`. None is
+    /// written by default, leaving the choice to the viewer.
+    pub fn intent(mut self, intent: RenderingIntent) -> Self {
+        self.options.intent = Some(intent);
+        self
+    }
+
+    /// Rejects images with more than `max_pixels` pixels before decoding
+    /// them, so that a small file claiming huge dimensions cannot exhaust
+    /// memory. This protects services converting untrusted uploads. The
+    /// default is 100 megapixels; trusted callers with larger images can
+    /// raise it, up to `u64::MAX` for no limit. JPEGs embedded as they are
+    /// are never decoded, and so not limited, but get no
+    /// [thumbnail](Self::thumbnails) when they are over the limit.
+    pub fn max_pixels(mut self, max_pixels: u64) -> Self {
+        self.options.max_pixels = max_pixels;
+        self
+    }
+
+    /// Downscales images, with a Lanczos filter, so that neither side is longer
+    /// than `max_px` pixels. The page keeps the size the full image would have
+    /// had, so the image is embedded at a lower resolution. Smaller images are
+    /// left untouched.
+    pub fn max_dimension(mut self, max_px: u32) -> Self {
+        self.options.max_dimension = Some(max_px);
+        self
+    }
+
+    /// Downscales images, with a Lanczos filter, to just enough pixels for
+    /// `dpi` dots per inch at the size they are placed at on the page, such
+    /// as 150 for documents to send by email. Unlike
+    /// [`max_dimension`](Self::max_dimension), the limit follows the page
+    /// size and margins. Images with fewer pixels are left untouched.
+    pub fn target_dpi(mut self, dpi: f32) -> Self {
+        self.options.target_dpi = Some(dpi);
+        self
+    }
+
+    /// Makes the pixels of colour `key` transparent with a `/Mask` colour-key
+    /// range, instead of writing the alpha channel as an `/SMask`. Images
+    /// whose samples cannot hold the colour, such as gray images and a
+    /// coloured key, are written opaque.
+    pub fn color_key(mut self, key: [u8; 3]) -> Self {
+        self.options.color_key = Some(key);
+        self
+    }
+
+    /// Writes the soft masks of nearly opaque images at half resolution. A mask
+    /// is halved when the variance of its alpha values, taken from 0 for
+    /// transparent to 1 for opaque, is at most `max_variance`; each of its
+    /// samples is then the average of two by two pixels. Faint shadows and
+    /// antialiased edges survive this well, at a quarter of the mask's size.
+    pub fn downsample_masks(mut self, max_variance: f32) -> Self {
+        self.options.mask_downsampling = Some(max_variance);
+        self
+    }
+
+    /// Writes image streams raw, with no `/Filter`, so they can be read in the
+    /// PDF source. JPEGs are decoded rather than embedded as-is. Meant for
+    /// debugging; the files are much larger.
+    pub fn uncompressed(mut self, uncompressed: bool) -> Self {
+        self.options.uncompressed = uncompressed;
+        self
+    }
+
+    /// Whether the `convert_file*` methods replace an existing output file.
+    /// When `false`, they fail with an [`io::ErrorKind::AlreadyExists`] error
+    /// instead and leave the file untouched. Defaults to `true`.
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.options.overwrite = overwrite;
+        self
+    }
+
+    /// Sets the document title shown in the viewer's document properties.
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.options.info.title = Some(title.into());
+        self
+    }
+
+    /// Sets the `/Producer` of the document, in place of this crate and its
+    /// version.
+    pub fn producer(mut self, producer: impl Into<String>) -> Self {
+        self.options.info.producer = Some(producer.into());
+        self
+    }
+
+    /// Sets the time written as the document's creation and modification
+    /// date. Defaults to the time of the conversion; set it for reproducible output.
+    pub fn timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.options.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Makes identical input produce byte-identical output: unless a
+    /// `timestamp` is set, the dates are fixed to the Unix epoch instead of
+    /// the current time, and both `/ID` entries are derived from the content.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.options.deterministic = deterministic;
+        self
+    }
+
+    /// Sets the document properties written to the `/Info` dictionary.
+    pub fn info(mut self, info: Info) -> Self {
+        self.options.info = info;
+        self
+    }
+
+    /// Sets the resolution, in dots per inch, overriding any stored in the images.
+    pub fn dpi(mut self, dpi: f32) -> Self {
+        self.options.dpi = Some(dpi);
+        self
+    }
+
+    /// Composites transparency onto `background` instead of writing an `/SMask`.
+    pub fn flatten(mut self, background: [u8; 3]) -> Self {
+        self.options.flatten = Some(background);
+        self
+    }
+
+    /// Sets whether images are turned upright according to their EXIF
+    /// orientation tag. Enabled by default.
+    pub fn auto_orient(mut self, auto_orient: bool) -> Self {
+        self.options.auto_orient = auto_orient;
+        self
+    }
+
+    /// Sets the `/UserUnit` of every page, the size of its units in points,
+    /// which needs PDF 1.6. Page coordinates are scaled down to match, so the
+    /// physical size stays the same.
+    ///
+    /// Many viewers cannot handle pages larger than 14400 units, 200 inches,
+    /// so such pages get a user unit that brings them within that limit
+    /// anyway, and the PDF is marked as version 1.6. This is only needed to
+    /// choose a unit of your own.
+    pub fn user_unit(mut self, user_unit: f32) -> Self {
+        self.options.user_unit = Some(user_unit);
+        self
+    }
+
+    /// Shows every page turned clockwise by `degrees`, a multiple of 90, with
+    /// the page's `/Rotate` entry. The image streams are left as they are, so
+    /// JPEGs are still embedded without decoding them.
+    ///
+    /// This replaces the EXIF orientation of the images rather than adding to
+    /// it: with a page rotation set, pixels are never turned upright, as if
+    /// [`auto_orient`](Self::auto_orient) were off.
+    pub fn page_rotation(mut self, degrees: i32) -> Self {
+        self.options.page_rotation = Some(degrees);
+        self
+    }
+
+    /// Writes a `/CropBox` inset from each edge of the page by `insets`, in
+    /// points, so that viewers and printers show only that part of the page,
+    /// while the `/MediaBox` keeps the whole image and its bleed. Without
+    /// it, the crop box is the whole page. Pages the crop box does not fit
+    /// are an error.
+    pub fn crop_box(mut self, insets: Margins) -> Self {
+        self.options.crop_box = Some(insets);
+        self
+    }
+
+    /// Encrypts the PDF so it can only be opened with a password. Documents
+    /// are not encrypted by default, and incremental updates cannot be.
+    pub fn encrypt(mut self, encryption: Encryption) -> Self {
+        self.options.encryption = Some(encryption);
+        self
+    }
+
+    /// Adds an XMP metadata stream to the catalog, with the same title,
+    /// author, subject, keywords, creator and dates as the `/Info`
+    /// dictionary, for tools that only read XMP. Disabled by default, and
+    /// always on for PDF/A.
+    pub fn xmp_metadata(mut self, xmp_metadata: bool) -> Self {
+        self.options.xmp_metadata = xmp_metadata;
+        self
+    }
+
+    /// Sets the natural language of the document, such as `en-US`, for
+    /// screen readers and text-to-speech.
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.options.language = Some(language.into());
+        self
+    }
+
+    /// Tags the document for accessibility, giving the images their alternate
+    /// texts in order: the first text belongs to the first image converted,
+    /// and so on. Each image with a text becomes a `Figure` of the structure
+    /// tree that screen readers announce. Images without one, or with an
+    /// empty one, are marked as decoration.
+    pub fn alt_texts<S: Into<String>>(mut self, alt_texts: impl IntoIterator<Item = S>) -> Self {
+        self.options.alt_texts = alt_texts.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the page labels viewers show instead of the page numbers, as
+    /// ranges in order of their first page. The first range must start at
+    /// page 0, and runs until the next. For example, roman numerals for the
+    /// front matter of a book and decimals from its first chapter:
+    ///
+    /// ```
+    /// use img2pdf::{Img2Pdf, NumberingStyle, PageLabel};
+    ///
+    /// let converter = Img2Pdf::new().page_labels([
+    ///     PageLabel::numbered(0, NumberingStyle::LowerRoman),
+    ///     PageLabel::numbered(4, NumberingStyle::Decimal),
+    /// ]);
+    /// ```
+    pub fn page_labels(mut self, labels: impl IntoIterator<Item = PageLabel>) -> Self {
+        self.options.page_labels = labels.into_iter().collect();
+        self
+    }
+
+    /// Adds a document outline to the PDFs of several image files, with a
+    /// bookmark per file titled with its name and pointing at its page, or
+    /// at its first page with [`all_frames`](Self::all_frames). This applies
+    /// to [`convert_files`](Self::convert_files) and the file sources of
+    /// [`convert_pages`](Self::convert_pages); images given as bytes are
+    /// named with [`convert_named`](Self::convert_named). Disabled by default.
+    pub fn outline(mut self, outline: bool) -> Self {
+        self.options.outline = outline;
+        self
+    }
+
+    /// Writes a PDF/A-1b file (ISO 19005-1, level B) for long-term archiving:
+    /// the document carries XMP metadata with the PDF/A identification and an
+    /// sRGB output intent, transparent images are flattened onto white unless
+    /// [`flatten`](Self::flatten) picks another background, and 16-bit
+    /// images are reduced to 8 bits. Conversions that PDF/A-1 cannot
+    /// represent fail: encryption, interpolation, transparent pages, PDF
+    /// versions above 1.4 and CMYK images without an ICC profile. Disabled by
+    /// default.
+    pub fn pdfa(mut self, pdfa: bool) -> Self {
+        self.options.pdfa = pdfa;
+        self
+    }
+
+    /// Linearizes the PDF for fast web view: viewers can display the first
+    /// page before the rest of the file has downloaded. The whole document is
+    /// held in memory until it is written. Disabled by default, and not
+    /// available together with encryption, cross-reference streams or
+    /// appending.
+    pub fn linearize(mut self, linearize: bool) -> Self {
+        self.options.linearize = linearize;
+        self
+    }
+
+    /// Writes a PDF 1.5 cross-reference stream instead of the classic `xref`
+    /// table and trailer. The stream is compressed and has no limit on the
+    /// size of offsets. Disabled by default.
+    pub fn xref_stream(mut self, xref_stream: bool) -> Self {
+        self.options.xref_stream = xref_stream;
+        self
+    }
+
+    /// Packs the objects that have no stream, such as the pages, the catalog
+    /// and the document information, into compressed PDF 1.5 object streams,
+    /// found through a cross-reference stream. This saves most of the
+    /// overhead of each page in documents with many of them. Image and
+    /// content streams are written as they are. Disabled by default, and not
+    /// available together with encryption, linearization or appending.
+    pub fn object_streams(mut self, object_streams: bool) -> Self {
+        self.options.object_streams = object_streams;
+        self
+    }
+
+    /// Converts an image from binary data to a PDF as binary data.
+    pub fn convert_bytes(&self, img_data: &[u8]) -> Result<Vec<u8>> {
+        self.convert_multiple_bytes(&[img_data])
+    }
+
+    /// Converts several images from binary data to a single multi-page PDF.
+    pub fn convert_multiple_bytes(&self, images: &[&[u8]]) -> Result<Vec<u8>> {
+        self.convert_multiple_bytes_with_progress(images, |_, _| {})
+    }
+
+    /// Like [`convert_multiple_bytes`](Self::convert_multiple_bytes), with a
+    /// document outline that has a bookmark per image, titled with the name
+    /// given with it. Images with an empty name get no bookmark.
+    pub fn convert_named(&self, images: &[(&str, &[u8])]) -> Result<Vec<u8>> {
+        let options = self
+            .options
+            .with_bookmarks(images.iter().map(|(name, _)| name.to_string()).collect());
+        let images: Vec<&[u8]> = images.iter().map(|&(_, img_data)| img_data).collect();
+        let out = Vec::with_capacity(expected_capacity(&images));
+        let (pdf_data, _) = convert(&images, &options, out, &mut |_, _| {})?;
+        debug_assert_eq!(validate_pdf(&pdf_data), Ok(()));
+        Ok(pdf_data)
+    }
+
+    /// Like [`convert_multiple_bytes`](Self::convert_multiple_bytes), but calls
+    /// `on_progress(pages_done, total)` after each page is written.
+    pub fn convert_multiple_bytes_with_progress<F: FnMut(usize, usize)>(
+        &self,
+        images: &[&[u8]],
+        mut on_progress: F,
+    ) -> Result<Vec<u8>> {
+        let out = Vec::with_capacity(expected_capacity(images));
+        let (pdf_data, _) = convert(images, &self.options, out, &mut on_progress)?;
+        debug_assert_eq!(validate_pdf(&pdf_data), Ok(()));
+        Ok(pdf_data)
+    }
+
+    /// Like [`convert_multiple_bytes`](Self::convert_multiple_bytes), but
+    /// writes the PDF into `out`, replacing its contents. Reusing one buffer
+    /// for many conversions saves growing a new one each time. On error,
+    /// `out` holds whatever was written before it.
+    pub fn convert_into(&self, images: &[&[u8]], out: &mut Vec<u8>) -> Result<()> {
+        out.clear();
+        out.reserve(expected_capacity(images));
+        convert(images, &self.options, &mut *out, &mut |_, _| {})?;
+        debug_assert_eq!(validate_pdf(out), Ok(()));
+        Ok(())
+    }
+
+    /// Like [`convert_multiple_bytes`](Self::convert_multiple_bytes), but also
+    /// returns a [`ConversionReport`] describing the PDF.
+    pub fn convert_multiple_bytes_with_report(
+        &self,
+        images: &[&[u8]],
+    ) -> Result<(Vec<u8>, ConversionReport)> {
+        let out = Vec::with_capacity(expected_capacity(images));
+        let (pdf_data, mut report) = convert(images, &self.options, out, &mut |_, _| {})?;
+        debug_assert_eq!(validate_pdf(&pdf_data), Ok(()));
+        report.byte_size = pdf_data.len();
+        Ok((pdf_data, report))
+    }
+
+    /// Converts an image read from `reader` to a PDF as binary data.
+    pub fn convert_reader<R: Read>(&self, reader: &mut R) -> Result<Vec<u8>> {
+        let mut img_data = Vec::new();
+        reader.read_to_end(&mut img_data)?;
+        self.convert_bytes(&img_data)
+    }
+
+    /// Converts a large image from binary data to a PDF with one page per
+    /// `tile_width` × `tile_height` pixel tile, in row-major order. Adjacent
+    /// tiles share `overlap` pixels, which helps when assembling a printed
+    /// poster. Edge tiles are smaller when the image does not divide evenly.
+    pub fn convert_tiled(
+        &self,
+        img_data: &[u8],
+        tile_width: u32,
+        tile_height: u32,
+        overlap: u32,
+    ) -> Result<Vec<u8>> {
+        if overlap >= tile_width || overlap >= tile_height {
+            return Err(Img2PdfError::Unsupported(format!(
+                "tiles of {}x{} pixels cannot overlap by {}",
+                tile_width, tile_height, overlap
+            )));
+        }
+
+        check_options(&self.options)?;
+        check_decoder_enabled(img_data)?;
+        check_pixel_count(img_data, &self.options)?;
+        let orientation = if self.options.orients_pixels() {
+            read_orientation(img_data).unwrap_or(1)
+        } else {
+            1
+        };
+        let img = apply_orientation(decode_image(img_data, &self.options)?, orientation);
+        // Each tile keeps the resolution and colour profile of the image, so
+        // the tiles together are the size the image would be.
+        let (dpi, pixel_aspect) = oriented_resolution(img_data, orientation);
+        let profile = read_icc_profile(img_data);
+        let detect_gray = detects_gray(profile.as_deref());
+
+        let (pdf_data, _) = write_document(
+            &[img_data],
+            &self.options,
+            Vec::new(),
+            |pdf, pages_object_id| {
+                let mut page_object_ids = Vec::new();
+                for y in tile_starts(img.height(), tile_height, overlap) {
+                    for x in tile_starts(img.width(), tile_width, overlap) {
+                        let tile = img.crop_imm(x, y, tile_width, tile_height);
+                        let mut image =
+                            encode_decoded(tile, dpi, pixel_aspect, &self.options, detect_gray)?;
+                        if let Some(profile) = &profile {
+                            attach_icc_profile(&mut image, profile.clone(), &self.options)?;
+                        }
+                        check_decode(&image, &self.options)?;
+                        page_object_ids.push(write_image_page(
+                            pdf,
+                            pages_object_id,
+                            &image,
+                            &self.options,
+                        )?);
+                    }
+                }
+                Ok(page_object_ids)
+            },
+        )?;
+        debug_assert_eq!(validate_pdf(&pdf_data), Ok(()));
+        Ok(pdf_data)
+    }
+
+    /// Lays images out as a contact sheet, in a grid of `cols` columns of
+    /// square cells of `cell_size_pt` points. Each image is scaled to fit its
+    /// cell and centered in it, in row-major order.
+    ///
+    /// With [`PageSize::Fit`], one page holds the whole grid and the margins.
+    /// Fixed page sizes hold as many rows as fit inside the margins, and the
+    /// sheet continues on further pages.
+    pub fn convert_contact_sheet(
+        &self,
+        images: &[&[u8]],
+        cols: usize,
+        cell_size_pt: f32,
+    ) -> Result<Vec<u8>> {
+        if images.is_empty() {
+            return Err(Img2PdfError::Unsupported(
+                "no images to convert".to_string(),
+            ));
+        }
+        if cols == 0 || !cell_size_pt.is_finite() || cell_size_pt <= 0.0 {
+            return Err(Img2PdfError::Unsupported(format!(
+                "a contact sheet needs at least one column and a positive cell size, got {} columns of {} pt",
+                cols, cell_size_pt
+            )));
+        }
+        check_options(&self.options)?;
+
+        let (pdf_data, _) =
+            write_document(images, &self.options, Vec::new(), |pdf, pages_object_id| {
+                write_contact_sheet(
+                    pdf,
+                    pages_object_id,
+                    images,
+                    cols,
+                    cell_size_pt,
+                    &self.options,
+                )
+            })?;
+        debug_assert_eq!(validate_pdf(&pdf_data), Ok(()));
+        Ok(pdf_data)
+    }
+
+    /// Packs images onto as few pages as they fit on, each at its natural
+    /// size, with `spacing_pt` points between neighbours.
+    ///
+    /// Images are placed in the order given, left to right in rows from the
+    /// top of the page, and a row that is full starts the next; a page that
+    /// is full starts the next page. Images larger than the space inside the
+    /// margins are scaled down to fit it. With [`PageSize::Fit`], the pages
+    /// are A4.
+    pub fn convert_packed(&self, images: &[&[u8]], spacing_pt: f32) -> Result<Vec<u8>> {
+        if images.is_empty() {
+            return Err(Img2PdfError::Unsupported(
+                "no images to convert".to_string(),
+            ));
+        }
+        if !spacing_pt.is_finite() || spacing_pt < 0.0 {
+            return Err(Img2PdfError::Unsupported(format!(
+                "spacing between packed images must not be negative, got {}",
+                spacing_pt
+            )));
+        }
+        check_options(&self.options)?;
+
+        let (pdf_data, _) =
+            write_document(images, &self.options, Vec::new(), |pdf, pages_object_id| {
+                write_packed_pages(pdf, pages_object_id, images, spacing_pt, &self.options)
+            })?;
+        debug_assert_eq!(validate_pdf(&pdf_data), Ok(()));
+        Ok(pdf_data)
+    }
+
+    /// Converts samples in a Lab or DeviceN colour space to a single-page
+    /// PDF. `samples` hold `width` × `height` pixels, row by row, with one
+    /// byte per component, and are embedded as they are: JPEG re-encoding,
+    /// resizing and the other options that change pixels do not apply.
+    pub fn convert_samples(
+        &self,
+        samples: &[u8],
+        width: u32,
+        height: u32,
+        color_space: &PassthroughColorSpace,
+    ) -> Result<Vec<u8>> {
+        let components = color_space.components();
+        match color_space {
+            PassthroughColorSpace::Lab { white_point, range } => {
+                let positive = |v: f32| v.is_finite() && v > 0.0;
+                if white_point[1] != 1.0 || !positive(white_point[0]) || !positive(white_point[2]) {
+                    return Err(Img2PdfError::Unsupported(format!(
+                        "a Lab white point needs positive X and Z and a Y of 1, got {:?}",
+                        white_point
+                    )));
+                }
+                if !range.iter().all(|bound| bound.is_finite())
+                    || range[0] > range[1]
+                    || range[2] > range[3]
+                {
+                    return Err(Img2PdfError::Unsupported(format!(
+                        "invalid Lab range {:?}",
+                        range
+                    )));
+                }
+            }
+            // PDF readers support up to 32 colorants.
+            PassthroughColorSpace::DeviceN { colorants } => {
+                if colorants.is_empty() || colorants.len() > 32 {
+                    return Err(Img2PdfError::Unsupported(format!(
+                        "DeviceN needs 1 to 32 colorants, got {}",
+                        colorants.len()
+                    )));
+                }
+                if colorants.iter().any(|(name, _)| name.is_empty()) {
+                    return Err(Img2PdfError::Unsupported(
+                        "DeviceN colorants need names".to_string(),
+                    ));
+                }
+                if let Some((name, cmyk)) = colorants.iter().find(|(_, cmyk)| {
+                    !cmyk
+                        .iter()
+                        .all(|v| v.is_finite() && (0.0..=1.0).contains(v))
+                }) {
+                    return Err(Img2PdfError::Unsupported(format!(
+                        "the CMYK equivalent of colorant {} needs values from 0 to 1, got {:?}",
+                        name, cmyk
+                    )));
+                }
+                // The CMYK alternate does not match the sRGB output intent.
+                if self.options.pdfa {
+                    return Err(Img2PdfError::Unsupported(
+                        "PDF/A-1b cannot use DeviceN colour spaces".to_string(),
+                    ));
+                }
+            }
+        }
+        if width == 0 || height == 0 {
+            return Err(Img2PdfError::Unsupported(format!(
+                "image has no area ({}x{})",
+                width, height
+            )));
+        }
+        let expected = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|area| area.checked_mul(components));
+        if expected != Some(samples.len()) {
+            return Err(Img2PdfError::Unsupported(format!(
+                "{}x{} pixels of {} components need {} bytes of samples, got {}",
+                width,
+                height,
+                components,
+                expected.map_or("more".to_string(), |expected| expected.to_string()),
+                samples.len()
+            )));
+        }
+        check_options(&self.options)?;
+
+        let (filter, data) = compress_samples(samples.to_vec(), &self.options)?;
+        let image = EncodedImage {
+            width,
+            height,
+            color_space: ColorSpace::Passthrough(color_space.clone()),
+            bits_per_component: 8,
+            filter,
+            data,
+            mask: None,
+            dpi: None,
+            pixel_aspect: 1.0,
+            resampled_from: None,
+            icc_profile: None,
+            decode: None,
+            predicted: false,
+            thumbnail: None,
+        };
+        check_decode(&image, &self.options)?;
+        self.write_single_page(samples, &image)
+    }
+
+    /// Converts pixels already decoded in memory, `width` × `height` of them
+    /// row by row in `format`, to a single-page PDF, as if they had been
+    /// decoded from an image file without a resolution.
+    pub fn convert_raw(
+        &self,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        format: PixelFormat,
+    ) -> Result<Vec<u8>> {
+        check_options(&self.options)?;
+        let image = encode_raw(width, height, pixels, format, &self.options)?;
+        self.write_single_page(pixels, &image)
+    }
+
+    /// Converts an image the caller has already decoded, and perhaps resized
+    /// or composited, to a single-page PDF, without encoding it as an image
+    /// file first. It is placed as if it had been decoded from a file without
+    /// a resolution.
+    pub fn convert_image(&self, img: DynamicImage) -> Result<Vec<u8>> {
+        check_options(&self.options)?;
+        let image = encode_decoded(img, None, 1.0, &self.options, true)?;
+        check_decode(&image, &self.options)?;
+        // The encoded pixels stand in for the file the identifier is derived
+        // from.
+        self.write_single_page(&image.data, &image)
+    }
+
+    /// Converts a page for each source to a single PDF, in the order given.
+    /// Files are all read before the PDF is written, and if one cannot be
+    /// read, the error message names its path. Each source is one page, even
+    /// with [`all_frames`](Self::all_frames).
+    pub fn convert_pages(&self, pages: &[PageSource]) -> Result<Vec<u8>> {
+        if pages.is_empty() {
+            return Err(Img2PdfError::Unsupported(
+                "no images to convert".to_string(),
+            ));
+        }
+        check_options(&self.options)?;
+
+        let mut sources: Vec<Cow<[u8]>> = Vec::with_capacity(pages.len());
+        for page in pages {
+            sources.push(match page {
+                PageSource::File(path) => {
+                    Cow::Owned(std::fs::read(path).map_err(|e| {
+                        io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+                    })?)
+                }
+                PageSource::Bytes(img_data) => Cow::Borrowed(img_data),
+                PageSource::Raw { pixels, .. } => Cow::Borrowed(pixels),
+            });
+        }
+
+        let images: Vec<&[u8]> = sources.iter().map(|source| source.as_ref()).collect();
+        // Sources other than files have no name, and so no bookmark.
+        let paths: Vec<&Path> = pages
+            .iter()
+            .map(|page| match page {
+                PageSource::File(path) => path.as_path(),
+                _ => Path::new(""),
+            })
+            .collect();
+        let options = self.options.with_file_bookmarks(&paths);
+        let (pdf_data, _) =
+            write_document(&images, &options, Vec::new(), |pdf, pages_object_id| {
+                let mut page_object_ids = Vec::with_capacity(pages.len());
+                for (page, img_data) in pages.iter().zip(&images) {
+                    let image = match page {
+                        PageSource::Raw {
+                            width,
+                            height,
+                            pixels,
+                            format,
+                        } => encode_raw(*width, *height, pixels, *format, &self.options)?,
+                        _ => encode_page(img_data, &self.options)?,
+                    };
+                    page_object_ids.push(write_image_page(
+                        pdf,
+                        pages_object_id,
+                        &image,
+                        &self.options,
+                    )?);
+                }
+                Ok(page_object_ids)
+            })?;
+        debug_assert_eq!(validate_pdf(&pdf_data), Ok(()));
+        Ok(pdf_data)
+    }
+
+    /// Writes a PDF with a page for an encoded image, whose file identifier
+    /// is derived from `source`.
+    fn write_single_page(&self, source: &[u8], image: &EncodedImage) -> Result<Vec<u8>> {
+        let (pdf_data, _) = write_document(
+            &[source],
+            &self.options,
+            Vec::new(),
+            |pdf, pages_object_id| {
+                Ok(vec![write_image_page(
+                    pdf,
+                    pages_object_id,
+                    image,
+                    &self.options,
+                )?])
+            },
+        )?;
+        debug_assert_eq!(validate_pdf(&pdf_data), Ok(()));
+        Ok(pdf_data)
+    }
+
+    /// Adds an image as a new last page of an existing PDF, returning the
+    /// updated PDF.
+    ///
+    /// The page is appended as an incremental update, so `existing_pdf` is
+    /// kept byte for byte at the start of the result. This supports the PDFs
+    /// this crate writes, and files laid out the same way.
+    pub fn append_bytes(&self, existing_pdf: &[u8], img_data: &[u8]) -> Result<Vec<u8>> {
+        append_page(existing_pdf, img_data, &self.options)
+    }
+
+    /// Downloads an image from `url` and converts it to a PDF as binary data.
+    ///
+    /// The format is detected from the image data, like for any other input,
+    /// so neither the URL's extension nor the `Content-Type` matters.
+    #[cfg(feature = "url")]
+    pub fn convert_url(&self, url: &str) -> Result<Vec<u8>> {
+        let fetch_error = |e: &dyn fmt::Display| io::Error::other(format!("{}: {}", url, e));
+        let response = ureq::get(url).call().map_err(|e| fetch_error(&e))?;
+        let mut img_data = Vec::new();
+        response
+            .into_reader()
+            .read_to_end(&mut img_data)
+            .map_err(|e| fetch_error(&e))?;
+        self.convert_bytes(&img_data)
+    }
+
+    /// Converts an image from binary data to a PDF, streaming the PDF to `out`
+    /// instead of buffering it in memory.
+    pub fn convert_to_writer<W: Write>(&self, img_data: &[u8], out: &mut W) -> Result<()> {
+        self.convert_multiple_to_writer(&[img_data], out, |_, _| {})
+    }
+
+    /// Converts images from binary data to a single multi-page PDF, in the
+    /// order given, streaming the PDF to `out` and calling
+    /// `on_progress(pages_done, total)` after each page is written.
+    pub fn convert_multiple_to_writer<W: Write, F: FnMut(usize, usize)>(
+        &self,
+        images: &[&[u8]],
+        out: &mut W,
+        mut on_progress: F,
+    ) -> Result<()> {
+        convert(images, &self.options, out, &mut on_progress)?;
+        Ok(())
+    }
+
+    /// Computes the size in bytes of the PDF [`convert_bytes`](Self::convert_bytes)
+    /// would return for `img_data`, counting the bytes as they are written
+    /// instead of keeping them. Useful for comparing settings on large
+    /// batches before writing anything.
+    pub fn estimate_size(&self, img_data: &[u8]) -> Result<usize> {
+        let (counter, _) = convert(&[img_data], &self.options, ByteCounter(0), &mut |_, _| {})?;
+        Ok(counter.0)
+    }
+
+    /// Converts an image file to a PDF file.
+    pub fn convert_file<P: AsRef<Path>, Q: AsRef<Path>>(&self, input: P, output: Q) -> Result<()> {
+        self.convert_files(&[input], output)
+    }
+
+    /// Converts several image files to a single multi-page PDF file, in the
+    /// order given. If an input cannot be read, the error message names the
+    /// offending path.
+    pub fn convert_files<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        inputs: &[P],
+        output: Q,
+    ) -> Result<()> {
+        self.convert_files_with_progress(inputs, output, |_, _| {})
+    }
+
+    /// Like [`convert_files`](Self::convert_files), but calls
+    /// `on_progress(pages_done, total)` after each page is written.
+    pub fn convert_files_with_progress<P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(usize, usize)>(
+        &self,
+        inputs: &[P],
+        output: Q,
+        mut on_progress: F,
+    ) -> Result<()> {
+        let mut images = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            let input = input.as_ref();
+            let img_data = std::fs::read(input)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", input.display(), e)))?;
+            images.push(img_data);
+        }
+
+        let images: Vec<&[u8]> = images.iter().map(Vec::as_slice).collect();
+        let paths: Vec<&Path> = inputs.iter().map(AsRef::as_ref).collect();
+        let options = self.options.with_file_bookmarks(&paths);
+        write_atomically(output.as_ref(), self.options.overwrite, |output_file| {
+            let (output_file, _) = convert(&images, &options, output_file, &mut on_progress)?;
+            Ok(output_file)
+        })
+    }
+
+    /// Converts images from binary data to a single multi-page PDF file, in
+    /// the order given, calling `on_progress(pages_done, total)` after each
+    /// page is written. Like the other conversions to files, the PDF is
+    /// written to a temporary file that replaces `output` only once it is
+    /// complete.
+    pub fn convert_multiple_bytes_to_file<Q: AsRef<Path>, F: FnMut(usize, usize)>(
+        &self,
+        images: &[&[u8]],
+        output: Q,
+        mut on_progress: F,
+    ) -> Result<()> {
+        write_atomically(output.as_ref(), self.options.overwrite, |output_file| {
+            let (output_file, _) = convert(images, &self.options, output_file, &mut on_progress)?;
+            Ok(output_file)
+        })
+    }
+
+    /// Converts each image in `dir` to its own PDF in `out_dir`, named after
+    /// the image with a `.pdf` extension. With `recursive`, subdirectories
+    /// are searched too, and each PDF goes to the same relative subdirectory
+    /// of `out_dir`, which is created as needed.
+    ///
+    /// Files are recognised as images by their content. Other files are
+    /// skipped and listed in the returned report, but an image that fails to
+    /// convert stops the conversion with its error. Two images that would be
+    /// written to the same PDF, such as `photo.jpg` and `photo.png`, are an
+    /// error before anything is written. Symbolic links to directories are
+    /// not followed.
+    pub fn convert_directory<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        dir: P,
+        out_dir: Q,
+        recursive: bool,
+    ) -> Result<DirectoryReport> {
+        let (dir, out_dir) = (dir.as_ref(), out_dir.as_ref());
+        // Listing everything first keeps PDFs written into `dir` from being
+        // picked up as inputs.
+        let mut files = Vec::new();
+        list_files(dir, recursive, &mut files)?;
+
+        let mut report = DirectoryReport::default();
+        let mut outputs: BTreeMap<PathBuf, &Path> = BTreeMap::new();
+        for input in &files {
+            let with_path =
+                |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", input.display(), e));
+            // The start of the file is enough to tell its format.
+            let mut header = Vec::with_capacity(FORMAT_HEADER_LEN);
+            File::open(input)
+                .and_then(|file| file.take(FORMAT_HEADER_LEN as u64).read_to_end(&mut header))
+                .map_err(with_path)?;
+            if image::guess_format(&header).is_err() {
+                report.skipped.push(input.clone());
+                continue;
+            }
+
+            let relative = input.strip_prefix(dir).unwrap_or(input);
+            let output = out_dir.join(relative).with_extension("pdf");
+            if let Some(other) = outputs.insert(output.clone(), input) {
+                return Err(Img2PdfError::Unsupported(format!(
+                    "{} and {} would both be written to {}",
+                    other.display(),
+                    input.display(),
+                    output.display()
+                )));
+            }
+            report.converted.push((input.clone(), output));
+        }
+
+        for (input, output) in &report.converted {
+            let with_path =
+                |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", input.display(), e));
+            let img_data = std::fs::read(input).map_err(with_path)?;
+            if let Some(parent) = output.parent() {
+                std::fs::create_dir_all(parent).map_err(with_path)?;
+            }
+            write_atomically(output, self.options.overwrite, |output_file| {
+                let (output_file, _) =
+                    convert(&[&img_data], &self.options, output_file, &mut |_, _| {})?;
+                Ok(output_file)
+            })?;
+        }
+        Ok(report)
+    }
+}
+
+/// What [`Img2Pdf::convert_directory`] did with the files it found, each
+/// list in the order the files were visited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirectoryReport {
+    /// Each image converted, with the PDF written for it.
+    pub converted: Vec<(PathBuf, PathBuf)>,
+    /// The files skipped because they are not images.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// The number of bytes read from the start of a file to guess its format.
+const FORMAT_HEADER_LEN: usize = 64;
+
+/// Appends the files in `dir` to `files`, sorted by name, and with
+/// `recursive` those in its subdirectories after the files of each
+/// directory. Symbolic links to files are listed, but those to directories
+/// are not followed, so that a link loop cannot recurse forever.
+fn list_files(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let with_path = |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", dir.display(), e));
+    let mut entries = std::fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| {
+                    let entry = entry?;
+                    Ok((entry.path(), entry.file_type()?))
+                })
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .map_err(with_path)?;
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut subdirs = Vec::new();
+    for (entry, file_type) in entries {
+        if file_type.is_dir() {
+            subdirs.push(entry);
+        } else if file_type.is_file() || (file_type.is_symlink() && entry.is_file()) {
+            files.push(entry);
+        }
+    }
+    if recursive {
+        for subdir in subdirs {
+            list_files(&subdir, recursive, files)?;
+        }
+    }
+    Ok(())
+}
+
+/// The settings shared by every page of a conversion.
+#[derive(Debug, Clone)]
+struct Options {
+    page_size: PageSize,
+    orientation: Orientation,
+    margins: Margins,
+    /// Scales images uniformly to fit the page, rather than stretching them.
+    preserve_aspect: bool,
+    border: Option<Border>,
+    /// The PDF version, when set explicitly.
+    pdf_version: Option<(u8, u8)>,
+    /// Fills the page with this colour behind the image.
+    background: Option<[u8; 3]>,
+    /// Writes every page as a transparency group, without a background.
+    transparent_page: bool,
+    /// Attaches a downscaled copy of the image to each page as its `/Thumb`.
+    thumbnails: bool,
+    /// Overrides the resolution read from the image.
+    dpi: Option<f32>,
+    /// Composites transparency onto this background instead of writing an `/SMask`.
+    flatten: Option<[u8; 3]>,
+    /// Applies the EXIF orientation of JPEG and TIFF inputs.
+    auto_orient: bool,
+    /// The `/Rotate` of every page, in degrees.
+    page_rotation: Option<i32>,
+    /// The `/UserUnit` of every page, instead of one only for pages too
+    /// large without.
+    user_unit: Option<f32>,
+    /// The insets of the `/CropBox` from the edges of the page.
+    crop_box: Option<Margins>,
+    /// The zlib compression level, 0 to 9.
+    compression: u32,
+    /// Filters sample rows with the PNG predictors before compression.
+    png_predictor: bool,
+    /// Writes image samples raw, without any `/Filter`.
+    uncompressed: bool,
+    /// Re-encodes images as JPEG at this quality.
+    jpeg_quality: Option<u8>,
+    /// Drops the metadata segments of JPEGs embedded as they are.
+    strip_metadata: bool,
+    /// Writes every frame of animated GIFs and multi-page TIFFs as a page.
+    all_frames: bool,
+    /// Encodes pages concurrently.
+    #[cfg(feature = "rayon")]
+    parallel: bool,
+    /// Asks viewers to smooth images when scaling them.
+    interpolate: bool,
+    /// The resource names of the images on a page, followed by their index.
+    image_resource_name: String,
+    intent: Option<RenderingIntent>,
+    /// The `/Decode` array of the images, or empty for the default.
+    decode: Vec<f32>,
+    alpha_mode: AlphaMode,
+    /// Rejects images with more pixels than this before decoding them.
+    max_pixels: u64,
+    /// Downscales images with a side longer than this many pixels.
+    max_dimension: Option<u32>,
+    /// Downscales images to this resolution at their size on the page.
+    target_dpi: Option<f32>,
+    /// Halves the resolution of soft masks whose alpha variance is at most this.
+    mask_downsampling: Option<f32>,
+    /// The colour made transparent by a `/Mask`, which replaces the `/SMask`.
+    color_key: Option<[u8; 3]>,
+    info: Info,
+    /// Writes a cross-reference stream instead of the classic table.
+    xref_stream: bool,
+    /// Packs objects without a stream into object streams.
+    object_streams: bool,
+    encryption: Option<Encryption>,
+    linearize: bool,
+    xmp_metadata: bool,
+    language: Option<String>,
+    /// The alternate texts of the images, which make the document tagged.
+    alt_texts: Vec<String>,
+    /// Bookmarks the pages of image files with the file names.
+    outline: bool,
+    /// The page label ranges, making the `/PageLabels` number tree.
+    page_labels: Vec<PageLabel>,
+    /// The bookmark titles of the images, in order, which make the document
+    /// outline. Images with an empty title get no bookmark.
+    bookmarks: Vec<String>,
+    /// Writes PDF/A-1b.
+    pdfa: bool,
+    /// Replaces existing output files.
+    overwrite: bool,
+    /// The creation and modification date; the current time if `None`.
+    timestamp: Option<SystemTime>,
+    deterministic: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            page_size: PageSize::Fit,
+            orientation: Orientation::Auto,
+            margins: Margins::default(),
+            preserve_aspect: true,
+            border: None,
+            pdf_version: None,
+            background: None,
+            transparent_page: false,
+            thumbnails: false,
+            dpi: None,
+            flatten: None,
+            auto_orient: true,
+            page_rotation: None,
+            user_unit: None,
+            crop_box: None,
+            compression: 9,
+            png_predictor: false,
+            uncompressed: false,
+            jpeg_quality: None,
+            strip_metadata: false,
+            all_frames: false,
+            #[cfg(feature = "rayon")]
+            parallel: false,
+            interpolate: false,
+            image_resource_name: "Img".to_string(),
+            intent: None,
+            decode: Vec::new(),
+            alpha_mode: AlphaMode::Straight,
+            max_pixels: DEFAULT_MAX_PIXELS,
+            max_dimension: None,
+            target_dpi: None,
+            mask_downsampling: None,
+            color_key: None,
+            info: Info::default(),
+            xref_stream: false,
+            object_streams: false,
+            encryption: None,
+            linearize: false,
+            xmp_metadata: false,
+            language: None,
+            alt_texts: Vec::new(),
+            outline: false,
+            page_labels: Vec::new(),
+            bookmarks: Vec::new(),
+            pdfa: false,
+            overwrite: true,
+            timestamp: None,
+            deterministic: false,
+        }
+    }
+}
+
+/// Converts the images to a PDF with one page per image, written to `out`.
+fn convert<W: Write>(
+    images: &[&[u8]],
+    options: &Options,
+    out: W,
+    on_progress: &mut dyn FnMut(usize, usize),
+) -> Result<(W, ConversionReport)> {
+    let (frames, first_frames) = if options.all_frames {
+        split_frames(images, options)?
+    } else {
+        (Vec::new(), Vec::new())
+    };
+    let frames: Vec<&[u8]> = frames.iter().map(|frame| frame.as_ref()).collect();
+    let images = if options.all_frames { &frames } else { images };
+    // Bookmarks belong to the first page of their image.
+    let frame_options;
+    let options = if options.all_frames && !options.bookmarks.is_empty() {
+        let mut bookmarks = vec![String::new(); frames.len()];
+        for (&first, title) in first_frames.iter().zip(&options.bookmarks) {
+            bookmarks[first] = title.clone();
+        }
+        frame_options = options.with_bookmarks(bookmarks);
+        &frame_options
+    } else {
+        options
+    };
+    if images.is_empty() {
+        return Err(Img2PdfError::Unsupported(
+            "no images to convert".to_string(),
+        ));
+    }
+    check_options(options)?;
+
+    write_document(images, options, out, |pdf, pages_object_id| {
+        // Pages are encoded a batch at a time, and written in order so object
+        // offsets stay correct. Serial conversions encode one page at a time.
+        #[cfg(feature = "rayon")]
+        let batch_size = if options.parallel {
+            2 * rayon::current_num_threads()
+        } else {
+            1
+        };
+        #[cfg(not(feature = "rayon"))]
+        let batch_size = 1;
+
+        let mut page_object_ids = Vec::with_capacity(images.len());
+        for batch in images.chunks(batch_size) {
+            for image in encode_pages(batch, options) {
+                let page_object_id = write_image_page(pdf, pages_object_id, &image?, options)?;
+                page_object_ids.push(page_object_id);
+                on_progress(page_object_ids.len(), images.len());
+            }
+        }
+        Ok(page_object_ids)
+    })
+}
+
+/// The bytes of objects other than the image streams a page adds, roughly.
+const PAGE_OVERHEAD: usize = 1024;
+
+/// Guesses the size of the PDF of `images`, to allocate its buffer once.
+///
+/// Embedded JPEGs are copied as they are and recompressed samples are
+/// usually close to the size of their input, so the inputs are a fair guess.
+fn expected_capacity(images: &[&[u8]]) -> usize {
+    images
+        .iter()
+        .map(|img_data| img_data.len() + PAGE_OVERHEAD)
+        .sum()
+}
+
+/// Counts the temporary files of this process, to keep their names unique.
+static TEMPORARY_FILES: AtomicU32 = AtomicU32::new(0);
+
+/// Creates the file at `path` with `write`, which is given a temporary file
+/// in the same directory to write to. The temporary file is renamed into
+/// place only once it is complete, so `path` never holds a partial file, and
+/// removed if writing fails.
+///
+/// Unless `overwrite` is set, an existing file at `path` is an
+/// [`io::ErrorKind::AlreadyExists`] error, and is left untouched.
+fn write_atomically(
+    path: &Path,
+    overwrite: bool,
+    write: impl FnOnce(BufWriter<File>) -> Result<BufWriter<File>>,
+) -> Result<()> {
+    let with_path = |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| with_path(io::ErrorKind::InvalidInput.into()))?;
+    let mut temporary_name = OsString::from(".");
+    temporary_name.push(file_name);
+    temporary_name.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temporary_path = path.with_file_name(temporary_name);
+
+    let result = File::create(&temporary_path)
+        .map_err(Img2PdfError::from)
+        .and_then(|file| write(BufWriter::new(file)))
+        .and_then(|file| {
+            let file = file.into_inner().map_err(io::IntoInnerError::into_error)?;
+            file.sync_all()?;
+            if overwrite {
+                std::fs::rename(&temporary_path, path).map_err(with_path)?;
+            } else {
+                publish_new(&temporary_path, path).map_err(with_path)?;
+            }
+            Ok(())
+        });
+    if result.is_err() || !overwrite {
+        let _ = std::fs::remove_file(&temporary_path);
+    }
+    result
+}
+
+/// Puts the complete file at `temporary_path` in place at `path`, failing
+/// if `path` exists.
+///
+/// Unlike a rename, a hard link fails if the file exists. File systems
+/// without hard links, such as FAT and many network shares, get a new file
+/// created exclusively and filled with a copy instead, which is removed
+/// again if copying fails.
+fn publish_new(temporary_path: &Path, path: &Path) -> io::Result<()> {
+    match std::fs::hard_link(temporary_path, path) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+            debug!("copying the output in place, as it cannot be linked: {}", e);
+        }
+        result => return result,
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    let copied = File::open(temporary_path)
+        .and_then(|mut temporary_file| io::copy(&mut temporary_file, &mut file))
+        .and_then(|_| file.sync_all());
+    if copied.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    copied
+}
+
+/// Writes a PDF whose pages are written by `write_pages`, which is given the
+/// writer and the id of the `/Pages` object and returns the ids of the pages
+/// in order. `images` are the inputs, which the file identifier is derived
+/// from.
+///
+/// The report returned with the output leaves the byte size to the caller.
+fn write_document<W: Write>(
+    images: &[&[u8]],
+    options: &Options,
+    out: W,
+    write_pages: impl FnOnce(&mut PdfWriter<W>, u32) -> Result<Vec<u32>>,
+) -> Result<(W, ConversionReport)> {
+    let (major, minor) = options.version();
+    let mut pdf = PdfWriter::new(out, &format!("{}.{}", major, minor))?;
+    if options.linearize {
+        pdf.hold();
+    }
+    if options.object_streams {
+        pdf.pack_objects();
+    }
+
+    let timestamp = options.timestamp.unwrap_or_else(|| {
+        if options.deterministic {
+            UNIX_EPOCH
+        } else {
+            now()
+        }
+    });
+    // Encryption keys are derived from the identifier, so it comes first.
+    let id = file_id(images, options, timestamp);
+    if let Some(encryption) = &options.encryption {
+        pdf.encryptor = Some(encrypt::Encryptor::new(
+            &encryption.user_password,
+            &encryption.owner_password,
+            encryption.method == EncryptionMethod::Aes128,
+            &id,
+        ));
+    }
+
+    let pages_object_id = pdf.reserve_object_id();
+    let page_object_ids = write_pages(&mut pdf, pages_object_id)?;
+
+    let kids = page_object_ids
+        .iter()
+        .map(|id| format!("{} 0 R", id))
+        .collect::<Vec<_>>()
+        .join(" ");
+    pdf.begin_object(pages_object_id)?;
+    writeln!(
+        pdf,
+        "<< /Type /Pages /Kids [ {} ] /Count {} >>",
+        kids,
+        page_object_ids.len()
+    )?;
+    pdf.end_object()?;
+
+    let mut catalog_entries = String::new();
+    if options.xmp_metadata || options.pdfa {
+        let pdfa = options.pdfa.then_some((1, 'B'));
+        let metadata = xmp::packet(&options.info, &xmp_date(timestamp), pdfa);
+        let metadata_object_id = write_metadata(&mut pdf, &metadata)?;
+        catalog_entries.push_str(&format!(" /Metadata {} 0 R", metadata_object_id));
+    }
+    if options.pdfa {
+        let profile_object_id = pdf.reserve_object_id();
+        pdf.begin_object(profile_object_id)?;
+        let (filter, profile) = compress_samples(srgb::srgb_profile(), options)?;
+        pdf.write_stream(&format!("/N 3{}", filter_entry(filter)), &profile)?;
+        pdf.end_object()?;
+        catalog_entries.push_str(&format!(
+            " /OutputIntents [<< /Type /OutputIntent /S /GTS_PDFA1 /OutputConditionIdentifier {0} /Info {0} /DestOutputProfile {1} 0 R >>]",
+            pdf_string(srgb::DESCRIPTION),
+            profile_object_id
+        ));
+    }
+
+    let bookmarks: Vec<(u32, &str)> = page_object_ids
+        .iter()
+        .zip(&options.bookmarks)
+        .filter(|(_, title)| !title.is_empty())
+        .map(|(&page_object_id, title)| (page_object_id, title.as_str()))
+        .collect();
+    if !bookmarks.is_empty() {
+        let outline_object_id = write_outline(&mut pdf, &bookmarks)?;
+        catalog_entries.push_str(&format!(
+            " /Outlines {} 0 R /PageMode /UseOutlines",
+            outline_object_id
+        ));
+    }
+
+    if options.tagged() {
+        let structure_object_id = write_structure_tree(&mut pdf)?;
+        catalog_entries.push_str(&format!(
+            " /MarkInfo << /Marked true >> /StructTreeRoot {} 0 R",
+            structure_object_id
+        ));
+    }
+
+    let catalog_object_id = pdf.reserve_object_id();
+    pdf.begin_object(catalog_object_id)?;
+    if let Some(language) = &options.language {
+        catalog_entries.push_str(&format!(" /Lang {}", pdf.text_string(language)));
+    }
+    // The header was written before pages needed user units.
+    if pdf.user_units && (major, minor) < (1, 6) {
+        catalog_entries.push_str(" /Version /1.6");
+    }
+    if !options.page_labels.is_empty() {
+        let mut nums = Vec::with_capacity(options.page_labels.len());
+        for label in &options.page_labels {
+            let mut entries = String::new();
+            if let Some(style) = label.style {
+                entries.push_str(&format!(" /S {}", style.to_pdf()));
+            }
+            if !label.prefix.is_empty() {
+                entries.push_str(&format!(" /P {}", pdf.text_string(&label.prefix)));
+            }
+            if label.start != 1 {
+                entries.push_str(&format!(" /St {}", label.start));
+            }
+            nums.push(format!("{} <<{} >>", label.first_page, entries));
+        }
+        catalog_entries.push_str(&format!(" /PageLabels << /Nums [{}] >>", nums.join(" ")));
+    }
+    writeln!(
+        pdf,
+        "<< /Type /Catalog /Pages {} 0 R{} >>",
+        pages_object_id, catalog_entries
+    )?;
+    pdf.end_object()?;
+
+    let info_object_id = write_info(&mut pdf, &options.info, timestamp)?;
+
+    // The encryption dictionary itself is not encrypted.
+    let encrypt_object_id = match pdf.encryptor.take() {
+        Some(encryptor) => {
+            let encrypt_object_id = pdf.reserve_object_id();
+            pdf.begin_object(encrypt_object_id)?;
+            writeln!(pdf, "<< {} >>", encryptor.dictionary())?;
+            pdf.end_object()?;
+            Some(encrypt_object_id)
+        }
+        None => None,
+    };
+
+    let trailer = Trailer {
+        root_object_id: catalog_object_id,
+        info_object_id: Some(info_object_id),
+        id: Some(id),
+        encrypt_object_id,
+        prev: None,
+    };
+    let report = std::mem::take(&mut pdf.report);
+    info!(
+        "wrote PDF {}.{} with {} pages",
+        major, minor, report.page_count
+    );
+    // Offsets past ten digits only fit an xref stream, which needs PDF 1.5.
+    let overflow = pdf.table_overflow().is_some() && (major, minor) >= (1, 5);
+    let out = if options.linearize {
+        pdf.finish_linearized(&trailer, &page_object_ids)?
+    } else if options.xref_stream || options.object_streams || overflow {
+        pdf.finish_with_xref_stream(&trailer)?
+    } else {
+        pdf.finish(&trailer)?
+    };
+    Ok((out, report))
+}
+
+impl Options {
+    /// These options with `bookmarks` as the bookmark titles.
+    fn with_bookmarks(&self, bookmarks: Vec<String>) -> Options {
+        Options {
+            bookmarks,
+            ..self.clone()
+        }
+    }
+
+    /// These options with the names of `paths` as the bookmark titles, if
+    /// an outline is asked for.
+    fn with_file_bookmarks<'a>(&'a self, paths: &[&Path]) -> Cow<'a, Options> {
+        if !self.outline {
+            return Cow::Borrowed(self);
+        }
+        Cow::Owned(
+            self.with_bookmarks(
+                paths
+                    .iter()
+                    .map(|path| {
+                        path.file_name()
+                            .map(|name| name.to_string_lossy().into_owned())
+                            .unwrap_or_default()
+                    })
+                    .collect(),
+            ),
+        )
+    }
+
+    /// The resource name of the image at `index` on its page.
+    fn image_name(&self, index: usize) -> String {
+        format!("/{}{}", self.image_resource_name, index)
+    }
+
+    /// Whether the document has a structure tree.
+    fn tagged(&self) -> bool {
+        !self.alt_texts.is_empty()
+    }
+
+    /// The background transparent images are flattened onto, if they are.
+    fn flatten_background(&self) -> Option<[u8; 3]> {
+        // PDF/A-1 has no transparency.
+        self.flatten.or(self.pdfa.then_some([255, 255, 255]))
+    }
+
+    /// Whether pixels are turned upright by their EXIF orientation. A page
+    /// rotation takes the place of auto-orientation, so the two never add up.
+    fn orients_pixels(&self) -> bool {
+        self.auto_orient && self.page_rotation.is_none()
+    }
+
+    /// The PDF version to write.
+    fn version(&self) -> (u8, u8) {
+        self.pdf_version.unwrap_or_else(|| self.minimum_version().0)
+    }
+
+    /// The lowest PDF version with every requested feature, and the feature
+    /// that needs it.
+    fn minimum_version(&self) -> ((u8, u8), &'static str) {
+        let aes = matches!(
+            self.encryption,
+            Some(Encryption {
+                method: EncryptionMethod::Aes128,
+                ..
+            })
+        );
+        if aes {
+            ((1, 6), "AES encryption needs")
+        } else if self.xref_stream {
+            // Cross-reference streams were introduced in PDF 1.5.
+            ((1, 5), "cross-reference streams need")
+        } else if self.user_unit.is_some() {
+            ((1, 6), "user units need")
+        } else if self.object_streams {
+            ((1, 5), "object streams need")
+        } else {
+            ((1, 4), "")
+        }
+    }
+}
+
+/// Rejects option values that cannot produce a valid PDF.
+fn check_options(options: &Options) -> Result<()> {
+    let (major, minor) = options.version();
+    if !matches!((major, minor), (1, 0..=7) | (2, 0)) {
+        return Err(Img2PdfError::Unsupported(format!(
+            "unknown PDF version {}.{}",
+            major, minor
+        )));
+    }
+    let ((needed_major, needed_minor), feature) = options.minimum_version();
+    if (major, minor) < (needed_major, needed_minor) && !feature.is_empty() {
+        return Err(Img2PdfError::Unsupported(format!(
+            "{} PDF {}.{} or later, got {}.{}",
+            feature, needed_major, needed_minor, major, minor
+        )));
+    }
+    if options.pdfa {
+        let conflict = if (major, minor) > (1, 4) {
+            Some(format!("PDF {}.{}", major, minor))
+        } else if options.encryption.is_some() {
+            Some("encryption".to_string())
+        } else if options.interpolate {
+            Some("interpolation".to_string())
+        } else if options.transparent_page {
+            Some("transparency groups".to_string())
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            return Err(Img2PdfError::Unsupported(format!(
+                "PDF/A-1b does not allow {}",
+                conflict
+            )));
+        }
+    }
+    if options.transparent_page && options.background.is_some() {
+        return Err(Img2PdfError::Unsupported(
+            "a transparent page cannot have a background".to_string(),
+        ));
+    }
+    if options.linearize {
+        let conflict = if options.encryption.is_some() {
+            Some("encrypted")
+        } else if options.xref_stream {
+            Some("written with a cross-reference stream")
+        } else if options.object_streams {
+            Some("written with object streams")
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            return Err(Img2PdfError::Unsupported(format!(
+                "linearized PDFs cannot be {}",
+                conflict
+            )));
+        }
+    }
+    // Strings in an object stream are encrypted with the stream, not with
+    // their own object's key, which the writer does not support.
+    if options.object_streams && options.encryption.is_some() {
+        return Err(Img2PdfError::Unsupported(
+            "encrypted PDFs cannot be written with object streams".to_string(),
+        ));
+    }
+    if let Some(dpi) = options.dpi {
+        if !dpi.is_finite() || dpi <= 0.0 {
+            return Err(Img2PdfError::Unsupported(format!(
+                "DPI must be positive, got {}",
+                dpi
+            )));
+        }
+    }
+    let name = &options.image_resource_name;
+    let regular = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if name.is_empty() || !name.chars().all(regular) {
+        return Err(Img2PdfError::Unsupported(format!(
+            "image resource name {:?} must be letters, digits, '_' and '-'",
+            name
+        )));
+    }
+    if !options.decode.len().is_multiple_of(2)
+        || options.decode.iter().any(|value| !value.is_finite())
+    {
+        return Err(Img2PdfError::Unsupported(format!(
+            "a /Decode array needs pairs of finite values, got {:?}",
+            options.decode
+        )));
+    }
+    if let Some(first) = options.page_labels.first() {
+        if first.first_page != 0 {
+            return Err(Img2PdfError::Unsupported(format!(
+                "page labels must start at page 0, not {}",
+                first.first_page
+            )));
+        }
+    }
+    for pair in options.page_labels.windows(2) {
+        if pair[1].first_page <= pair[0].first_page {
+            return Err(Img2PdfError::Unsupported(format!(
+                "page label ranges must be in order, but page {} follows page {}",
+                pair[1].first_page, pair[0].first_page
+            )));
+        }
+    }
+    if let Some(label) = options.page_labels.iter().find(|label| label.start == 0) {
+        return Err(Img2PdfError::Unsupported(format!(
+            "page labels are numbered from 1, but the range at page {} starts at 0",
+            label.first_page
+        )));
+    }
+    if let Some(user_unit) = options.user_unit {
+        if !user_unit.is_finite() || user_unit <= 0.0 {
+            return Err(Img2PdfError::Unsupported(format!(
+                "the user unit must be positive, got {}",
+                user_unit
+            )));
+        }
+    }
+    if let Some(dpi) = options.target_dpi {
+        if !dpi.is_finite() || dpi <= 0.0 {
+            return Err(Img2PdfError::Unsupported(format!(
+                "target DPI must be positive, got {}",
+                dpi
+            )));
+        }
+    }
+    if let Some(border) = options.border {
+        if !border.width_pt.is_finite() || border.width_pt < 0.0 {
+            return Err(Img2PdfError::Unsupported(format!(
+                "border width must not be negative, got {}",
+                border.width_pt
+            )));
+        }
+    }
+    if let Some(max_variance) = options.mask_downsampling {
+        if !max_variance.is_finite() || max_variance < 0.0 {
+            return Err(Img2PdfError::Unsupported(format!(
+                "mask variance threshold must not be negative, got {}",
+                max_variance
+            )));
+        }
+    }
+    if let Some(degrees) = options.page_rotation {
+        if degrees % 90 != 0 {
+            return Err(Img2PdfError::Unsupported(format!(
+                "page rotation must be a multiple of 90 degrees, got {}",
+                degrees
+            )));
+        }
+    }
+    if options.compression > 9 {
+        return Err(Img2PdfError::Unsupported(format!(
+            "compression level must be 0 to 9, got {}",
+            options.compression
+        )));
+    }
+    if let Some(quality) = options.jpeg_quality {
+        if !(1..=100).contains(&quality) {
+            return Err(Img2PdfError::Unsupported(format!(
+                "JPEG quality must be 1 to 100, got {}",
+                quality
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Appends `img_data` as a new last page to `existing_pdf` with an
+/// incremental update, leaving the existing bytes untouched.
+///
+/// The update adds the page's objects, a new revision of the `/Pages`
+/// object, and a cross-reference section in the same form as the file's
+/// last one, linked to it with `/Prev`.
+fn append_page(existing_pdf: &[u8], img_data: &[u8], options: &Options) -> Result<Vec<u8>> {
+    check_options(options)?;
+    let malformed =
+        |what: &str| Img2PdfError::Unsupported(format!("cannot append to PDF: {}", what));
+    if options.encryption.is_some() {
+        return Err(malformed("incremental updates cannot be encrypted"));
+    }
+    if options.linearize {
+        return Err(malformed("incremental updates cannot be linearized"));
+    }
+    if options.object_streams {
+        return Err(malformed("incremental updates cannot use object streams"));
+    }
+    if options.pdfa {
+        return Err(malformed(
+            "PDF/A identification cannot be added to an existing PDF",
+        ));
+    }
+    if options.tagged() || options.language.is_some() {
+        return Err(malformed("tagging cannot be added to an existing PDF"));
+    }
+    if !options.page_labels.is_empty() {
+        return Err(malformed("page labels cannot be added to an existing PDF"));
+    }
+
+    let xref_start =
+        parse::last_startxref(existing_pdf).ok_or_else(|| malformed("no startxref"))?;
+    let xref_stream = !existing_pdf
+        .get(xref_start..)
+        .is_some_and(|xref| xref.starts_with(b"xref"));
+    let trailer_start = if xref_stream {
+        Some(xref_start)
+    } else {
+        existing_pdf[xref_start..]
+            .windows(7)
+            .position(|window| window == b"trailer")
+            .map(|offset| xref_start + offset)
+    };
+    let trailer = trailer_start
+        .and_then(|start| parse::read_dict(existing_pdf, start))
+        .ok_or_else(|| malformed("no trailer"))?;
+    if parse::dict_ref(trailer, "/Encrypt").is_some() {
+        return Err(malformed("the PDF is encrypted"));
+    }
+    let size = parse::dict_int(trailer, "/Size").ok_or_else(|| malformed("no /Size"))?;
+    // New objects are numbered from the size, so a `/Size` below the objects
+    // listed would reuse their numbers.
+    let size = parse::xref_size(existing_pdf).map_or(size, |listed| size.max(listed));
+    // Readers handle at most 8 388 607 objects, which also leaves room to
+    // number the new ones.
+    let size = u32::try_from(size)
+        .ok()
+        .filter(|&size| size <= 8_388_607)
+        .ok_or_else(|| malformed("/Size is past the 8388607 objects readers support"))?;
+    let root_object_id = parse::dict_ref(trailer, "/Root").ok_or_else(|| malformed("no /Root"))?;
+    let catalog =
+        parse::object_dict(existing_pdf, root_object_id).ok_or_else(|| malformed("no /Root"))?;
+    let pages_object_id =
+        parse::dict_ref(catalog, "/Pages").ok_or_else(|| malformed("no /Pages"))?;
+    let pages =
+        parse::object_dict(existing_pdf, pages_object_id).ok_or_else(|| malformed("no /Pages"))?;
+    let kids = parse::dict_refs(pages, "/Kids").ok_or_else(|| malformed("no /Kids"))?;
+
+    let mut pdf = PdfWriter::append(Vec::new(), existing_pdf, size)?;
+    let image = encode_page(img_data, options)?;
+    let page_object_id = write_image_page(&mut pdf, pages_object_id, &image, options)?;
+
+    let kids = kids
+        .iter()
+        .chain([&page_object_id])
+        .map(|id| format!("{} 0 R", id))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let count = parse::dict_int(pages, "/Count").unwrap_or_default() + 1;
+    // The rest of the page tree node, such as the `/Resources`, `/MediaBox`
+    // and `/Rotate` its pages inherit, is kept as it is.
+    let pages = parse::with_entry(pages, "/Kids", &format!("[ {} ]", kids));
+    let pages = parse::with_entry(&pages, "/Count", &count.to_string());
+    pdf.begin_object(pages_object_id)?;
+    writeln!(pdf, "{}", pages)?;
+    pdf.end_object()?;
+
+    // The header cannot change, but a catalog `/Version` overrides it when
+    // it is later. A page with user units needs PDF 1.6.
+    if let Some(version) = options.pdf_version.max(pdf.user_units.then_some((1, 6))) {
+        let current = parse::header_version(existing_pdf)
+            .max(parse::dict_version(catalog))
+            .unwrap_or((1, 0));
+        if version > current {
+            pdf.begin_object(root_object_id)?;
+            let version = format!("/{}.{}", version.0, version.1);
+            writeln!(pdf, "{}", parse::with_entry(catalog, "/Version", &version))?;
+            pdf.end_object()?;
+        }
+    }
+
+    // The first identifier stays with the document; the second changes with
+    // each revision.
+    let mut hasher = md5::Md5::new();
+    hasher.update(existing_pdf);
+    hasher.update(img_data);
+    if !options.deterministic {
+        let now = now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos());
+        hasher.update(&now.to_le_bytes());
+    }
+    let revision_id = hasher.finalize();
+    let document_id = parse::dict_id(trailer)
+        .and_then(|[first, _]| first.try_into().ok())
+        .unwrap_or(revision_id);
+
+    let trailer = Trailer {
+        root_object_id,
+        info_object_id: parse::dict_ref(trailer, "/Info"),
+        id: Some([document_id, revision_id]),
+        encrypt_object_id: None,
+        prev: Some(xref_start),
+    };
+    if xref_stream {
+        Ok(pdf.finish_with_xref_stream(&trailer)?)
+    } else {
+        Ok(pdf.finish(&trailer)?)
+    }
+}
+
+/// Whether the target has a clock. Reading the time panics on
+/// `wasm32-unknown-unknown`, which has none.
+const HAS_CLOCK: bool = !cfg!(all(target_arch = "wasm32", target_os = "unknown"));
+
+/// The current time, or the epoch without a clock, which dates documents
+/// like deterministic ones.
+fn now() -> SystemTime {
+    if HAS_CLOCK {
+        SystemTime::now()
+    } else {
+        UNIX_EPOCH
+    }
+}
+
+/// Computes the `/ID` pair for the trailer.
+///
+/// The first identifier is an MD5 of the input images and the metadata. The
+/// second is derived from the current time as well, so it changes with each
+/// conversion, except in deterministic mode where both are content-derived.
+fn file_id(images: &[&[u8]], options: &Options, timestamp: SystemTime) -> [[u8; 16]; 2] {
+    let mut hasher = md5::Md5::new();
+    for img_data in images {
+        hasher.update(&(img_data.len() as u64).to_le_bytes());
+        hasher.update(img_data);
+    }
+    let info = &options.info;
+    for field in [
+        &info.title,
+        &info.author,
+        &info.subject,
+        &info.keywords,
+        &info.creator,
+    ] {
+        hasher.update(field.as_deref().unwrap_or_default().as_bytes());
+        hasher.update(&[0]);
+    }
+    hasher.update(pdf_date(timestamp).as_bytes());
+    let content_id = hasher.finalize();
+
+    if options.deterministic {
+        return [content_id, content_id];
+    }
+
+    let now = now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos());
+    let time_id = md5::md5(&[&content_id[..], &now.to_le_bytes()].concat());
+    [content_id, time_id]
+}
+
+/// The frames of images, in order, with the index of the first frame of each
+/// image.
+type Frames<'a> = (Vec<Cow<'a, [u8]>>, Vec<usize>);
+
+/// Splits animated GIFs and multi-page TIFFs into one image per frame, in
+/// order, re-encoded as still images. Other inputs, including single-frame
+/// GIFs and TIFFs, are passed through unchanged.
+fn split_frames<'a>(images: &[&'a [u8]], options: &Options) -> Result<Frames<'a>> {
+    let mut frames = Vec::with_capacity(images.len());
+    let mut first_frames = Vec::with_capacity(images.len());
+    for &img_data in images {
+        first_frames.push(frames.len());
+        let split = match image::guess_format(img_data) {
+            Ok(ImageFormat::Gif) => {
+                check_pixel_count(img_data, options)?;
+                split_gif_frames(img_data)?
+            }
+            Ok(ImageFormat::Tiff) => split_tiff_pages(img_data, options)?.unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        if split.len() > 1 {
+            frames.extend(split.into_iter().map(Cow::Owned));
+        } else {
+            frames.push(Cow::Borrowed(img_data));
+        }
+    }
+    Ok((frames, first_frames))
+}
+
+/// Decodes every frame of a GIF, composited as it is displayed, and encodes
+/// each as a PNG. The GIF's aspect ratio byte is kept as the ratio of the
+/// PNG's densities, without a unit.
+fn split_gif_frames(img_data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    use image::AnimationDecoder;
+
+    let pixel_dims = match img_data.get(12) {
+        Some(&n) if n != 0 => Some(png::PixelDimensions {
+            xppu: 64,
+            yppu: n as u32 + 15,
+            unit: png::Unit::Unspecified,
+        }),
+        _ => None,
+    };
+    let decoder = image::codecs::gif::GifDecoder::new(img_data)?;
+    let mut frames = Vec::new();
+    for frame in decoder.into_frames() {
+        let buffer = frame?.into_buffer();
+        let mut png_data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png_data, buffer.width(), buffer.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_pixel_dims(pixel_dims);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&buffer))
+            .map_err(|error| {
+                Img2PdfError::Decode(image::ImageError::Encoding(
+                    image::error::EncodingError::new(ImageFormat::Png.into(), error),
+                ))
+            })?;
+        frames.push(png_data);
+    }
+    Ok(frames)
+}
+
+/// Splits a TIFF into single-page TIFFs, one per image file directory.
+/// Returns `None` if a page has a layout that cannot be rewritten, and an
+/// error if a page has more pixels than the options allow.
+fn split_tiff_pages(img_data: &[u8], options: &Options) -> Result<Option<Vec<Vec<u8>>>> {
+    let Ok(mut decoder) = tiff::decoder::Decoder::new(io::Cursor::new(img_data)) else {
+        return Ok(None);
+    };
+    let mut pages = Vec::new();
+    loop {
+        let Ok((width, height)) = decoder.dimensions() else {
+            return Ok(None);
+        };
+        check_pixels(width, height, options)?;
+        let Some(page) = split_tiff_page(&mut decoder, width, height) else {
+            return Ok(None);
+        };
+        pages.push(page);
+
+        if !decoder.more_images() {
+            return Ok(Some(pages));
+        }
+        if decoder.next_image().is_err() {
+            return Ok(None);
+        }
+    }
+}
+
+/// Rewrites the current page of a TIFF, of `width` × `height` pixels, as a
+/// single-page TIFF.
+fn split_tiff_page(
+    decoder: &mut tiff::decoder::Decoder<io::Cursor<&[u8]>>,
+    width: u32,
+    height: u32,
+) -> Option<Vec<u8>> {
+    use tiff::encoder::colortype;
+
+    match decoder.colortype().ok()? {
+        tiff::ColorType::Gray(8) => copy_tiff_page::<colortype::Gray8>(decoder, width, height),
+        tiff::ColorType::Gray(16) => copy_tiff_page::<colortype::Gray16>(decoder, width, height),
+        tiff::ColorType::RGB(8) => copy_tiff_page::<colortype::RGB8>(decoder, width, height),
+        tiff::ColorType::RGB(16) => copy_tiff_page::<colortype::RGB16>(decoder, width, height),
+        tiff::ColorType::RGBA(8) => copy_tiff_page::<colortype::RGBA8>(decoder, width, height),
+        tiff::ColorType::RGBA(16) => copy_tiff_page::<colortype::RGBA16>(decoder, width, height),
+        tiff::ColorType::CMYK(8) => copy_tiff_page::<colortype::CMYK8>(decoder, width, height),
+        _ => return None,
+    }
+    .ok()
+}
+
+/// Copies the current page of a TIFF, with samples of colour type `C`, into
+/// a single-page TIFF one band of rows at a time, with a strip per band. The
+/// page keeps its resolution, orientation and ICC profile.
+fn copy_tiff_page<C>(
+    decoder: &mut tiff::decoder::Decoder<io::Cursor<&[u8]>>,
+    width: u32,
+    height: u32,
+) -> Result<Vec<u8>>
+where
+    C: tiff::encoder::colortype::ColorType,
+    C::Inner: Sample,
+    [C::Inner]: tiff::encoder::TiffValue,
+{
+    use tiff::tags::{ResolutionUnit, Tag};
+
+    let (_, band_height) = decoder.chunk_dimensions();
+    let resolution = tiff_page_resolution(decoder);
+    let orientation = decoder
+        .find_tag_unsigned::<u16>(Tag::Orientation)
+        .ok()
+        .flatten();
+    let profile = tiff_page_icc_profile(decoder);
+
+    let mut page = io::Cursor::new(Vec::new());
+    let mut encoder = tiff::encoder::TiffEncoder::new(&mut page).map_err(tiff_error)?;
+    let mut image = encoder.new_image::<C>(width, height).map_err(tiff_error)?;
+    image.rows_per_strip(band_height).map_err(tiff_error)?;
+    if let Some((x, y, unit)) = resolution {
+        image.x_resolution(x);
+        image.y_resolution(y);
+        image.resolution_unit(ResolutionUnit::from_u16(unit).unwrap_or(ResolutionUnit::None));
+    }
+    if let Some(orientation) = orientation {
+        image
+            .encoder()
+            .write_tag(Tag::Orientation, orientation)
+            .map_err(tiff_error)?;
+    }
+    if let Some(profile) = profile {
+        image
+            .encoder()
+            .write_tag(TIFF_ICC_PROFILE, &profile[..])
+            .map_err(tiff_error)?;
+    }
+    read_tiff_bands::<C::Inner>(decoder, C::BITS_PER_SAMPLE.len(), |band| {
+        image.write_strip(band).map_err(tiff_error)
+    })?;
+    image.finish().map_err(tiff_error)?;
+    Ok(page.into_inner())
+}
+
+/// The tag holding the ICC profile of a TIFF page.
+const TIFF_ICC_PROFILE: tiff::tags::Tag = tiff::tags::Tag::Unknown(34675);
+
+/// Reads the `XResolution` and `YResolution` of the current page of a TIFF,
+/// with its `ResolutionUnit`: 1 for none, 2 for inches (the default when
+/// missing) or 3 for centimetres.
+fn tiff_page_resolution<R: io::Read + io::Seek>(
+    decoder: &mut tiff::decoder::Decoder<R>,
+) -> Option<(tiff::encoder::Rational, tiff::encoder::Rational, u16)> {
+    use tiff::decoder::ifd::Value;
+    use tiff::tags::Tag;
+
+    let mut rational = |tag| match decoder.find_tag(tag).ok()?? {
+        Value::Rational(n, d) => Some(tiff::encoder::Rational { n, d }),
+        _ => None,
+    };
+    let (x, y) = (rational(Tag::XResolution)?, rational(Tag::YResolution)?);
+    let unit = decoder
+        .find_tag_unsigned::<u16>(Tag::ResolutionUnit)
+        .ok()?
+        .unwrap_or(2);
+    Some((x, y, unit))
+}
+
+/// Reads the ICC profile of the current page of a TIFF.
+fn tiff_page_icc_profile<R: io::Read + io::Seek>(
+    decoder: &mut tiff::decoder::Decoder<R>,
+) -> Option<Vec<u8>> {
+    let profile = decoder
+        .find_tag(TIFF_ICC_PROFILE)
+        .ok()??
+        .into_u8_vec()
+        .ok()?;
+    (!profile.is_empty()).then_some(profile)
+}
+
+/// Calls `on_band` with the samples of the current page of a TIFF, with
+/// `channels` samples per pixel, one band of whole rows at a time: each
+/// strip, or each row of tiles put side by side. Only one band is decoded at
+/// a time, so large pages are never held whole.
+fn read_tiff_bands<T: Sample>(
+    decoder: &mut tiff::decoder::Decoder<io::Cursor<&[u8]>>,
+    channels: usize,
+    mut on_band: impl FnMut(&[T]) -> Result<()>,
+) -> Result<()> {
+    let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+    let planar = decoder
+        .find_tag_unsigned::<u16>(tiff::tags::Tag::PlanarConfiguration)
+        .map_err(tiff_error)?;
+    if planar.is_some_and(|planar| planar != 1) {
+        return Err(Img2PdfError::Unsupported(
+            "TIFF samples stored as separate planes are not supported".to_string(),
+        ));
+    }
+    let read_chunk = |decoder: &mut tiff::decoder::Decoder<_>, chunk| {
+        T::from_tiff(decoder.read_chunk(chunk).map_err(tiff_error)?).ok_or_else(|| {
+            Img2PdfError::Unsupported("TIFF samples of an unexpected type".to_string())
+        })
+    };
+
+    if decoder.get_chunk_type() == tiff::decoder::ChunkType::Strip {
+        for strip in 0..decoder.strip_count().map_err(tiff_error)? {
+            on_band(&read_chunk(decoder, strip)?)?;
+        }
+        return Ok(());
+    }
+
+    let (tile_width, tile_height) = decoder.chunk_dimensions();
+    let across = width.div_ceil(tile_width);
+    let tiles = decoder.tile_count().map_err(tiff_error)?;
+    if across as u64 * height.div_ceil(tile_height) as u64 != tiles as u64 {
+        return Err(Img2PdfError::Unsupported(format!(
+            "a {}x{} TIFF cannot have {} tiles of {}x{} pixels",
+            width, height, tiles, tile_width, tile_height
+        )));
+    }
+    let row_len = width as usize * channels;
+    let mut band = Vec::new();
+    for first in (0..tiles).step_by(across as usize) {
+        let (_, rows) = decoder.chunk_data_dimensions(first);
+        band.clear();
+        band.resize(row_len * rows as usize, T::from_u64(0));
+        for (column, tile) in (first..first + across).enumerate() {
+            let (columns, _) = decoder.chunk_data_dimensions(tile);
+            let samples = read_chunk(decoder, tile)?;
+            let tile_row_len = columns as usize * channels;
+            let start = column * tile_width as usize * channels;
+            for (band_row, tile_row) in band
+                .chunks_exact_mut(row_len)
+                .zip(samples.chunks_exact(tile_row_len))
+            {
+                band_row[start..start + tile_row_len].copy_from_slice(tile_row);
+            }
+        }
+        on_band(&band)?;
+    }
+    Ok(())
+}
+
+/// Reports an error of the `tiff` crate as a TIFF decoding error.
+fn tiff_error(error: tiff::TiffError) -> Img2PdfError {
+    Img2PdfError::Decode(image::ImageError::Decoding(
+        image::error::DecodingError::new(ImageFormat::Tiff.into(), error),
+    ))
+}
+
+/// Returns the offsets at which tiles of `tile` pixels, overlapping by
+/// `overlap`, start along an image edge of `length` pixels. The last tile ends
+/// at the edge, so it may be shorter.
+fn tile_starts(length: u32, tile: u32, overlap: u32) -> impl Iterator<Item = u32> {
+    let stride = (tile - overlap) as usize;
+    (0..length)
+        .step_by(stride)
+        .take_while(move |&start| start == 0 || start + overlap < length)
+}
+
+/// Writes the document information dictionary with the set fields of `info`
+/// and `timestamp` as both the creation and modification date.
+///
+/// # Returns
+///
+/// A `Result` containing the id of the info object.
+fn write_info<W: Write>(
+    pdf: &mut PdfWriter<W>,
+    info: &Info,
+    timestamp: SystemTime,
+) -> io::Result<u32> {
+    let mut entries: Vec<(&str, &str)> = [
+        ("Title", &info.title),
+        ("Author", &info.author),
+        ("Subject", &info.subject),
+        ("Keywords", &info.keywords),
+        ("Creator", &info.creator),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| value.as_ref().map(|value| (key, value.as_str())))
+    .collect();
+    entries.push(("Producer", info.producer()));
+    let date = pdf_date(timestamp);
+    entries.push(("CreationDate", &date));
+    entries.push(("ModDate", &date));
+
+    let info_object_id = pdf.reserve_object_id();
+    pdf.begin_object(info_object_id)?;
+    let entries: Vec<String> = entries
+        .into_iter()
+        .map(|(key, value)| format!("/{} {}", key, pdf.text_string(value)))
+        .collect();
+    writeln!(pdf, "<< {} >>", entries.join(" "))?;
+    pdf.end_object()?;
+    Ok(info_object_id)
+}
+
+/// Writes a flat document outline with a bookmark for each `(page object id,
+/// title)` pair, in order, and returns the id of the outline dictionary.
+fn write_outline<W: Write>(pdf: &mut PdfWriter<W>, bookmarks: &[(u32, &str)]) -> io::Result<u32> {
+    let outline_object_id = pdf.reserve_object_id();
+    let item_object_ids: Vec<u32> = bookmarks.iter().map(|_| pdf.reserve_object_id()).collect();
+
+    pdf.begin_object(outline_object_id)?;
+    writeln!(
+        pdf,
+        "<< /Type /Outlines /First {} 0 R /Last {} 0 R /Count {} >>",
+        item_object_ids[0],
+        item_object_ids[item_object_ids.len() - 1],
+        item_object_ids.len()
+    )?;
+    pdf.end_object()?;
+
+    for (i, &(page_object_id, title)) in bookmarks.iter().enumerate() {
+        let mut links = String::new();
+        if i > 0 {
+            links.push_str(&format!(" /Prev {} 0 R", item_object_ids[i - 1]));
+        }
+        if let Some(next) = item_object_ids.get(i + 1) {
+            links.push_str(&format!(" /Next {} 0 R", next));
+        }
+        pdf.begin_object(item_object_ids[i])?;
+        let title = pdf.text_string(title);
+        writeln!(
+            pdf,
+            "<< /Title {} /Parent {} 0 R{} /Dest [{} 0 R /Fit] >>",
+            title, outline_object_id, links, page_object_id
+        )?;
+        pdf.end_object()?;
+    }
+    Ok(outline_object_id)
+}
+
+/// Writes the structure tree of a tagged document: a `Document` element with
+/// a `Figure` for each image with an alternate text, and the parent tree
+/// that leads from the marked content of the pages back to the figures.
+///
+/// # Returns
+///
+/// The object id of the structure tree root.
+fn write_structure_tree<W: Write>(pdf: &mut PdfWriter<W>) -> io::Result<u32> {
+    let root_object_id = pdf.reserve_object_id();
+    let document_object_id = pdf.reserve_object_id();
+    let parent_tree_object_id = pdf.reserve_object_id();
+
+    let figure_pages = std::mem::take(&mut pdf.figure_pages);
+    let mut figure_object_ids = Vec::new();
+    let mut parent_tree = Vec::new();
+    for (struct_parents, (page_object_id, alt_texts)) in figure_pages.iter().enumerate() {
+        let mut page_figures = Vec::new();
+        for (mcid, alt_text) in alt_texts.iter().enumerate() {
+            let figure_object_id = pdf.reserve_object_id();
+            pdf.begin_object(figure_object_id)?;
+            let alt_text = pdf.text_string(alt_text);
+            writeln!(
+                pdf,
+                "<< /Type /StructElem /S /Figure /P {} 0 R /Pg {} 0 R /K {} /Alt {} >>",
+                document_object_id, page_object_id, mcid, alt_text
+            )?;
+            pdf.end_object()?;
+            page_figures.push(format!("{} 0 R", figure_object_id));
+        }
+        parent_tree.push(format!("{} [{}]", struct_parents, page_figures.join(" ")));
+        figure_object_ids.extend(page_figures);
+    }
+
+    pdf.begin_object(root_object_id)?;
+    writeln!(
+        pdf,
+        "<< /Type /StructTreeRoot /K {} 0 R /ParentTree {} 0 R /ParentTreeNextKey {} >>",
+        document_object_id,
+        parent_tree_object_id,
+        figure_pages.len()
+    )?;
+    pdf.end_object()?;
+    pdf.begin_object(document_object_id)?;
+    writeln!(
+        pdf,
+        "<< /Type /StructElem /S /Document /P {} 0 R /K [{}] >>",
+        root_object_id,
+        figure_object_ids.join(" ")
+    )?;
+    pdf.end_object()?;
+    pdf.begin_object(parent_tree_object_id)?;
+    writeln!(pdf, "<< /Nums [{}] >>", parent_tree.join(" "))?;
+    pdf.end_object()?;
+    Ok(root_object_id)
+}
+
+/// Writes an XMP metadata stream and returns its object id. The stream is
+/// left uncompressed, as PDF/A-1 requires, so that tools that do not parse
+/// PDF can still find it.
+fn write_metadata<W: Write>(pdf: &mut PdfWriter<W>, packet: &str) -> io::Result<u32> {
+    let metadata_object_id = pdf.reserve_object_id();
+    pdf.begin_object(metadata_object_id)?;
+    pdf.write_stream("/Type /Metadata /Subtype /XML", packet.as_bytes())?;
+    pdf.end_object()?;
+    Ok(metadata_object_id)
+}
+
+/// Formats `time` as the text of a PDF date in UTC, `D:YYYYMMDDHHmmSSZ`.
+fn pdf_date(time: SystemTime) -> String {
+    let [year, month, day, hour, minute, second] = calendar_time(time);
+    format!(
+        "D:{:04}{:02}{:02}{:02}{:02}{:02}Z",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// Formats `time` as an XMP date in UTC, `YYYY-MM-DDTHH:mm:SSZ`.
+fn xmp_date(time: SystemTime) -> String {
+    let [year, month, day, hour, minute, second] = calendar_time(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// Splits `time` into year, month, day, hour, minute and second in UTC.
+/// Times before the Unix epoch are clamped to it.
+fn calendar_time(time: SystemTime) -> [u64; 6] {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Converts days since 1970-01-01 to a proleptic Gregorian date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    [
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+    ]
+}
+
+/// Encodes the image of a single page, rejecting images that cannot make one.
+fn encode_page(img_data: &[u8], options: &Options) -> Result<EncodedImage> {
+    if img_data.is_empty() {
+        return Err(Img2PdfError::Unsupported("image data is empty".to_string()));
+    }
+    let image = encode_image(img_data, options)?;
+    if image.width == 0 || image.height == 0 {
+        return Err(Img2PdfError::Unsupported(format!(
+            "image has no area ({}x{})",
+            image.width, image.height
+        )));
+    }
+    check_decode(&image, options)?;
+    Ok(image)
+}
+
+/// The longest side of a thumbnail, in pixels, as Acrobat makes them.
+const THUMBNAIL_SIZE: u32 = 106;
+
+/// Decodes an image that is embedded without being decoded, and shrinks it
+/// to a thumbnail, turned upright like the image itself.
+///
+/// # Returns
+///
+/// The thumbnail, or `None` if the `image` crate cannot decode the image,
+/// as for CMYK TIFFs, or if it has more pixels than the options allow, as
+/// JPEGs embedded as they are may.
+fn encode_thumbnail(img_data: &[u8], options: &Options) -> Result<Option<Thumbnail>> {
+    if check_pixel_count(img_data, options).is_err() {
+        debug!("skipping thumbnail of an image with more pixels than allowed");
+        return Ok(None);
+    }
+    let Ok(img) = decode_image(img_data, options) else {
+        debug!("skipping thumbnail of an image the image crate cannot decode");
+        return Ok(None);
+    };
+    let orientation = if options.orients_pixels() {
+        read_orientation(img_data).unwrap_or(1)
+    } else {
+        1
+    };
+    Ok(Some(thumbnail_of(
+        &apply_orientation(img, orientation),
+        options,
+    )?))
+}
+
+/// Shrinks decoded pixels to a thumbnail, composited onto the background.
+fn thumbnail_of(img: &DynamicImage, options: &Options) -> io::Result<Thumbnail> {
+    let img = img.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    let mut samples = SampleWriter::new(options);
+    let background = options.background.unwrap_or([255, 255, 255]);
+    write_samples::<u8>(&img, Some(background), false, false, &mut samples, None)?;
+    let (filter, data) = samples.finish()?;
+    Ok(Thumbnail {
+        width: img.width(),
+        height: img.height(),
+        filter,
+        data,
+    })
+}
+
+/// Encodes pixels given in memory for a single page, as [`encode_page`]
+/// does decoded images.
+fn encode_raw(
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+    format: PixelFormat,
+    options: &Options,
+) -> Result<EncodedImage> {
+    let expected = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|area| area.checked_mul(format.channels()));
+    if expected != Some(pixels.len()) {
+        return Err(Img2PdfError::Unsupported(format!(
+            "{}x{} {:?} pixels need {} bytes, got {}",
+            width,
+            height,
+            format,
+            expected.map_or("more".to_string(), |expected| expected.to_string()),
+            pixels.len()
+        )));
+    }
+    let pixels = pixels.to_vec();
+    let img = match format {
+        PixelFormat::Gray8 => {
+            image::GrayImage::from_raw(width, height, pixels).map(DynamicImage::from)
+        }
+        PixelFormat::GrayA8 => {
+            image::GrayAlphaImage::from_raw(width, height, pixels).map(DynamicImage::from)
+        }
+        PixelFormat::Rgb8 => {
+            image::RgbImage::from_raw(width, height, pixels).map(DynamicImage::from)
+        }
+        PixelFormat::Rgba8 => {
+            image::RgbaImage::from_raw(width, height, pixels).map(DynamicImage::from)
+        }
+    }
+    .expect("the length of the pixels was checked");
+    let image = encode_decoded(img, None, 1.0, options, true)?;
+    check_decode(&image, options)?;
+    Ok(image)
+}
+
+/// Encodes decoded pixels, already upright, at resolution `dpi` with pixels
+/// `pixel_aspect` times as wide as tall: downscaled to the size limits, and
+/// with a thumbnail made from the full pixels when the options ask for one.
+///
+/// Decoded image files and images built by the caller, placed as if they
+/// were decoded from a file without a resolution, both go through here.
+/// Gray detection is only done when `detect_gray` is set.
+fn encode_decoded(
+    img: DynamicImage,
+    dpi: Option<(f32, f32)>,
+    pixel_aspect: f32,
+    options: &Options,
+    detect_gray: bool,
+) -> Result<EncodedImage> {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return Err(Img2PdfError::Unsupported(format!(
+            "image has no area ({}x{})",
+            width, height
+        )));
+    }
+    let thumbnail = if options.thumbnails {
+        Some(thumbnail_of(&img, options)?)
+    } else {
+        None
+    };
+    let (img, resampled_from) = limit_size(img, resolution(dpi, pixel_aspect, options), options);
+
+    let image = encode_pixels(&img, options, detect_gray)?;
+    Ok(EncodedImage {
+        dpi,
+        pixel_aspect,
+        resampled_from,
+        thumbnail,
+        ..image
+    })
+}
+
+/// Encodes a batch of pages, concurrently when the conversion is parallel.
+/// The results are in the order of `images`.
+fn encode_pages(images: &[&[u8]], options: &Options) -> Vec<Result<EncodedImage>> {
+    #[cfg(feature = "rayon")]
+    if options.parallel {
+        use rayon::prelude::*;
+        return images
+            .par_iter()
+            .map(|img_data| encode_page(img_data, options))
+            .collect();
+    }
+    images
+        .iter()
+        .map(|img_data| encode_page(img_data, options))
+        .collect()
+}
+
+/// The `/Decode` array of an image, if it has one: the one it needs to be
+/// read right, such as for an inverted Adobe CMYK JPEG, followed by `decode`,
+/// the one set with [`Img2Pdf::decode`]. Palette images keep theirs, since
+/// `decode` maps the palette.
+fn decode_array(image: &EncodedImage, decode: &[f32]) -> io::Result<Option<String>> {
+    if decode.is_empty() || matches!(image.color_space, ColorSpace::Indexed(_)) {
+        return Ok(image.decode.map(str::to_string));
+    }
+    let components = image.color_space.components() as usize;
+    let decode = decode_pairs(decode, components, &image.color_space)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let needed: Vec<f32> = match image.decode {
+        Some(needed) => needed
+            .trim_matches(['[', ']'])
+            .split_whitespace()
+            .map(|value| value.parse().expect("decode hints are numbers"))
+            .collect(),
+        None => [0.0, 1.0].repeat(components),
+    };
+    // Decoding maps a sample to `min + sample * (max - min)`, so mapping
+    // the result again maps the ends of the range.
+    let composed: Vec<String> = needed
+        .chunks_exact(2)
+        .zip(decode.chunks_exact(2))
+        .flat_map(|(needed, pair)| {
+            needed
+                .iter()
+                .map(move |end| pair[0] + end * (pair[1] - pair[0]))
+        })
+        .map(|value| value.to_string())
+        .collect();
+    Ok(Some(format!("[{}]", composed.join(" "))))
+}
+
+/// Maps each colour of a palette of `r, g, b` triples through a `/Decode`
+/// array, as readers would map the samples of an RGB image.
+fn decode_palette(palette: &[u8], decode: &[f32]) -> io::Result<Vec<u8>> {
+    let decode = decode_pairs(decode, 3, &ColorSpace::DeviceRGB)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    Ok(palette
+        .chunks_exact(3)
+        .flat_map(|color| {
+            color
+                .iter()
+                .zip(decode.chunks_exact(2))
+                .map(|(&value, pair)| {
+                    let value = pair[0] + value as f32 / 255.0 * (pair[1] - pair[0]);
+                    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+                })
+        })
+        .collect())
+}
+
+/// A `/Decode` array with a pair for each of `components`, repeating a single
+/// pair. Other arrays that do not fit the colour space are an error.
+fn decode_pairs(
+    decode: &[f32],
+    components: usize,
+    color_space: &ColorSpace,
+) -> std::result::Result<Vec<f32>, String> {
+    match decode.len() {
+        2 => Ok(decode.repeat(components)),
+        length if length == 2 * components => Ok(decode.to_vec()),
+        length => Err(format!(
+            "the /Decode array has {} values, but {} images need {}",
+            length,
+            color_space.device_space(),
+            2 * components
+        )),
+    }
+}
+
+/// Rejects a `/Decode` array set in the options that does not fit the colour
+/// space of `image`, before the image is written.
+///
+/// Lab samples are rejected outright: their range is set with the colour
+/// space, and their default decoding is not the `[0 1]` per component the
+/// array is composed with.
+fn check_decode(image: &EncodedImage, options: &Options) -> Result<()> {
+    if options.decode.is_empty() {
+        return Ok(());
+    }
+    let components = match &image.color_space {
+        ColorSpace::Passthrough(PassthroughColorSpace::Lab { .. }) => {
+            return Err(Img2PdfError::Unsupported(
+                "a /Decode array cannot be used with Lab samples; set their range instead"
+                    .to_string(),
+            ))
+        }
+        // The palette is mapped, which holds RGB colours.
+        ColorSpace::Indexed(_) => 3,
+        color_space => color_space.components() as usize,
+    };
+    decode_pairs(&options.decode, components, &image.color_space)
+        .map(drop)
+        .map_err(Img2PdfError::Unsupported)
+}
+
+/// Writes the image XObject of an image, followed by its colour profile and
+/// soft mask, if it has them.
+///
+/// # Returns
+///
+/// The object ids of the image XObject and of its soft mask, if it has one.
+fn write_image_xobject<W: Write>(
+    pdf: &mut PdfWriter<W>,
+    image: &EncodedImage,
+    options: &Options,
+) -> io::Result<(u32, Option<u32>)> {
+    let image_object_id = pdf.reserve_object_id();
+    let profile_object_id = image.icc_profile.as_ref().map(|_| pdf.reserve_object_id());
+    let tint_transform = match &image.color_space {
+        ColorSpace::Passthrough(PassthroughColorSpace::DeviceN { colorants }) => {
+            Some((pdf.reserve_object_id(), colorants))
+        }
+        _ => None,
+    };
+    let mask_object_id = image.mask.as_ref().map(|_| pdf.reserve_object_id());
+
+    let smask = match (mask_object_id, options.color_key) {
+        (Some(id), _) => format!(" /SMask {} 0 R", id),
+        (None, Some(key)) => match color_key_ranges(image, key) {
+            Some(ranges) => format!(" /Mask [{}]", ranges),
+            None => String::new(),
+        },
+        (None, None) => String::new(),
+    };
+    pdf.begin_object(image_object_id)?;
+    let mut hints = String::new();
+    if options.interpolate {
+        hints.push_str(" /Interpolate true");
+    }
+    if let Some(intent) = options.intent {
+        hints.push_str(&format!(" /Intent {}", intent.name()));
+    }
+    // Palettes are mapped rather than their indices.
+    let color_space = match (&image.color_space, options.decode.is_empty()) {
+        (ColorSpace::Indexed(palette), false) => Cow::Owned(ColorSpace::Indexed(decode_palette(
+            palette,
+            &options.decode,
+        )?)),
+        (color_space, _) => Cow::Borrowed(color_space),
+    };
+    if let Some(decode) = decode_array(image, &options.decode)? {
+        hints.push_str(&format!(" /Decode {}", decode));
+    }
+    let dict = format!(
+        "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace {} /BitsPerComponent {}{}{}{}{}",
+        image.width,
+        image.height,
+        color_space.to_pdf(profile_object_id.or(tint_transform.map(|(id, _)| id))),
+        image.bits_per_component,
+        hints,
+        filter_entry(image.filter),
+        decode_parms_entry(
+            image.predicted,
+            image.color_space.components(),
+            image.bits_per_component,
+            image.width
+        ),
+        smask
+    );
+    pdf.write_stream(&dict, &image.data)?;
+    pdf.end_object()?;
+    pdf.report.compressed |= image.filter == Some("/FlateDecode");
+    // Re-encoded JPEGs only have `/DCTDecode` when a JPEG quality is set.
+    pdf.report.passthrough |= image.filter == Some("/DCTDecode") && options.jpeg_quality.is_none();
+
+    if let (Some(profile_object_id), Some(profile)) = (profile_object_id, &image.icc_profile) {
+        pdf.begin_object(profile_object_id)?;
+        let dict = format!(
+            "/N {} /Alternate {}{}",
+            profile.components,
+            image.color_space.device_space(),
+            filter_entry(profile.filter)
+        );
+        pdf.write_stream(&dict, &profile.data)?;
+        pdf.end_object()?;
+    }
+
+    if let Some((id, colorants)) = tint_transform {
+        pdf.begin_object(id)?;
+        let dict = format!(
+            "/FunctionType 4 /Domain [{}] /Range [0 1 0 1 0 1 0 1]",
+            vec!["0 1"; colorants.len()].join(" ")
+        );
+        let code = PassthroughColorSpace::tint_transform(colorants);
+        pdf.write_stream(&dict, code.as_bytes())?;
+        pdf.end_object()?;
+    }
+
+    if let (Some(mask_object_id), Some(mask)) = (mask_object_id, &image.mask) {
+        pdf.begin_object(mask_object_id)?;
+        let dict = format!(
+            "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceGray /BitsPerComponent {}{}{}",
+            mask.width,
+            mask.height,
+            mask.bits_per_component,
+            filter_entry(mask.filter),
+            decode_parms_entry(mask.predicted, 1, mask.bits_per_component, mask.width)
+        );
+        pdf.write_stream(&dict, &mask.data)?;
+        pdf.end_object()?;
+    }
+
+    Ok((image_object_id, mask_object_id))
+}
+
+/// Returns the `/Mask` colour-key ranges that make the pixels of colour `key`
+/// transparent, a minimum and maximum per component, or `None` if the samples
+/// of `image` cannot hold that colour exactly.
+fn color_key_ranges(image: &EncodedImage, key: [u8; 3]) -> Option<String> {
+    let max = (1u32 << image.bits_per_component) - 1;
+    // Keys are scaled to the bit depth, which for fewer than 8 bits only
+    // works for the extremes.
+    let scale = |value: u8| {
+        (value as u32 * max)
+            .is_multiple_of(255)
+            .then_some(value as u32 * max / 255)
+    };
+    let components = match &image.color_space {
+        ColorSpace::DeviceRGB => key
+            .iter()
+            .map(|&value| scale(value))
+            .collect::<Option<Vec<_>>>()?,
+        ColorSpace::DeviceGray if key[0] == key[1] && key[1] == key[2] => vec![scale(key[0])?],
+        ColorSpace::Indexed(palette) => {
+            vec![palette.chunks_exact(3).position(|color| color == key)? as u32]
+        }
+        _ => return None,
+    };
+    Some(
+        components
+            .iter()
+            .map(|value| format!("{0} {0}", value))
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Writes the image XObject, soft mask, content stream and page object for a
+/// single image.
+///
+/// # Arguments
+///
+/// * `pdf` - The `PdfWriter` to append the objects to.
+/// * `pages_object_id` - The id of the `/Pages` object the page belongs to.
+/// * `image` - The encoded image, from [`encode_page`].
+/// * `options` - The page settings.
+///
+/// # Returns
+///
+/// A `Result` containing the id of the page object on success, or an `Img2PdfError` on failure.
+fn write_image_page<W: Write>(
+    pdf: &mut PdfWriter<W>,
+    pages_object_id: u32,
+    image: &EncodedImage,
+    options: &Options,
+) -> Result<u32> {
+    let (image_object_id, _) = write_image_xobject(pdf, image, options)?;
+    let thumbnail_object_id = match &image.thumbnail {
+        Some(thumbnail) => {
+            let id = pdf.reserve_object_id();
+            pdf.begin_object(id)?;
+            let dict = format!(
+                "/Width {} /Height {} /ColorSpace /DeviceRGB /BitsPerComponent 8{}",
+                thumbnail.width,
+                thumbnail.height,
+                filter_entry(thumbnail.filter)
+            );
+            pdf.write_stream(&dict, &thumbnail.data)?;
+            pdf.end_object()?;
+            Some(id)
+        }
+        None => None,
+    };
+    let (natural_width, natural_height) = natural_size(image, options);
+    let placement = place_image(natural_width, natural_height, options);
+    let drawing = draw_image(
+        &options.image_name(0),
+        placement.x,
+        placement.y,
+        placement.width,
+        placement.height,
+        options,
+    );
+    write_page(
+        pdf,
+        pages_object_id,
+        (placement.page_width, placement.page_height),
+        &[(image_object_id, drawing)],
+        thumbnail_object_id,
+        options,
+    )
+}
+
+/// Returns the size of an image at its resolution, in points.
+fn natural_size(image: &EncodedImage, options: &Options) -> (f32, f32) {
+    let (dpi_x, dpi_y) = resolution(image.dpi, image.pixel_aspect, options);
+    // Downscaled images keep the page size of the original.
+    let (width, height) = image.resampled_from.unwrap_or((image.width, image.height));
+    (width as f32 * 72.0 / dpi_x, height as f32 * 72.0 / dpi_y)
+}
+
+/// Returns the resolution an image is placed at, across and down: the one
+/// set in the options, else `dpi`, the image's own, else 72 DPI.
+///
+/// The image's own resolution describes the shape of its pixels already.
+/// Otherwise pixels `pixel_aspect` times as wide as they are tall keep their
+/// shape by taking the resolution down the page and scaling it across, as
+/// anamorphic video frames are widened for display.
+fn resolution(dpi: Option<(f32, f32)>, pixel_aspect: f32, options: &Options) -> (f32, f32) {
+    match (options.dpi, dpi) {
+        (None, Some(dpi)) => dpi,
+        (dpi, _) => {
+            let dpi = dpi.unwrap_or(72.0);
+            (dpi / pixel_aspect, dpi)
+        }
+    }
+}
+
+/// Returns the content stream operators that draw the image XObject named
+/// `name` in the page's resources into the rectangle at `x`, `y` of
+/// `width` × `height` points, followed by its border if the options have one.
+fn draw_image(name: &str, x: f32, y: f32, width: f32, height: f32, options: &Options) -> String {
+    let mut content = format!("q\n{} 0 0 {} {} {} cm\n{} Do\nQ", width, height, x, y, name);
+    if let Some(border) = options.border {
+        // Strokes are centered on the path, so a border outside the image is
+        // drawn along a rectangle half a line width larger.
+        let grow = if border.outside {
+            border.width_pt / 2.0
+        } else {
+            0.0
+        };
+        content.push_str(&format!(
+            "\nq\n{} RG\n{} w\n{} {} {} {} re\nS\nQ",
+            rgb_operands(border.color),
+            border.width_pt,
+            x - grow,
+            y - grow,
+            width + 2.0 * grow,
+            height + 2.0 * grow
+        ));
+    }
+    content
+}
+
+/// Formats an RGB colour as the three operands of a colour operator such as
+/// `RG` or `rg`.
+fn rgb_operands(color: [u8; 3]) -> String {
+    color
+        .iter()
+        .map(|&channel| format!("{}", channel as f32 / 255.0))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Writes the content stream and page object of a page.
+///
+/// # Arguments
+///
+/// * `pdf` - The `PdfWriter` to append the objects to.
+/// * `pages_object_id` - The id of the `/Pages` object the page belongs to.
+/// * `page_size` - The width and height of the page, in points.
+/// * `drawings` - The image XObjects on the page, each with the content
+///   stream operators that draw it. Each is named in the page's
+///   resources by [`Options::image_name`] with its index in `drawings`.
+/// * `thumbnail_object_id` - The thumbnail image of the page, if it has one.
+/// * `options` - The page settings. A background is filled in before the
+///   drawings, unless the page is a transparency group.
+///
+/// # Returns
+///
+/// The id of the page object, or an error if the crop box does not fit the
+/// page.
+fn write_page<W: Write>(
+    pdf: &mut PdfWriter<W>,
+    pages_object_id: u32,
+    (page_width, page_height): (f32, f32),
+    drawings: &[(u32, String)],
+    thumbnail_object_id: Option<u32>,
+    options: &Options,
+) -> Result<u32> {
+    // Coordinates are in user units, which a user unit other than 1 scales.
+    let user_unit = options.user_unit.unwrap_or_else(|| {
+        let largest = page_width.max(page_height);
+        if largest > MAX_PAGE_UNITS {
+            largest / MAX_PAGE_UNITS
+        } else {
+            1.0
+        }
+    });
+    if user_unit != 1.0 && options.pdfa {
+        return Err(Img2PdfError::Unsupported(format!(
+            "PDF/A-1b pages cannot be larger than {} pt, got {}x{} pt",
+            MAX_PAGE_UNITS, page_width, page_height
+        )));
+    }
+    let scale = 1.0 / user_unit;
+
+    let mut entries = String::new();
+    if user_unit != 1.0 {
+        entries.push_str(&format!(" /UserUnit {}", user_unit));
+        pdf.user_units = true;
+    }
+    if let Some(insets) = options.crop_box {
+        let (right, top) = (page_width - insets.right, page_height - insets.top);
+        // Negative insets would reach outside the media box.
+        let outside = [insets.top, insets.right, insets.bottom, insets.left]
+            .iter()
+            .any(|inset| !inset.is_finite() || *inset < 0.0);
+        if outside || insets.left >= right || insets.bottom >= top {
+            return Err(Img2PdfError::Unsupported(format!(
+                "a crop box inset by {:?} does not fit within a page of {}x{} pt",
+                insets, page_width, page_height
+            )));
+        }
+        entries.push_str(&format!(
+            " /CropBox [{} {} {} {}]",
+            insets.left * scale,
+            insets.bottom * scale,
+            right * scale,
+            top * scale
+        ));
+    }
+
+    let content_stream_object_id = pdf.reserve_object_id();
+    let page_object_id = pdf.reserve_object_id();
+
+    // In tagged documents, images with an alternate text are figures of the
+    // structure tree, and everything else is marked as an artifact.
+    let tagged = options.tagged();
+    let mut alt_texts = Vec::new();
+    let mut content: Vec<String> = drawings
+        .iter()
+        .map(|(_, drawing)| {
+            let alt_text = options.alt_texts.get(pdf.images_placed);
+            pdf.images_placed += 1;
+            if !tagged {
+                return drawing.clone();
+            }
+            match alt_text.filter(|text| !text.is_empty()) {
+                Some(text) => {
+                    alt_texts.push(text.clone());
+                    format!(
+                        "/Figure <</MCID {}>> BDC\n{}\nEMC",
+                        alt_texts.len() - 1,
+                        drawing
+                    )
+                }
+                None => format!("/Artifact BMC\n{}\nEMC", drawing),
+            }
+        })
+        .collect();
+    if let Some(color) = options.background {
+        let fill = format!(
+            "q\n{} rg\n0 0 {} {} re\nf\nQ",
+            rgb_operands(color),
+            page_width,
+            page_height
+        );
+        if tagged {
+            content.insert(0, format!("/Artifact BMC\n{}\nEMC", fill));
+        } else {
+            content.insert(0, fill);
+        }
+    }
+    if user_unit != 1.0 {
+        content.insert(0, format!("{0} 0 0 {0} 0 0 cm", scale));
+    }
+    pdf.begin_object(content_stream_object_id)?;
+    pdf.write_stream("", content.join("\n").as_bytes())?;
+    pdf.end_object()?;
+
+    let xobjects = drawings
+        .iter()
+        .enumerate()
+        .map(|(i, (id, _))| format!("{} {} 0 R", options.image_name(i), id))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if let Some(degrees) = options.page_rotation.filter(|degrees| degrees % 360 != 0) {
+        entries.push_str(&format!(" /Rotate {}", degrees.rem_euclid(360)));
+    }
+    if let Some(id) = thumbnail_object_id {
+        entries.push_str(&format!(" /Thumb {} 0 R", id));
+    }
+    if options.transparent_page {
+        entries.push_str(" /Group << /S /Transparency /CS /DeviceRGB >>");
+    }
+    if !alt_texts.is_empty() {
+        entries.push_str(&format!(" /StructParents {}", pdf.figure_pages.len()));
+        // Tab order was introduced in PDF 1.5.
+        if options.version() >= (1, 5) {
+            entries.push_str(" /Tabs /S");
+        }
+        pdf.figure_pages.push((page_object_id, alt_texts));
+    }
+    pdf.begin_object(page_object_id)?;
+    writeln!(
+        pdf,
+        "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Contents {} 0 R /Resources << /XObject << {} >> >>{} >>",
+        pages_object_id,
+        page_width * scale,
+        page_height * scale,
+        content_stream_object_id,
+        xobjects,
+        entries
+    )?;
+    pdf.end_object()?;
+    pdf.report.page_count += 1;
+    pdf.report.page_sizes.push((page_width, page_height));
+
+    Ok(page_object_id)
+}
+
+/// The largest page side, in units, that viewers handle reliably: 200 inches
+/// in points.
+const MAX_PAGE_UNITS: f32 = 14400.0;
+
+/// Writes a contact sheet: the images in a grid of `cols` columns of square
+/// cells, each image scaled to fit its cell and centered in it.
+///
+/// Pages sized to fit hold the whole grid. Fixed-size pages hold as many rows
+/// as fit inside the margins, and the sheet continues on further pages.
+fn write_contact_sheet<W: Write>(
+    pdf: &mut PdfWriter<W>,
+    pages_object_id: u32,
+    images: &[&[u8]],
+    cols: usize,
+    cell_size: f32,
+    options: &Options,
+) -> Result<Vec<u32>> {
+    let margins = options.margins;
+    let grid_width = cols as f32 * cell_size;
+    let (page_width, page_height, rows_per_page) = match options.page_size.dimensions() {
+        Some((width, height)) => {
+            let (width, height) = turn_page(width, height, options.orientation);
+            let available_width = width - margins.left - margins.right;
+            let available_height = height - margins.top - margins.bottom;
+            let rows = (available_height / cell_size).floor();
+            if grid_width > available_width || rows < 1.0 {
+                return Err(Img2PdfError::Unsupported(format!(
+                    "{} columns of {} pt cells do not fit the page",
+                    cols, cell_size
+                )));
+            }
+            (width, height, rows as usize)
+        }
+        None => {
+            let rows = images.len().div_ceil(cols);
+            (
+                grid_width + margins.left + margins.right,
+                rows as f32 * cell_size + margins.top + margins.bottom,
+                rows,
+            )
+        }
+    };
+
+    let mut page_object_ids = Vec::new();
+    for page_images in images.chunks(cols * rows_per_page) {
+        let mut drawings = Vec::new();
+        for (i, image) in encode_pages(page_images, options).into_iter().enumerate() {
+            let image = image?;
+            let (image_object_id, _) = write_image_xobject(pdf, &image, options)?;
+            let (width, height) = natural_size(&image, options);
+            let scale = (cell_size / width).min(cell_size / height);
+            let (width, height) = (width * scale, height * scale);
+            let (col, row) = (i % cols, i / cols);
+            let cell_x = margins.left + col as f32 * cell_size;
+            let cell_y = page_height - margins.top - (row + 1) as f32 * cell_size;
+            let drawing = draw_image(
+                &options.image_name(drawings.len()),
+                cell_x + (cell_size - width) / 2.0,
+                cell_y + (cell_size - height) / 2.0,
+                width,
+                height,
+                options,
+            );
+            drawings.push((image_object_id, drawing));
+        }
+        page_object_ids.push(write_page(
+            pdf,
+            pages_object_id,
+            (page_width, page_height),
+            &drawings,
+            None,
+            options,
+        )?);
+    }
+    Ok(page_object_ids)
+}
+
+/// Writes images packed onto pages in rows: each image goes to the right of
+/// the last one, below the current row if it does not fit next to it, and
+/// on a new page if it does not fit below either. A row is as tall as its
+/// tallest image.
+fn write_packed_pages<W: Write>(
+    pdf: &mut PdfWriter<W>,
+    pages_object_id: u32,
+    images: &[&[u8]],
+    spacing: f32,
+    options: &Options,
+) -> Result<Vec<u32>> {
+    let margins = options.margins;
+    let (page_width, page_height) = options
+        .page_size
+        .dimensions()
+        .or(PageSize::A4.dimensions())
+        .unwrap();
+    let (page_width, page_height) = turn_page(page_width, page_height, options.orientation);
+    let (left, right) = (margins.left, page_width - margins.right);
+    let (top, bottom) = (page_height - margins.top, margins.bottom);
+    if left >= right || bottom >= top {
+        return Err(Img2PdfError::Unsupported(format!(
+            "the margins leave no space on a page of {}x{} pt",
+            page_width, page_height
+        )));
+    }
+
+    let mut page_object_ids = Vec::new();
+    let mut drawings = Vec::new();
+    // The left edge of the next image, and the top and height of the row.
+    let (mut x, mut row_top, mut row_height) = (left, top, 0.0f32);
+    for img_data in images {
+        let image = encode_page(img_data, options)?;
+        let (width, height) = natural_size(&image, options);
+        let scale = ((right - left) / width)
+            .min((top - bottom) / height)
+            .min(1.0);
+        let (width, height) = (width * scale, height * scale);
+
+        if x > left && x + width > right {
+            (x, row_top, row_height) = (left, row_top - row_height - spacing, 0.0);
+        }
+        if row_top < top && row_top - height < bottom {
+            page_object_ids.push(write_page(
+                pdf,
+                pages_object_id,
+                (page_width, page_height),
+                &drawings,
+                None,
+                options,
+            )?);
+            drawings.clear();
+            (x, row_top, row_height) = (left, top, 0.0);
+        }
+
+        let (image_object_id, _) = write_image_xobject(pdf, &image, options)?;
+        let name = options.image_name(drawings.len());
+        let drawing = draw_image(&name, x, row_top - height, width, height, options);
+        drawings.push((image_object_id, drawing));
+        x += width + spacing;
+        row_height = row_height.max(height);
+    }
+    page_object_ids.push(write_page(
+        pdf,
+        pages_object_id,
+        (page_width, page_height),
+        &drawings,
+        None,
+        options,
+    )?);
+    Ok(page_object_ids)
+}
+
+/// The page size and the rectangle the image is drawn into, in points.
+struct Placement {
+    page_width: f32,
+    page_height: f32,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+/// Computes the page size and where on it an image with a natural size of
+/// `image_width` × `image_height` points is drawn: scaled to fit inside the
+/// margins and centered there, or stretched to fill them when the aspect
+/// ratio is not preserved.
+fn place_image(image_width: f32, image_height: f32, options: &Options) -> Placement {
+    let margins = options.margins;
+    let (page_width, page_height) = options.page_size.dimensions().unwrap_or((
+        image_width + margins.left + margins.right,
+        image_height + margins.top + margins.bottom,
+    ));
+    // Forcing an orientation turns the page, not the image, which is then
+    // fitted to the turned page.
+    let (page_width, page_height) = turn_page(page_width, page_height, options.orientation);
+
+    let available_width = (page_width - margins.left - margins.right).max(0.0);
+    let available_height = (page_height - margins.top - margins.bottom).max(0.0);
+    let (placed_width, placed_height) = if options.preserve_aspect {
+        let scale = (available_width / image_width).min(available_height / image_height);
+        (image_width * scale, image_height * scale)
+    } else {
+        (available_width, available_height)
+    };
+
+    Placement {
+        page_width,
+        page_height,
+        x: margins.left + (available_width - placed_width) / 2.0,
+        y: margins.bottom + (available_height - placed_height) / 2.0,
+        width: placed_width,
+        height: placed_height,
+    }
+}
+
+/// Swaps the sides of a page when that gives it the requested orientation.
+fn turn_page(width: f32, height: f32, orientation: Orientation) -> (f32, f32) {
+    match orientation {
+        Orientation::Portrait if width > height => (height, width),
+        Orientation::Landscape if height > width => (height, width),
+        _ => (width, height),
+    }
+}
+
+/// An image whose samples are ready to be written as an image XObject stream.
+struct EncodedImage {
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+    bits_per_component: u8,
+    /// The PDF filter name the `data` is encoded with, if any.
+    filter: Option<&'static str>,
+    data: Vec<u8>,
+    /// The alpha channel, written as an `/SMask`.
+    mask: Option<SoftMask>,
+    /// The horizontal and vertical resolution stored in the image, in dots per inch.
+    dpi: Option<(f32, f32)>,
+    /// The width of a pixel over its height, for placing images without a
+    /// resolution of their own.
+    pixel_aspect: f32,
+    /// The size in pixels before the image was downscaled, which the page is
+    /// sized for.
+    resampled_from: Option<(u32, u32)>,
+    /// The colour profile of the source image, written as an `/ICCBased`
+    /// colour space.
+    icc_profile: Option<IccProfile>,
+    /// The `/Decode` array, when the samples do not map to the colour space
+    /// the usual way.
+    decode: Option<&'static str>,
+    /// Whether the rows of `data` were filtered with the PNG predictors
+    /// before compression.
+    predicted: bool,
+    /// A small copy of the image, written as the `/Thumb` of its page.
+    thumbnail: Option<Thumbnail>,
+}
+
+/// A downscaled copy of an image, ready to be written as a DeviceRGB
+/// thumbnail image.
+struct Thumbnail {
+    width: u32,
+    height: u32,
+    filter: Option<&'static str>,
+    data: Vec<u8>,
+}
+
+/// An ICC profile, ready to be written as an `/ICCBased` stream.
+struct IccProfile {
+    /// The number of colour components the profile describes, its `/N`.
+    components: u8,
+    filter: Option<&'static str>,
+    data: Vec<u8>,
+}
+
+/// The alpha channel of an image, ready to be written as a DeviceGray `/SMask`.
+struct SoftMask {
+    width: u32,
+    height: u32,
+    bits_per_component: u8,
+    filter: Option<&'static str>,
+    data: Vec<u8>,
+    /// Whether the rows of `data` were filtered with the PNG predictors.
+    predicted: bool,
+}
+
+/// Returns the ` /Filter` entry for a stream dictionary, or nothing for an
+/// unfiltered stream.
+fn filter_entry(filter: Option<&str>) -> String {
+    match filter {
+        Some(filter) => format!(" /Filter {}", filter),
+        None => String::new(),
+    }
+}
+
+/// Returns the ` /DecodeParms` entry undoing the PNG predictors for samples
+/// of `colors` components of `bits` bits in rows of `columns` pixels, or
+/// nothing when the rows were not `predicted`.
+fn decode_parms_entry(predicted: bool, colors: u8, bits: u8, columns: u32) -> String {
+    if !predicted {
+        return String::new();
+    }
+    format!(
+        " /DecodeParms << /Predictor 15 /Colors {} /BitsPerComponent {} /Columns {} >>",
+        colors, bits, columns
+    )
+}
+
+/// Compresses raw image samples with zlib, or leaves them as they are when
+/// the conversion is uncompressed.
+///
+/// # Returns
+///
+/// A tuple containing the filter to decode the data with, if any, and the data.
+fn compress_samples(
+    samples: Vec<u8>,
+    options: &Options,
+) -> io::Result<(Option<&'static str>, Vec<u8>)> {
+    if options.uncompressed {
+        return Ok((None, samples));
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(options.compression));
+    encoder.write_all(&samples)?;
+    let data = encoder.finish()?;
+    debug!("compressed {} bytes to {}", samples.len(), data.len());
+    Ok((Some("/FlateDecode"), data))
+}
+
+/// Encodes an image for embedding in a PDF.
+///
+/// 8-bit grayscale, RGB and CMYK JPEGs are embedded as-is with
+/// `/DCTDecode`; everything else is decoded and recompressed with zlib, or
+/// written raw when the conversion is uncompressed.
+///
+/// CMYK is only preserved for JPEGs embedded as-is and for 8-bit CMYK TIFFs.
+/// The `image` crate converts other CMYK inputs to RGB while decoding, so
+/// they are written as DeviceRGB. CMYK JPEGs from Adobe applications, which
+/// store inverted values, get a `/Decode` array that inverts them back.
+///
+/// # Arguments
+///
+/// * `img_data` - A slice of bytes representing the image data.
+/// * `options` - The conversion settings.
+///
+/// # Returns
+///
+/// A `Result` containing the `EncodedImage` on success, or an `Img2PdfError` on failure.
+fn encode_image(img_data: &[u8], options: &Options) -> Result<EncodedImage> {
+    check_decoder_enabled(img_data)?;
+    debug!(
+        "detected format {:?} in {} bytes",
+        image::guess_format(img_data).ok(),
+        img_data.len()
+    );
+    let profile = read_icc_profile(img_data);
+    let mut image = encode_samples(img_data, options, detects_gray(profile.as_deref()))?;
+    if let Some(profile) = profile {
+        attach_icc_profile(&mut image, profile, options)?;
+    }
+    if options.pdfa && image.color_space == ColorSpace::DeviceCMYK && image.icc_profile.is_none() {
+        // The sRGB output intent cannot stand for CMYK.
+        return Err(Img2PdfError::Unsupported(
+            "PDF/A-1b needs a version 2 ICC profile for CMYK images".to_string(),
+        ));
+    }
+    Ok(image)
+}
+
+/// Whether images with the ICC `profile` may be written as gray. An RGB
+/// profile does not describe gray samples, so images that have one stay RGB
+/// even when every pixel is gray.
+fn detects_gray(profile: Option<&[u8]>) -> bool {
+    profile.and_then(icc_components) != Some(3)
+}
+
+/// Embeds the ICC `profile` of an image file with `image`, if it describes
+/// the samples and the PDF can read it.
+fn attach_icc_profile(image: &mut EncodedImage, profile: Vec<u8>, options: &Options) -> Result<()> {
+    let components = icc_components(&profile);
+    // PDF 1.4, and so PDF/A-1, reads version 2 profiles only.
+    let readable = !options.pdfa || profile.get(8).is_some_and(|&major| major <= 2);
+    if components == Some(image.color_space.components()) && readable {
+        debug!("embedding ICC profile of {} bytes", profile.len());
+        let (filter, data) = compress_samples(profile, options)?;
+        image.icc_profile = Some(IccProfile {
+            components: image.color_space.components(),
+            filter,
+            data,
+        });
+    } else {
+        debug!(
+            "ignoring ICC profile for {:?} components, which cannot describe the {} samples",
+            components,
+            image.color_space.device_space()
+        );
+    }
+    Ok(())
+}
+
+/// The most pixels images may have by default, 100 megapixels.
+const DEFAULT_MAX_PIXELS: u64 = 100_000_000;
+
+/// Rejects images with more pixels than the options allow, reading their
+/// dimensions from the header only. Images whose header cannot be read are
+/// left for the decoder to report.
+fn check_pixel_count(img_data: &[u8], options: &Options) -> Result<()> {
+    let dimensions = image::io::Reader::new(io::Cursor::new(img_data))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok());
+    match dimensions {
+        Some((width, height)) => check_pixels(width, height, options),
+        None => Ok(()),
+    }
+}
+
+/// Decodes an image, with the limits of the `image` crate set from the
+/// options: no side longer than the pixel limit, and no more memory than
+/// that many pixels of the widest kind take.
+///
+/// The limits are checked once the decoder has read the dimensions, before
+/// the pixels are allocated. They cannot bound the number of pixels itself,
+/// which [`check_pixel_count`] does from the header.
+fn decode_image(img_data: &[u8], options: &Options) -> Result<DynamicImage> {
+    let mut reader = image::io::Reader::new(io::Cursor::new(img_data)).with_guessed_format()?;
+    let mut limits = image::io::Limits::default();
+    let max_side = u32::try_from(options.max_pixels).unwrap_or(u32::MAX);
+    limits.max_image_width = Some(max_side);
+    limits.max_image_height = Some(max_side);
+    limits.max_alloc = Some(options.max_pixels.saturating_mul(MAX_BYTES_PER_PIXEL));
+    reader.limits(limits);
+    Ok(reader.decode()?)
+}
+
+/// The bytes of the widest pixels the `image` crate decodes to, four
+/// channels of `f32`.
+const MAX_BYTES_PER_PIXEL: u64 = 16;
+
+/// Rejects a `width` × `height` image with more pixels than the options
+/// allow.
+fn check_pixels(width: u32, height: u32, options: &Options) -> Result<()> {
+    if width as u64 * height as u64 > options.max_pixels {
+        return Err(Img2PdfError::Unsupported(format!(
+            "image of {}x{} pixels exceeds the limit of {} pixels",
+            width, height, options.max_pixels
+        )));
+    }
+    Ok(())
+}
+
+/// Rejects images in a format whose decoder is behind a feature of this crate
+/// that is not enabled, naming the feature, instead of failing to decode them.
+fn check_decoder_enabled(img_data: &[u8]) -> Result<()> {
+    let (format, feature, enabled) = match image::guess_format(img_data) {
+        Ok(ImageFormat::WebP) => ("WebP", "webp", cfg!(feature = "webp")),
+        Ok(ImageFormat::Avif) => ("AVIF", "avif", cfg!(feature = "avif")),
+        _ => return Ok(()),
+    };
+    if enabled {
+        Ok(())
+    } else {
+        Err(Img2PdfError::Unsupported(format!(
+            "format {} not supported; enable feature `{}`",
+            format, feature
+        )))
+    }
+}
+
+/// Encodes the samples of an image, without its colour profile.
+///
+/// Gray detection is only done when `detect_gray` is set.
+fn encode_samples(img_data: &[u8], options: &Options, detect_gray: bool) -> Result<EncodedImage> {
+    let orientation = if options.orients_pixels() {
+        read_orientation(img_data).unwrap_or(1)
+    } else {
+        1
+    };
+
+    let jpeg_quality = options.jpeg_quality.filter(|_| !options.uncompressed);
+
+    let fits = |image: &EncodedImage| {
+        let dpi = resolution(image.dpi, image.pixel_aspect, options);
+        let (max_width, max_height) = size_limit(image.width, image.height, dpi, options);
+        image.width <= max_width && image.height <= max_height
+    };
+
+    // Rotated JPEGs have to be decoded so the pixels can be turned upright,
+    // and oversized ones so they can be downscaled.
+    if let Ok(ImageFormat::Jpeg) = image::guess_format(img_data) {
+        let reason = if options.uncompressed {
+            "the conversion is uncompressed"
+        } else if orientation != 1 {
+            "its EXIF orientation is applied"
+        } else if jpeg_quality.is_some() {
+            "it is re-encoded at the set quality"
+        } else {
+            match jpeg_passthrough(img_data) {
+                Some(mut image) if fits(&image) => {
+                    if options.strip_metadata {
+                        image.data = strip_jpeg_metadata(&image.data);
+                    }
+                    debug!(
+                        "embedding {}x{} JPEG as it is with /DCTDecode",
+                        image.width, image.height
+                    );
+                    if options.thumbnails {
+                        image.thumbnail = encode_thumbnail(img_data, options)?;
+                    }
+                    return Ok(image);
+                }
+                Some(_) => "it has more pixels than the size limits allow",
+                None => "its precision or number of components is not supported",
+            }
+        };
+        debug!("decoding JPEG instead of embedding it: {}", reason);
+    }
+    check_pixel_count(img_data, options)?;
+
+    if let Ok(ImageFormat::Tiff) = image::guess_format(img_data) {
+        if let Some(scheme) = unsupported_tiff_compression(img_data) {
+            return Err(Img2PdfError::Unsupported(format!(
+                "TIFF compression {} is not supported",
+                scheme
+            )));
+        }
+        if let Some(mut decoder) = cmyk_tiff_decoder(img_data) {
+            let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+            let dpi = read_dpi(img_data);
+            let (max_width, max_height) =
+                size_limit(width, height, resolution(dpi, 1.0, options), options);
+            let (width, height, filter, data, predicted, resampled_from) =
+                if orientation == 1 && width <= max_width && height <= max_height {
+                    // Upright pages that fit are streamed to the compressor band by
+                    // band, without decoding the whole page.
+                    let mut samples = SampleWriter::for_rows(options, 4, 8, width);
+                    read_tiff_bands::<u8>(&mut decoder, 4, |band| Ok(samples.write_all(band)?))?;
+                    let predicted = samples.predicts();
+                    let (filter, data) = samples.finish()?;
+                    (width, height, filter, data, predicted, None)
+                } else {
+                    // CMYK has four channels, so the samples can be reoriented and
+                    // resized as RGBA.
+                    let samples = u8::from_tiff(decoder.read_image().map_err(tiff_error)?);
+                    let Some(pixels) = samples
+                        .and_then(|samples| image::RgbaImage::from_raw(width, height, samples))
+                    else {
+                        return Err(Img2PdfError::Unsupported("truncated CMYK TIFF".to_string()));
+                    };
+                    let pixels = apply_orientation(DynamicImage::ImageRgba8(pixels), orientation);
+                    let (pixels, resampled_from) =
+                        limit_size(pixels, resolution(dpi, 1.0, options), options);
+                    let (width, height) = pixels.dimensions();
+                    let (filter, data) = compress_samples(pixels.into_bytes(), options)?;
+                    (width, height, filter, data, false, resampled_from)
+                };
+            return Ok(EncodedImage {
+                width,
+                height,
+                color_space: ColorSpace::DeviceCMYK,
+                bits_per_component: 8,
+                filter,
+                data,
+                mask: None,
+                dpi,
+                pixel_aspect: 1.0,
+                resampled_from,
+                icc_profile: None,
+                decode: None,
+                predicted,
+                thumbnail: None,
+            });
+        }
+    }
+
+    if let (Ok(ImageFormat::Png), None) = (image::guess_format(img_data), jpeg_quality) {
+        if let Some(mut image) = read_indexed_png(img_data).filter(fits) {
+            (image.filter, image.data) =
+                compress_samples(std::mem::take(&mut image.data), options)?;
+            if options.thumbnails {
+                image.thumbnail = encode_thumbnail(img_data, options)?;
+            }
+            return Ok(image);
+        }
+    }
+
+    let started = HAS_CLOCK.then(Instant::now);
+    let img = apply_orientation(decode_image(img_data, options)?, orientation);
+    debug!(
+        "decoded {}x{} {:?} image in {:?}",
+        img.width(),
+        img.height(),
+        img.color(),
+        started.map(|started| started.elapsed()).unwrap_or_default()
+    );
+    let (dpi, pixel_aspect) = oriented_resolution(img_data, orientation);
+    encode_decoded(img, dpi, pixel_aspect, options, detect_gray)
+}
+
+/// Reads the resolution and pixel aspect ratio of an image file, for its
+/// pixels turned upright by `orientation`.
+fn oriented_resolution(img_data: &[u8], orientation: u16) -> (Option<(f32, f32)>, f32) {
+    // Orientations 5 to 8 swap the axes the resolution was given for.
+    let dpi = read_dpi(img_data).map(|(x, y)| if orientation >= 5 { (y, x) } else { (x, y) });
+    let pixel_aspect = match read_pixel_aspect(img_data) {
+        aspect if orientation >= 5 => 1.0 / aspect,
+        aspect => aspect,
+    };
+    (dpi, pixel_aspect)
+}
+
+/// Encodes decoded pixels as gray or RGB samples, with an alpha channel if
+/// the image has transparency, or as a JPEG when re-encoding is enabled.
+///
+/// Gray detection is only done when `detect_gray` is set.
+fn encode_pixels(img: &DynamicImage, options: &Options, detect_gray: bool) -> Result<EncodedImage> {
+    let unpremultiplied;
+    let img = if options.alpha_mode == AlphaMode::Premultiplied && img.color().has_alpha() {
+        unpremultiplied = unpremultiply(img);
+        &unpremultiplied
+    } else {
+        img
+    };
+    let jpeg_quality = options.jpeg_quality.filter(|_| !options.uncompressed);
+    let (width, height) = img.dimensions();
+
+    // 16-bit inputs keep their full depth, unless re-encoded as JPEG; everything
+    // else is written as 8-bit.
+    // PDF 1.4, and so PDF/A-1, has no 16-bit samples.
+    let sixteen_bit = matches!(
+        img.color(),
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16
+    ) && jpeg_quality.is_none()
+        && !options.pdfa;
+    let summary = if sixteen_bit {
+        summarize_pixels::<u16>(img, options.flatten_background())?
+    } else {
+        summarize_pixels::<u8>(img, options.flatten_background())?
+    };
+    let gray = detect_gray && summary.gray;
+    let color_space = if gray {
+        ColorSpace::DeviceGray
+    } else {
+        ColorSpace::DeviceRGB
+    };
+    // A fully opaque alpha channel adds nothing, so no `/SMask` is needed. A
+    // colour key stands in for the alpha channel.
+    let has_alpha =
+        options.flatten_background().is_none() && !summary.opaque && options.color_key.is_none();
+
+    if let Some(quality) = jpeg_quality {
+        if has_alpha {
+            return Err(Img2PdfError::Unsupported(
+                "JPEG has no alpha channel; flatten transparent images to re-encode them"
+                    .to_string(),
+            ));
+        }
+        let color_type = if gray { ColorType::L8 } else { ColorType::Rgb8 };
+        let mut color_img = Vec::new();
+        write_samples::<u8>(
+            img,
+            options.flatten_background(),
+            gray,
+            false,
+            &mut color_img,
+            None,
+        )?;
+        let mut data = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, quality)
+            .encode(&color_img, width, height, color_type)?;
+        return Ok(EncodedImage {
+            width,
+            height,
+            color_space,
+            bits_per_component: 8,
+            filter: Some("/DCTDecode"),
+            data,
+            mask: None,
+            dpi: None,
+            pixel_aspect: 1.0,
+            resampled_from: None,
+            icc_profile: None,
+            decode: None,
+            predicted: false,
+            thumbnail: None,
+        });
+    }
+
+    // Pure black-and-white images only need one bit per pixel.
+    let bilevel = !sixteen_bit && gray && summary.bilevel;
+    // A mask that hardly varies loses little at half resolution; readers
+    // stretch it over the image.
+    let half_mask = has_alpha
+        && options
+            .mask_downsampling
+            .is_some_and(|max_variance| summary.alpha_variance <= max_variance as f64);
+    let bits_per_component = if bilevel {
+        1
+    } else if sixteen_bit {
+        16
+    } else {
+        8
+    };
+    let mask_bits = if sixteen_bit { 16 } else { 8 };
+    let mask_width = if half_mask { width.div_ceil(2) } else { width };
+    let mut color =
+        SampleWriter::for_rows(options, color_space.components(), bits_per_component, width);
+    let mut alpha = has_alpha.then(|| SampleWriter::for_rows(options, 1, mask_bits, mask_width));
+    let full_alpha = alpha.as_mut().filter(|_| !half_mask);
+    if sixteen_bit {
+        write_samples::<u16>(
+            img,
+            options.flatten_background(),
+            gray,
+            false,
+            &mut color,
+            full_alpha,
+        )?;
+    } else {
+        write_samples::<u8>(
+            img,
+            options.flatten_background(),
+            gray,
+            bilevel,
+            &mut color,
+            full_alpha,
+        )?;
+    }
+    if let Some(alpha) = alpha.as_mut().filter(|_| half_mask) {
+        if sixteen_bit {
+            write_half_alpha::<u16>(img, alpha)?;
+        } else {
+            write_half_alpha::<u8>(img, alpha)?;
+        }
+    }
+
+    let mask = match alpha {
+        Some(alpha) => {
+            let predicted = alpha.predicts();
+            let (filter, data) = alpha.finish()?;
+            let height = if half_mask {
+                height.div_ceil(2)
+            } else {
+                height
+            };
+            Some(SoftMask {
+                width: mask_width,
+                height,
+                bits_per_component: mask_bits,
+                filter,
+                data,
+                predicted,
+            })
+        }
+        None => None,
+    };
+    let predicted = color.predicts();
+    let (filter, color_data) = color.finish()?;
+    debug!(
+        "writing {} samples at {} bits per component{}: {} bytes, {} with {:?}",
+        color_space.device_space(),
+        bits_per_component,
+        if mask.is_some() {
+            " with a soft mask"
+        } else {
+            ""
+        },
+        (width as usize * color_space.components() as usize * bits_per_component as usize)
+            .div_ceil(8)
+            * height as usize,
+        color_data.len(),
+        filter
+    );
+
+    Ok(EncodedImage {
+        width,
+        height,
+        color_space,
+        bits_per_component,
+        filter,
+        data: color_data,
+        mask,
+        dpi: None,
+        pixel_aspect: 1.0,
+        resampled_from: None,
+        icc_profile: None,
+        decode: None,
+        predicted,
+        thumbnail: None,
+    })
+}
+
+/// Image samples being written out, compressed with zlib or kept raw when the
+/// conversion is uncompressed.
+enum SampleWriter {
+    Raw(Vec<u8>),
+    Zlib(ZlibEncoder<Vec<u8>>),
+    /// Compressed with zlib after filtering each row with a PNG predictor.
+    Predicted(PredictedRows),
+}
+
+/// Rows of samples being filtered with the PNG predictors, each with the one
+/// that leaves the smallest residuals, and compressed.
+struct PredictedRows {
+    encoder: ZlibEncoder<Vec<u8>>,
+    /// The bytes of a whole row, and of a pixel, at least one.
+    row_len: usize,
+    pixel_len: usize,
+    row: Vec<u8>,
+    previous: Vec<u8>,
+    /// The predictor byte and residuals of the row being tried, and of the
+    /// best so far.
+    filtered: Vec<u8>,
+    best: Vec<u8>,
+}
+
+impl PredictedRows {
+    /// Filters and compresses the buffered row, which must be complete.
+    fn write_row(&mut self) -> io::Result<()> {
+        let mut best_cost = u64::MAX;
+        for predictor in 0..5u8 {
+            self.filtered.clear();
+            self.filtered.push(predictor);
+            for (i, &byte) in self.row.iter().enumerate() {
+                let j = i.checked_sub(self.pixel_len);
+                let left = j.map_or(0, |j| self.row[j]);
+                let up = self.previous[i];
+                let prediction = match predictor {
+                    0 => 0,
+                    1 => left,
+                    2 => up,
+                    3 => ((left as u16 + up as u16) / 2) as u8,
+                    _ => paeth(left, up, j.map_or(0, |j| self.previous[j])),
+                };
+                self.filtered.push(byte.wrapping_sub(prediction));
+            }
+            // Residuals close to zero, either way, compress best.
+            let cost = self.filtered[1..]
+                .iter()
+                .map(|&residual| (residual as i8).unsigned_abs() as u64)
+                .sum();
+            if cost < best_cost {
+                best_cost = cost;
+                std::mem::swap(&mut self.filtered, &mut self.best);
+            }
+        }
+        self.encoder.write_all(&self.best)?;
+        std::mem::swap(&mut self.row, &mut self.previous);
+        self.row.clear();
+        Ok(())
+    }
+}
+
+/// The Paeth predictor of PNG: whichever of the left, upper and upper-left
+/// bytes is closest to their linear estimate `left + up - up_left`.
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (a, b, c) = (
+        (estimate - left as i16).abs(),
+        (estimate - up as i16).abs(),
+        (estimate - up_left as i16).abs(),
+    );
+    if a <= b && a <= c {
+        left
+    } else if b <= c {
+        up
+    } else {
+        up_left
+    }
+}
+
+impl SampleWriter {
+    fn new(options: &Options) -> Self {
+        if options.uncompressed {
+            SampleWriter::Raw(Vec::new())
+        } else {
+            SampleWriter::Zlib(ZlibEncoder::new(
+                Vec::new(),
+                Compression::new(options.compression),
+            ))
+        }
+    }
+
+    /// Like [`new`](Self::new), but filters the rows with the PNG predictors
+    /// when the options ask for it. Rows are `columns` pixels of `colors`
+    /// components of `bits` bits.
+    fn for_rows(options: &Options, colors: u8, bits: u8, columns: u32) -> Self {
+        if !options.png_predictor || options.uncompressed {
+            return SampleWriter::new(options);
+        }
+        let row_len = (columns as usize * colors as usize * bits as usize).div_ceil(8);
+        SampleWriter::Predicted(PredictedRows {
+            encoder: ZlibEncoder::new(Vec::new(), Compression::new(options.compression)),
+            row_len,
+            pixel_len: (colors as usize * bits as usize).div_ceil(8),
+            row: Vec::with_capacity(row_len),
+            previous: vec![0; row_len],
+            filtered: Vec::with_capacity(row_len + 1),
+            best: Vec::with_capacity(row_len + 1),
+        })
+    }
+
+    /// Whether the rows are filtered with the PNG predictors.
+    fn predicts(&self) -> bool {
+        matches!(self, SampleWriter::Predicted(_))
+    }
+
+    /// Returns the filter to decode the data with, if any, and the data.
+    fn finish(self) -> io::Result<(Option<&'static str>, Vec<u8>)> {
+        match self {
+            SampleWriter::Raw(data) => Ok((None, data)),
+            SampleWriter::Zlib(encoder) => Ok((Some("/FlateDecode"), encoder.finish()?)),
+            SampleWriter::Predicted(mut rows) => {
+                if !rows.row.is_empty() {
+                    // A partial last row is padded, as readers would.
+                    rows.row.resize(rows.row_len, 0);
+                    rows.write_row()?;
+                }
+                Ok((Some("/FlateDecode"), rows.encoder.finish()?))
+            }
+        }
+    }
+}
+
+impl Write for SampleWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SampleWriter::Raw(data) => data.write(buf),
+            SampleWriter::Zlib(encoder) => encoder.write(buf),
+            SampleWriter::Predicted(rows) => {
+                let mut rest = buf;
+                while !rest.is_empty() {
+                    let take = rest.len().min(rows.row_len - rows.row.len());
+                    rows.row.extend_from_slice(&rest[..take]);
+                    rest = &rest[take..];
+                    if rows.row.len() == rows.row_len {
+                        rows.write_row()?;
+                    }
+                }
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SampleWriter::Raw(data) => data.flush(),
+            SampleWriter::Zlib(encoder) => encoder.flush(),
+            SampleWriter::Predicted(rows) => rows.encoder.flush(),
+        }
+    }
+}
+
+/// Packs 8-bit gray samples that are all either 0 or 255 into 1-bit samples.
+///
+/// Each row starts on a byte boundary, as PDF requires, so the last byte of a
+/// row is padded with zero bits when the width is not a multiple of 8.
+fn pack_bilevel(samples: &[u8], width: u32) -> Vec<u8> {
+    let width = width.max(1) as usize;
+    let mut packed = Vec::with_capacity(width.div_ceil(8) * (samples.len() / width));
+    for row in samples.chunks_exact(width) {
+        for pixels in row.chunks(8) {
+            let byte = pixels
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, &sample)| byte | (sample & 0x80) >> i);
+            packed.push(byte);
+        }
+    }
+    packed
+}
+
+/// Returns the most pixels across and down the options allow for an image
+/// of `width` × `height` pixels at resolution `dpi`: the maximum dimension,
+/// and enough for the target resolution at the size the image is placed at.
+fn size_limit(width: u32, height: u32, dpi: (f32, f32), options: &Options) -> (u32, u32) {
+    let mut limit = options
+        .max_dimension
+        .map_or((u32::MAX, u32::MAX), |max| (max, max));
+    if let Some(target_dpi) = options.target_dpi {
+        let (dpi_x, dpi_y) = dpi;
+        let placement = place_image(
+            width as f32 * 72.0 / dpi_x,
+            height as f32 * 72.0 / dpi_y,
+            options,
+        );
+        let pixels = |points: f32| ((points / 72.0 * target_dpi).ceil() as u32).max(1);
+        limit.0 = limit.0.min(pixels(placement.width));
+        limit.1 = limit.1.min(pixels(placement.height));
+    }
+    limit
+}
+
+/// Downscales `img`, placed at resolution `dpi`, with a Lanczos filter so it
+/// fits the [`size_limit`] of the options, keeping its aspect ratio.
+///
+/// # Returns
+///
+/// The image, and its original size if it was downscaled.
+fn limit_size(
+    img: DynamicImage,
+    dpi: (f32, f32),
+    options: &Options,
+) -> (DynamicImage, Option<(u32, u32)>) {
+    let (width, height) = img.dimensions();
+    let (max_width, max_height) = size_limit(width, height, dpi, options);
+    if width <= max_width && height <= max_height {
+        return (img, None);
+    }
+    (
+        img.resize(max_width, max_height, image::imageops::FilterType::Lanczos3),
+        Some((width, height)),
+    )
+}
+
+/// Rotates and flips an image as described by an EXIF orientation value, so
+/// that it displays upright. Unknown values leave the image unchanged.
+fn apply_orientation(img: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// Reads the EXIF orientation (tag `0x0112`) of a JPEG, from its `APP1`
+/// segment, or of a TIFF, from its first IFD.
+fn read_orientation(img_data: &[u8]) -> Option<u16> {
+    match image::guess_format(img_data).ok()? {
+        ImageFormat::Jpeg => {
+            let (_, app1) = jpeg_segments(img_data)?
+                .into_iter()
+                .find(|(marker, payload)| *marker == 0xE1 && payload.starts_with(b"Exif\0\0"))?;
+            tiff_orientation(&app1[6..])
+        }
+        ImageFormat::Tiff => tiff_orientation(img_data),
+        _ => None,
+    }
+}
+
+/// Finds the orientation tag in the first IFD of a TIFF structure, which is
+/// also how EXIF data is laid out.
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let read_u16 = |pos: usize| -> Option<u16> {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?];
+        match tiff.get(..2)? {
+            b"II" => Some(u16::from_le_bytes(bytes)),
+            b"MM" => Some(u16::from_be_bytes(bytes)),
+            _ => None,
+        }
+    };
+    let read_u32 = |pos: usize| -> Option<u32> {
+        let (high, low) = (read_u16(pos)? as u32, read_u16(pos + 2)? as u32);
+        match tiff.get(..2)? {
+            b"II" => Some(low << 16 | high),
+            _ => Some(high << 16 | low),
+        }
+    };
+
+    if read_u16(2)? != 42 {
+        return None;
+    }
+    let ifd = read_u32(4)? as usize;
+    let count = read_u16(ifd)? as usize;
+    (0..count)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| read_u16(entry) == Some(0x0112))
+        .and_then(|entry| read_u16(entry + 8))
+}
+
+/// Reads the palette and raw index samples of a palette PNG.
+///
+/// The `image` crate expands palettes to RGB when decoding, so palette PNGs
+/// are decoded with `png` directly to keep one index per pixel. PNG rows are
+/// byte-aligned just like PDF's, so samples of any bit depth can be used
+/// as-is. Returns `None` for any other kind of PNG, and for palettes with
+/// transparency, which need an alpha channel.
+///
+/// The returned samples are not compressed yet.
+fn read_indexed_png(img_data: &[u8]) -> Option<EncodedImage> {
+    let mut decoder = png::Decoder::new(img_data);
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info().ok()?;
+    let info = reader.info();
+    if info.color_type != png::ColorType::Indexed || info.trns.is_some() {
+        return None;
+    }
+    let palette = info.palette.as_ref()?.to_vec();
+    if palette.is_empty() {
+        return None;
+    }
+
+    let mut samples = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut samples).ok()?;
+    samples.truncate(frame.buffer_size());
+    Some(EncodedImage {
+        width: frame.width,
+        height: frame.height,
+        color_space: ColorSpace::Indexed(palette),
+        bits_per_component: frame.bit_depth as u8,
+        filter: None,
+        data: samples,
+        mask: None,
+        dpi: read_dpi(img_data),
+        pixel_aspect: read_pixel_aspect(img_data),
+        resampled_from: None,
+        icc_profile: None,
+        decode: None,
+        predicted: false,
+        thumbnail: None,
+    })
+}
+
+/// Opens an 8-bit CMYK TIFF for reading its raw samples.
+///
+/// The `image` crate converts CMYK to RGB when decoding, which shifts colours
+/// and loses the separations, so CMYK TIFFs are decoded with `tiff` directly.
+/// Returns `None` for any other kind of TIFF.
+fn cmyk_tiff_decoder(img_data: &[u8]) -> Option<tiff::decoder::Decoder<io::Cursor<&[u8]>>> {
+    let mut decoder = tiff::decoder::Decoder::new(io::Cursor::new(img_data)).ok()?;
+    (decoder.colortype().ok()? == tiff::ColorType::CMYK(8)).then_some(decoder)
+}
+
+/// Names the compression scheme of the first image of a TIFF, with its tag
+/// value, if the `tiff` decoder cannot decompress it.
+///
+/// Striped and tiled TIFFs decode alike; only the compression decides
+/// whether a TIFF can be read, and naming it beats a generic decoding error.
+fn unsupported_tiff_compression(img_data: &[u8]) -> Option<String> {
+    let mut decoder = tiff::decoder::Decoder::new(io::Cursor::new(img_data)).ok()?;
+    let code: u16 = decoder
+        .find_tag_unsigned(tiff::tags::Tag::Compression)
+        .ok()??;
+    let name = match code {
+        // No compression, LZW, baseline JPEG, Deflate and PackBits.
+        1 | 5 | 7 | 8 | 32946 | 32773 => return None,
+        2 => "CCITT modified Huffman RLE",
+        3 => "CCITT Group 3 fax",
+        4 => "CCITT Group 4 fax",
+        6 => "old-style JPEG",
+        34712 => "JPEG 2000",
+        34887 => "LERC",
+        34925 => "LZMA",
+        50000 => "Zstandard",
+        50001 => "WebP",
+        _ => "unknown",
+    };
+    Some(format!("{} ({})", name, code))
+}
+
+/// Wraps the original JPEG bytes for `/DCTDecode` embedding, if the JPEG is
+/// one a PDF reader can decode directly with the colour space we can describe.
+///
+/// The bytes are embedded unchanged, so `APPn` segments such as EXIF metadata
+/// stay readable from the image stream, unless they are stripped afterwards.
+fn jpeg_passthrough(img_data: &[u8]) -> Option<EncodedImage> {
+    let info = read_jpeg_info(img_data)?;
+    // A height of 0 means it is only given after the first scan, in a `DNL`
+    // marker, which is left to the decoder.
+    if info.precision != 8 || info.width == 0 || info.height == 0 {
+        return None;
+    }
+    let color_space = match info.components {
+        1 => ColorSpace::DeviceGray,
+        3 => ColorSpace::DeviceRGB,
+        4 => ColorSpace::DeviceCMYK,
+        _ => return None,
+    };
+    // Adobe applications write CMYK JPEGs with every component inverted,
+    // which they mark with an `APP14` segment, whatever its transform.
+    let decode = (info.components == 4 && info.adobe).then_some("[1 0 1 0 1 0 1 0]");
+    if decode.is_some() {
+        debug!("inverting Adobe CMYK JPEG with /Decode");
+    }
+
+    Some(EncodedImage {
+        width: info.width,
+        height: info.height,
+        color_space,
+        bits_per_component: 8,
+        filter: Some("/DCTDecode"),
+        data: img_data.to_vec(),
+        mask: None,
+        dpi: read_dpi(img_data),
+        pixel_aspect: read_pixel_aspect(img_data),
+        resampled_from: None,
+        icc_profile: None,
+        decode,
+        predicted: false,
+        thumbnail: None,
+    })
+}
+
+/// Rebuilds a JPEG without its metadata: the `APPn` segments other than the
+/// JFIF header and the Adobe `APP14` segment, and the comments. The JFIF
+/// thumbnail is dropped too. The JPEG must have a valid marker structure,
+/// which [`jpeg_passthrough`] checks.
+fn strip_jpeg_metadata(data: &[u8]) -> Vec<u8> {
+    let Some(segments) = jpeg_segments(data) else {
+        return data.to_vec();
+    };
+    let mut stripped = Vec::with_capacity(data.len());
+    stripped.extend_from_slice(&[0xFF, 0xD8]);
+    let mut scan_start = data.len();
+    for (marker, payload) in segments {
+        if marker == 0xDA {
+            // The scan header is the last segment, and the entropy-coded
+            // data follows it.
+            scan_start = payload.as_ptr() as usize - data.as_ptr() as usize + payload.len();
+        }
+        let payload: Cow<[u8]> = match marker {
+            0xE0 if payload.starts_with(b"JFIF\0") && payload.len() >= 14 => {
+                Cow::Owned([&payload[..12], &[0, 0]].concat())
+            }
+            0xEE if payload.starts_with(b"Adobe") => Cow::Borrowed(payload),
+            0xE0..=0xEF | 0xFE => {
+                debug!(
+                    "stripping {} byte JPEG segment 0x{:02X}",
+                    payload.len(),
+                    marker
+                );
+                continue;
+            }
+            _ => Cow::Borrowed(payload),
+        };
+        stripped.extend_from_slice(&[0xFF, marker]);
+        stripped.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        stripped.extend_from_slice(&payload);
+    }
+    stripped.extend_from_slice(&data[scan_start..]);
+    stripped
+}
+
+/// The frame parameters of a JPEG, read from its SOF marker.
+struct JpegInfo {
+    width: u32,
+    height: u32,
+    precision: u8,
+    components: u8,
+    /// Whether the JPEG has an Adobe `APP14` segment.
+    adobe: bool,
+}
+
+/// Returns the marker segments of a JPEG as `(marker, payload)` pairs, up to
+/// the start of the scan data. Returns `None` if the marker structure is malformed.
+fn jpeg_segments(data: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        match marker {
+            // Fill bytes may pad a marker.
+            0xFF => pos += 1,
+            // Standalone markers carry no length.
+            0x01 | 0xD0..=0xD7 => pos += 2,
+            _ => {
+                let length =
+                    u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+                let payload = data.get(pos + 4..pos + 2 + length)?;
+                segments.push((marker, payload));
+                if marker == 0xDA {
+                    return Some(segments);
+                }
+                pos += 2 + length;
+            }
+        }
+    }
+}
+
+/// Reads the frame header of a JPEG without decoding it.
+fn read_jpeg_info(data: &[u8]) -> Option<JpegInfo> {
+    let segments = jpeg_segments(data)?;
+    let adobe = segments
+        .iter()
+        .any(|(marker, payload)| *marker == 0xEE && payload.starts_with(b"Adobe"));
+    let (_, sof) = segments.into_iter().find(|(marker, _)| {
+        matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC)
+    })?;
+    if sof.len() < 6 {
+        return None;
+    }
+
+    Some(JpegInfo {
+        precision: sof[0],
+        height: u16::from_be_bytes([sof[1], sof[2]]) as u32,
+        width: u16::from_be_bytes([sof[3], sof[4]]) as u32,
+        components: sof[5],
+        adobe,
+    })
+}
+
+/// Returns the chunks of a PNG as `(type, data)` pairs, up to the first
+/// `IDAT`. Returns `None` if the chunk structure is malformed.
+fn png_chunks(data: &[u8]) -> Option<Vec<([u8; 4], &[u8])>> {
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return None;
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[pos..pos + 4].try_into().ok()?) as usize;
+        let chunk_type: [u8; 4] = data[pos + 4..pos + 8].try_into().ok()?;
+        if &chunk_type == b"IDAT" {
+            break;
+        }
+        chunks.push((chunk_type, data.get(pos + 8..pos + 8 + length)?));
+        // Skip the data and the CRC.
+        pos += 12 + length;
+    }
+    Some(chunks)
+}
+
+/// Reads the ICC profile embedded in a JPEG (split across `APP2` segments),
+/// PNG (compressed in its `iCCP` chunk) or TIFF (in the first IFD).
+fn read_icc_profile(img_data: &[u8]) -> Option<Vec<u8>> {
+    match image::guess_format(img_data).ok()? {
+        ImageFormat::Jpeg => {
+            // Each segment holds the marker, its sequence number, the segment
+            // count and a piece of the profile.
+            let mut pieces: Vec<(u8, &[u8])> = jpeg_segments(img_data)?
+                .into_iter()
+                .filter(|(marker, _)| *marker == 0xE2)
+                .filter_map(|(_, payload)| {
+                    let rest = payload.strip_prefix(b"ICC_PROFILE\0")?;
+                    Some((*rest.first()?, rest.get(2..)?))
+                })
+                .collect();
+            if pieces.is_empty() {
+                return None;
+            }
+            pieces.sort_by_key(|&(sequence, _)| sequence);
+            Some(
+                pieces
+                    .into_iter()
+                    .flat_map(|(_, piece)| piece.to_vec())
+                    .collect(),
+            )
+        }
+        ImageFormat::Png => {
+            let (_, chunk) = png_chunks(img_data)?
+                .into_iter()
+                .find(|(chunk_type, _)| chunk_type == b"iCCP")?;
+            // The profile name is followed by a null byte and the compression
+            // method, which is always zlib.
+            let name_end = chunk.iter().position(|&b| b == 0)?;
+            let compressed = chunk.get(name_end + 2..)?;
+            let mut profile = Vec::new();
+            flate2::read::ZlibDecoder::new(compressed)
+                .read_to_end(&mut profile)
+                .ok()?;
+            Some(profile)
+        }
+        ImageFormat::Tiff => {
+            let mut decoder = tiff::decoder::Decoder::new(io::Cursor::new(img_data)).ok()?;
+            tiff_page_icc_profile(&mut decoder)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the number of colour components described by an ICC profile,
+/// from the data colour space in its header. Profiles for colour spaces
+/// PDF images are not written in return `None`.
+fn icc_components(profile: &[u8]) -> Option<u8> {
+    match profile.get(16..20)? {
+        b"GRAY" => Some(1),
+        b"RGB " => Some(3),
+        b"CMYK" => Some(4),
+        _ => None,
+    }
+}
+
+/// Reads the resolution of a JPEG (from its JFIF header), PNG (from its
+/// `pHYs` chunk) or TIFF (from the first IFD), in dots per inch. Returns
+/// `None` if the image only stores an aspect ratio or no resolution at all.
+fn read_dpi(img_data: &[u8]) -> Option<(f32, f32)> {
+    let (dpi_x, dpi_y) = match image::guess_format(img_data).ok()? {
+        ImageFormat::Jpeg => {
+            let (_, app0) = jpeg_segments(img_data)?
+                .into_iter()
+                .find(|(marker, payload)| *marker == 0xE0 && payload.starts_with(b"JFIF\0"))?;
+            let units = *app0.get(7)?;
+            let x = u16::from_be_bytes([*app0.get(8)?, *app0.get(9)?]) as f32;
+            let y = u16::from_be_bytes([*app0.get(10)?, *app0.get(11)?]) as f32;
+            match units {
+                1 => (x, y),
+                2 => (x * 2.54, y * 2.54),
+                _ => return None,
+            }
+        }
+        ImageFormat::Png => {
+            let (_, phys) = png_chunks(img_data)?
+                .into_iter()
+                .find(|(chunk_type, _)| chunk_type == b"pHYs")?;
+            if phys.len() < 9 || phys[8] != 1 {
+                return None;
+            }
+            let x = u32::from_be_bytes(phys[0..4].try_into().ok()?) as f32;
+            let y = u32::from_be_bytes(phys[4..8].try_into().ok()?) as f32;
+            (x * 0.0254, y * 0.0254)
+        }
+        ImageFormat::Tiff => {
+            let (x, y, unit) = read_tiff_resolution(img_data)?;
+            match unit {
+                2 => (x, y),
+                3 => (x * 2.54, y * 2.54),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    if dpi_x > 0.0 && dpi_y > 0.0 {
+        Some((dpi_x, dpi_y))
+    } else {
+        None
+    }
+}
+
+/// Reads the resolution of the first page of a TIFF as its horizontal and
+/// vertical densities and their `ResolutionUnit`.
+fn read_tiff_resolution(img_data: &[u8]) -> Option<(f32, f32, u16)> {
+    let mut decoder = tiff::decoder::Decoder::new(io::Cursor::new(img_data)).ok()?;
+    let (x, y, unit) = tiff_page_resolution(&mut decoder)?;
+    let density = |r: tiff::encoder::Rational| (r.d != 0).then(|| r.n as f32 / r.d as f32);
+    Some((density(x)?, density(y)?, unit))
+}
+
+/// Reads the width over the height of the pixels of a JPEG, PNG or TIFF,
+/// from the ratio of its densities, or of a GIF, from its aspect ratio byte. The
+/// densities give the ratio even without a unit, when there is no
+/// resolution. Square pixels are assumed otherwise.
+fn read_pixel_aspect(img_data: &[u8]) -> f32 {
+    let ratio = || -> Option<(f32, f32)> {
+        match image::guess_format(img_data).ok()? {
+            ImageFormat::Jpeg => {
+                let (_, app0) = jpeg_segments(img_data)?
+                    .into_iter()
+                    .find(|(marker, payload)| *marker == 0xE0 && payload.starts_with(b"JFIF\0"))?;
+                let x = u16::from_be_bytes([*app0.get(8)?, *app0.get(9)?]) as f32;
+                let y = u16::from_be_bytes([*app0.get(10)?, *app0.get(11)?]) as f32;
+                Some((y, x))
+            }
+            ImageFormat::Png => {
+                let (_, phys) = png_chunks(img_data)?
+                    .into_iter()
+                    .find(|(chunk_type, _)| chunk_type == b"pHYs")?;
+                let x = u32::from_be_bytes(phys.get(0..4)?.try_into().ok()?) as f32;
+                let y = u32::from_be_bytes(phys.get(4..8)?.try_into().ok()?) as f32;
+                Some((y, x))
+            }
+            ImageFormat::Tiff => {
+                let (x, y, _) = read_tiff_resolution(img_data)?;
+                Some((y, x))
+            }
+            // The byte N of the logical screen descriptor gives the ratio
+            // (N + 15) / 64, or none when 0.
+            ImageFormat::Gif => match *img_data.get(12)? {
+                0 => None,
+                n => Some((n as f32 + 15.0, 64.0)),
+            },
+            _ => None,
+        }
+    };
+    match ratio() {
+        Some((width, height)) if width > 0.0 && height > 0.0 => width / height,
+        _ => 1.0,
+    }
+}
+
+/// The entries of the trailer dictionary, besides `/Size`.
+struct Trailer {
+    root_object_id: u32,
+    info_object_id: Option<u32>,
+    /// The two file identifiers written as `/ID`.
+    id: Option<[[u8; 16]; 2]>,
+    /// The encryption dictionary of an encrypted document.
+    encrypt_object_id: Option<u32>,
+    /// The offset of the previous cross-reference section, for an
+    /// incremental update.
+    prev: Option<usize>,
+}
+
+impl Trailer {
+    /// Formats the entries, together with `/Size`, for a trailer or
+    /// cross-reference stream dictionary.
+    fn entries(&self, size: usize) -> String {
+        let mut entries = format!("/Size {} /Root {} 0 R", size, self.root_object_id);
+        if let Some(id) = self.info_object_id {
+            entries.push_str(&format!(" /Info {} 0 R", id));
+        }
+        if let Some([first, second]) = &self.id {
+            entries.push_str(&format!(
+                " /ID [{} {}]",
+                pdf_hex_string(first),
+                pdf_hex_string(second)
+            ));
+        }
+        if let Some(id) = self.encrypt_object_id {
+            entries.push_str(&format!(" /Encrypt {} 0 R", id));
+        }
+        if let Some(prev) = self.prev {
+            entries.push_str(&format!(" /Prev {}", prev));
+        }
+        entries
+    }
+}
+
+/// Formats `bytes` as a PDF hex string.
+fn pdf_hex_string(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2 + 2);
+    hex.push('<');
+    for byte in bytes {
+        hex.push_str(&format!("{:02X}", byte));
+    }
+    hex.push('>');
+    hex
+}
+
+/// Writes PDF objects to an output and records the byte offset of each one
+/// so the cross-reference table can be generated once everything is written.
+///
+/// Object ids are handed out by `reserve_object_id` and may be written in any
+/// order; the xref table is always emitted sorted by id. Offsets are tracked
+/// as bytes pass through, so the output never needs to be seekable.
+///
+/// A writer can also append an incremental update to an existing PDF, in
+/// which case new ids continue after the existing ones and objects of the
+/// existing file can be written again to replace them.
+struct PdfWriter<W: Write> {
+    out: W,
+    position: usize,
+    /// The id of the first object in `offsets`.
+    first_object_id: u32,
+    offsets: Vec<usize>,
+    /// The offsets of rewritten objects from an earlier revision.
+    replaced: Vec<(u32, usize)>,
+    /// Encrypts strings and streams, in encrypted documents.
+    encryptor: Option<encrypt::Encryptor>,
+    /// The object being written, whose key encrypts its strings and streams.
+    current_object: Option<u32>,
+    /// The output held back, for linearization, which reorders the objects
+    /// once all are written.
+    held: Option<Vec<u8>>,
+    /// The number of images placed on pages so far, which picks their
+    /// alternate texts.
+    images_placed: usize,
+    /// The pages of a tagged document that have figures, with the alternate
+    /// text of each figure in order of their marked-content ids.
+    figure_pages: Vec<(u32, Vec<String>)>,
+    /// What has been written so far, for [`ConversionReport`].
+    report: ConversionReport,
+    /// Whether a page has a `/UserUnit`, which needs PDF 1.6.
+    user_units: bool,
+    /// The objects waiting to be packed into the next object stream, as
+    /// `(id, body)`, when objects are packed.
+    packing: Option<Vec<(u32, Vec<u8>)>>,
+    /// The body of the object being written, held back to be packed unless
+    /// it turns out to have a stream.
+    captured: Option<Vec<u8>>,
+    /// The object stream each packed object is in, and its index there.
+    packed: BTreeMap<u32, (u32, usize)>,
+}
+
+/// The most objects packed into one object stream, which readers decompress
+/// as a whole to get at any of them.
+const OBJECTS_PER_STREAM: usize = 100;
+
+/// The largest offset the ten digits of an xref table entry hold.
+const MAX_XREF_TABLE_OFFSET: u64 = 9_999_999_999;
+
+impl<W: Write> PdfWriter<W> {
+    /// Creates a writer and writes the header for PDF `version` to `out`,
+    /// followed by the binary comment line.
+    fn new(out: W, version: &str) -> io::Result<Self> {
+        let mut pdf = PdfWriter::objects(out, 1);
+        writeln!(pdf, "%PDF-{}", version)?;
+        // A comment of bytes above 127 marks the file as binary for tools
+        // that would otherwise treat it as text.
+        pdf.write_all(b"%\xE2\xE3\xCF\xD3\n")?;
+        Ok(pdf)
+    }
+
+    /// Creates a writer for an incremental update and copies `existing_pdf`,
+    /// whose trailer has a `/Size` of `size`, to `out`.
+    fn append(out: W, existing_pdf: &[u8], size: u32) -> io::Result<Self> {
+        let mut pdf = PdfWriter::objects(out, size);
+        pdf.write_all(existing_pdf)?;
+        if !existing_pdf.ends_with(b"\n") {
+            writeln!(pdf)?;
+        }
+        Ok(pdf)
+    }
+
+    /// Creates a writer for bare objects numbered from `first_object_id`,
+    /// without a header.
+    fn objects(out: W, first_object_id: u32) -> Self {
+        PdfWriter {
+            out,
+            position: 0,
+            first_object_id,
+            offsets: Vec::new(),
+            replaced: Vec::new(),
+            encryptor: None,
+            current_object: None,
+            held: None,
+            images_placed: 0,
+            figure_pages: Vec::new(),
+            report: ConversionReport::default(),
+            user_units: false,
+            packing: None,
+            captured: None,
+            packed: BTreeMap::new(),
+        }
+    }
+
+    /// Reserves the next free object id. Ids start at 1, as 0 is the head of
+    /// the free list in the xref table.
+    fn reserve_object_id(&mut self) -> u32 {
+        self.offsets.push(0);
+        self.first_object_id + self.offsets.len() as u32 - 1
+    }
+
+    /// The offset object `id` was written at, if it is one of the objects
+    /// of this writer.
+    fn offset_of(&self, id: u32) -> Option<usize> {
+        let index = id.checked_sub(self.first_object_id)?;
+        self.offsets.get(index as usize).copied()
+    }
+
+    /// Packs the objects written from now on into object streams, unless
+    /// they have a stream. They then need
+    /// [`finish_with_xref_stream`](Self::finish_with_xref_stream).
+    fn pack_objects(&mut self) {
+        self.packing = Some(Vec::new());
+    }
+
+    /// Begins object `id`: records its offset and writes its `N 0 obj`
+    /// marker, or, when objects are packed, starts capturing its body.
+    fn begin_object(&mut self, id: u32) -> io::Result<()> {
+        self.current_object = Some(id);
+        if self.packing.is_some() {
+            self.captured = Some(Vec::new());
+            return Ok(());
+        }
+        self.write_object_marker(id)
+    }
+
+    /// Records the offset of object `id` and writes its `N 0 obj` marker.
+    fn write_object_marker(&mut self, id: u32) -> io::Result<()> {
+        match id.checked_sub(self.first_object_id) {
+            Some(index) => match self.offsets.get_mut(index as usize) {
+                Some(offset) => *offset = self.position,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("object {} was never reserved", id),
+                    ))
+                }
+            },
+            None => self.replaced.push((id, self.position)),
+        }
+        writeln!(self, "{} 0 obj", id)
+    }
+
+    /// Formats a text string of the object being written, encrypted in
+    /// encrypted documents.
+    fn text_string(&self, s: &str) -> String {
+        match (&self.encryptor, self.current_object) {
+            (Some(encryptor), Some(id)) => {
+                pdf_hex_string(&encryptor.encrypt(id, &pdf_string_bytes(s)))
+            }
+            _ => pdf_string(s),
+        }
+    }
+
+    /// Writes a stream: its dictionary, made of the `dict` entries and a
+    /// `/Length` counting the bytes of `stream`, then the data itself. The end
+    /// of line before `endstream` is not part of the data.
+    fn write_stream(&mut self, dict: &str, stream: &[u8]) -> io::Result<()> {
+        // Streams cannot be packed, so an object being captured is written
+        // out as usual.
+        if let (Some(captured), Some(id)) = (self.captured.take(), self.current_object) {
+            self.write_object_marker(id)?;
+            self.write_all(&captured)?;
+        }
+        let encrypted;
+        let stream = match (&self.encryptor, self.current_object) {
+            (Some(encryptor), Some(id)) => {
+                encrypted = encryptor.encrypt(id, stream);
+                &encrypted[..]
+            }
+            _ => stream,
+        };
+        if dict.is_empty() {
+            writeln!(self, "<< /Length {} >>", stream.len())?;
+        } else {
+            writeln!(self, "<< {} /Length {} >>", dict, stream.len())?;
+        }
+        writeln!(self, "stream")?;
+        self.write_all(stream)?;
+        writeln!(self)?;
+        writeln!(self, "endstream")
+    }
+
+    fn end_object(&mut self) -> io::Result<()> {
+        let id = self.current_object.take();
+        let (Some(body), Some(id)) = (self.captured.take(), id) else {
+            return writeln!(self, "endobj");
+        };
+        let packing = self.packing.as_mut().expect("objects are not packed");
+        packing.push((id, body));
+        if packing.len() == OBJECTS_PER_STREAM {
+            self.write_object_stream()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the objects waiting to be packed as a compressed object
+    /// stream: the ids and offsets of the objects, then their bodies.
+    fn write_object_stream(&mut self) -> io::Result<()> {
+        let objects = match &mut self.packing {
+            Some(objects) if !objects.is_empty() => std::mem::take(objects),
+            _ => return Ok(()),
+        };
+        let stream_object_id = self.reserve_object_id();
+        let mut header = String::new();
+        let mut bodies = Vec::new();
+        for (index, (id, body)) in objects.iter().enumerate() {
+            header.push_str(&format!("{} {} ", id, bodies.len()));
+            bodies.extend_from_slice(body);
+            self.packed.insert(*id, (stream_object_id, index));
+        }
+        let first = header.len();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(header.as_bytes())?;
+        encoder.write_all(&bodies)?;
+        let data = encoder.finish()?;
+
+        self.current_object = Some(stream_object_id);
+        self.write_object_marker(stream_object_id)?;
+        let dict = format!(
+            "/Type /ObjStm /N {} /First {} /Filter /FlateDecode",
+            objects.len(),
+            first
+        );
+        self.write_stream(&dict, &data)?;
+        self.end_object()
+    }
+
+    /// The `/Size` of the trailer: one more than the highest object id.
+    fn size(&self) -> usize {
+        self.first_object_id as usize + self.offsets.len()
+    }
+
+    /// Groups the written objects into cross-reference subsections of
+    /// consecutive ids, as `(first id, offsets)`. An offset of 0 marks a free
+    /// entry: the header is at offset 0, so that is never an object's offset,
+    /// and objects reserved but never written are listed as free.
+    fn xref_sections(&mut self) -> Vec<(u32, Vec<usize>)> {
+        let mut sections: Vec<(u32, Vec<usize>)> = Vec::new();
+        self.replaced.sort_unstable();
+        for &(id, offset) in &self.replaced {
+            match sections.last_mut() {
+                Some((first, offsets)) if *first + offsets.len() as u32 == id => {
+                    offsets.push(offset)
+                }
+                _ => sections.push((id, vec![offset])),
+            }
+        }
+        let offsets = std::mem::take(&mut self.offsets);
+        if self.first_object_id == 1 {
+            // Object 0 is the head of the free list.
+            sections.push((0, [0].into_iter().chain(offsets).collect()));
+        } else {
+            sections.push((self.first_object_id, offsets));
+        }
+        sections
+    }
+
+    /// The first object offset too large for the ten digits of an xref
+    /// table, if there is one.
+    fn table_overflow(&self) -> Option<usize> {
+        self.offsets
+            .iter()
+            .chain(self.replaced.iter().map(|(_, offset)| offset))
+            .copied()
+            .find(|&offset| offset as u64 > MAX_XREF_TABLE_OFFSET)
+    }
+
+    /// Writes the xref table and trailer and returns the underlying output.
+    /// Fails if an offset does not fit the table, rather than writing a
+    /// corrupt one.
+    fn finish(mut self, trailer: &Trailer) -> io::Result<W> {
+        if let Some(offset) = self.table_overflow() {
+            return Err(io::Error::other(format!(
+                "object offset {} does not fit the 10 digits of an xref table; write an xref stream instead",
+                offset
+            )));
+        }
+        let xref_start = self.position;
+        let size = self.size();
+        writeln!(self, "xref")?;
+        for (first, offsets) in self.xref_sections() {
+            writeln!(self, "{} {}", first, offsets.len())?;
+            for (id, offset) in (first..).zip(offsets) {
+                match (id, offset) {
+                    (0, _) => writeln!(self, "0000000000 65535 f ")?,
+                    (_, 0) => writeln!(self, "0000000000 00001 f ")?,
+                    _ => writeln!(self, "{:010} 00000 n ", offset)?,
+                }
+            }
+        }
+
+        let entries = trailer.entries(size);
+        writeln!(self, "trailer\n<< {} >>", entries)?;
+        self.end(xref_start)
+    }
+
+    /// Like [`finish`](Self::finish), but writes a compressed cross-reference
+    /// stream, which also carries the trailer entries, instead of the classic
+    /// table. Requires PDF 1.5.
+    fn finish_with_xref_stream(mut self, trailer: &Trailer) -> io::Result<W> {
+        self.write_object_stream()?;
+        self.packing = None;
+
+        // The stream is an object itself and lists its own offset.
+        let xref_object_id = self.reserve_object_id();
+        let xref_start = self.position;
+        *self.offsets.last_mut().unwrap() = xref_start;
+        let size = self.size();
+        let sections = self.xref_sections();
+
+        // Each row is a type byte, an offset as wide as the largest needs and
+        // a two-byte generation number. Packed objects have the id of their
+        // object stream and their index in it in place of the two.
+        let offset_width = (1..8)
+            .find(|&bytes| xref_start.max(size) >> (8 * bytes) == 0)
+            .unwrap_or(8);
+        let mut rows = Vec::new();
+        for (first, offsets) in &sections {
+            for (id, &offset) in (*first..).zip(offsets) {
+                // As in the classic table, unwritten objects are listed as free.
+                let (kind, field, generation) = match (id, offset) {
+                    (0, _) => (0u8, 0, 0xFFFFu16),
+                    (_, 0) => match self.packed.get(&id) {
+                        Some(&(stream_id, index)) => (2, stream_id as usize, index as u16),
+                        None => (0, 0, 1),
+                    },
+                    _ => (1, offset, 0),
+                };
+                rows.push(kind);
+                rows.extend_from_slice(&(field as u64).to_be_bytes()[8 - offset_width..]);
+                rows.extend_from_slice(&generation.to_be_bytes());
+            }
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&rows)?;
+        let rows = encoder.finish()?;
+
+        // `/Index` defaults to a single section starting at object 0.
+        let index = if sections.len() == 1 && sections[0].0 == 0 {
+            String::new()
+        } else {
+            let ranges: Vec<String> = sections
+                .iter()
+                .map(|(first, offsets)| format!("{} {}", first, offsets.len()))
+                .collect();
+            format!(" /Index [{}]", ranges.join(" "))
+        };
+        writeln!(self, "{} 0 obj", xref_object_id)?;
+        let dict = format!(
+            "/Type /XRef {}{} /W [1 {} 2] /Filter /FlateDecode",
+            trailer.entries(size),
+            index,
+            offset_width
+        );
+        self.write_stream(&dict, &rows)?;
+        self.end_object()?;
+        self.end(xref_start)
+    }
+
+    /// Like [`finish`](Self::finish), but reorders the objects held back
+    /// since [`hold`](Self::hold) into a linearized file.
+    fn finish_linearized(mut self, trailer: &Trailer, page_object_ids: &[u32]) -> io::Result<W> {
+        let held = self.held.take().expect("output is not held back");
+        // Linearized files have xref tables, and are about as long as the
+        // objects written.
+        if self.position as u64 > MAX_XREF_TABLE_OFFSET {
+            return Err(io::Error::other(format!(
+                "a linearized PDF of {} bytes is too large for the 10-digit offsets of its xref tables",
+                self.position
+            )));
+        }
+        let start = self.position - held.len();
+        let objects: Vec<(u32, usize)> = (self.first_object_id..)
+            .zip(&self.offsets)
+            .filter(|&(_, &offset)| offset != 0)
+            .map(|(id, &offset)| (id, offset - start))
+            .collect();
+        let linearized = linearize::linearize(start, &held, &objects, page_object_ids, trailer);
+        self.out.write_all(&linearized)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// Holds the output back from now on, until
+    /// [`finish_linearized`](Self::finish_linearized).
+    fn hold(&mut self) {
+        self.held = Some(Vec::new());
+    }
+
+    /// Writes the pointer to the cross-reference section and the end-of-file
+    /// marker, and returns the underlying output.
+    fn end(mut self, xref_start: usize) -> io::Result<W> {
+        writeln!(self, "startxref\n{}", xref_start)?;
+        writeln!(self, "%%EOF")?;
+
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Write for PdfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(captured) = &mut self.captured {
+            return captured.write(buf);
+        }
+        let written = match &mut self.held {
+            Some(held) => held.write(buf)?,
+            None => self.out.write(buf)?,
+        };
+        self.position += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// An output that only counts the bytes written to it.
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Formats `name` as a PDF name object, with `#` escapes for the bytes
+/// that are not regular characters, such as spaces.
+fn pdf_name(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len() + 1);
+    escaped.push('/');
+    for &byte in name.as_bytes() {
+        let delimiter = b"()<>[]{}/%#".contains(&byte);
+        if (b'!'..=b'~').contains(&byte) && !delimiter {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("#{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+/// Formats `s` as a PDF text string: an escaped literal string if it is
+/// plain ASCII, otherwise a UTF-16BE hex string with a byte order mark.
+fn pdf_string(s: &str) -> String {
+    if s.is_ascii() {
+        let mut literal = String::with_capacity(s.len() + 2);
+        literal.push('(');
+        for c in s.chars() {
+            match c {
+                '(' | ')' | '\\' => {
+                    literal.push('\\');
+                    literal.push(c);
+                }
+                '\r' => literal.push_str("\\r"),
+                '\n' => literal.push_str("\\n"),
+                _ => literal.push(c),
+            }
+        }
+        literal.push(')');
+        literal
+    } else {
+        pdf_hex_string(&pdf_string_bytes(s))
+    }
+}
+
+/// The bytes of `s` as a PDF text string: `s` itself if it is plain ASCII,
+/// otherwise UTF-16BE with a byte order mark.
+fn pdf_string_bytes(s: &str) -> Vec<u8> {
+    if s.is_ascii() {
+        s.as_bytes().to_vec()
+    } else {
+        let mut bytes = vec![0xFE, 0xFF];
+        bytes.extend(s.encode_utf16().flat_map(u16::to_be_bytes));
+        bytes
+    }
+}
+
+/// The colour space of an image XObject's samples.
+#[derive(Debug, Clone, PartialEq)]
+enum ColorSpace {
+    DeviceGray,
+    DeviceRGB,
+    DeviceCMYK,
+    /// Samples are indices into an RGB palette of packed `r, g, b` triples.
+    Indexed(Vec<u8>),
+    /// A colour space given with the samples, which are embedded as they are.
+    Passthrough(PassthroughColorSpace),
+}
+
+impl ColorSpace {
+    /// The PDF object used for the `/ColorSpace` entry. With a `profile`
+    /// object id, the device colour space is replaced by that `/ICCBased`
+    /// profile. DeviceN passthrough colour spaces take the object id of
+    /// their tint transform instead.
+    fn to_pdf(&self, profile: Option<u32>) -> String {
+        if let ColorSpace::Passthrough(color_space) = self {
+            return color_space.to_pdf(profile);
+        }
+        let base = match profile {
+            Some(id) => format!("[/ICCBased {} 0 R]", id),
+            None => self.device_space().to_string(),
+        };
+        match self {
+            ColorSpace::Indexed(palette) => format!(
+                "[/Indexed {} {} {}]",
+                base,
+                palette.len() / 3 - 1,
+                pdf_hex_string(palette)
+            ),
+            _ => base,
+        }
+    }
+
+    /// The device colour space of the samples, or of the palette entries for
+    /// indexed images.
+    fn device_space(&self) -> &'static str {
+        match self {
+            ColorSpace::DeviceGray => "/DeviceGray",
+            ColorSpace::DeviceRGB | ColorSpace::Indexed(_) => "/DeviceRGB",
+            ColorSpace::DeviceCMYK => "/DeviceCMYK",
+            ColorSpace::Passthrough(PassthroughColorSpace::Lab { .. }) => "/Lab",
+            ColorSpace::Passthrough(PassthroughColorSpace::DeviceN { .. }) => "/DeviceN",
+        }
+    }
+
+    /// The number of colour components of the device colour space.
+    fn components(&self) -> u8 {
+        match self {
+            ColorSpace::DeviceGray => 1,
+            ColorSpace::DeviceRGB | ColorSpace::Indexed(_) => 3,
+            ColorSpace::DeviceCMYK => 4,
+            ColorSpace::Passthrough(color_space) => color_space.components() as u8,
+        }
+    }
+}
+
+/// A channel type that can be written as PDF image samples.
+trait Sample: Copy + PartialEq + Into<u64> {
+    const BITS: u8;
+
+    fn from_u64(value: u64) -> Self;
+
+    /// Returns the samples decoded by `tiff`, if they have this type.
+    fn from_tiff(samples: tiff::decoder::DecodingResult) -> Option<Vec<Self>>;
+
+    /// Appends the sample in the big-endian byte order PDF requires.
+    fn write_to(self, out: &mut Vec<u8>);
+
+    /// Returns the interleaved samples of `img` and its number of channels,
+    /// if it is a gray or RGB image stored with this sample type.
+    fn samples(img: &DynamicImage) -> Option<(&[Self], usize)>;
+
+    /// Converts `img` to RGBA with this sample type.
+    fn to_rgba(img: &DynamicImage) -> DynamicImage;
+}
+
+impl Sample for u8 {
+    const BITS: u8 = 8;
+
+    fn from_u64(value: u64) -> Self {
+        value as u8
+    }
+
+    fn from_tiff(samples: tiff::decoder::DecodingResult) -> Option<Vec<Self>> {
+        match samples {
+            tiff::decoder::DecodingResult::U8(samples) => Some(samples),
+            _ => None,
+        }
+    }
+
+    fn write_to(self, out: &mut Vec<u8>) {
+        out.push(self);
+    }
+
+    fn samples(img: &DynamicImage) -> Option<(&[Self], usize)> {
+        match img {
+            DynamicImage::ImageLuma8(img) => Some((img.as_raw(), 1)),
+            DynamicImage::ImageLumaA8(img) => Some((img.as_raw(), 2)),
+            DynamicImage::ImageRgb8(img) => Some((img.as_raw(), 3)),
+            DynamicImage::ImageRgba8(img) => Some((img.as_raw(), 4)),
+            _ => None,
+        }
+    }
+
+    fn to_rgba(img: &DynamicImage) -> DynamicImage {
+        DynamicImage::ImageRgba8(img.to_rgba8())
+    }
+}
+
+impl Sample for u16 {
+    const BITS: u8 = 16;
+
+    fn from_u64(value: u64) -> Self {
+        value as u16
+    }
+
+    fn from_tiff(samples: tiff::decoder::DecodingResult) -> Option<Vec<Self>> {
+        match samples {
+            tiff::decoder::DecodingResult::U16(samples) => Some(samples),
+            _ => None,
+        }
+    }
+
+    fn write_to(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn samples(img: &DynamicImage) -> Option<(&[Self], usize)> {
+        match img {
+            DynamicImage::ImageLuma16(img) => Some((img.as_raw(), 1)),
+            DynamicImage::ImageLumaA16(img) => Some((img.as_raw(), 2)),
+            DynamicImage::ImageRgb16(img) => Some((img.as_raw(), 3)),
+            DynamicImage::ImageRgba16(img) => Some((img.as_raw(), 4)),
+            _ => None,
+        }
+    }
+
+    fn to_rgba(img: &DynamicImage) -> DynamicImage {
+        DynamicImage::ImageRgba16(img.to_rgba16())
+    }
+}
+
+/// Calls `f` with the RGBA samples of each row of `img`.
+///
+/// Gray and RGB images are expanded to RGBA one row at a time, so the whole
+/// image is never held as RGBA; other images are converted up front.
+fn for_each_rgba_row<T: Sample>(
+    img: &DynamicImage,
+    mut f: impl FnMut(&[T]) -> io::Result<()>,
+) -> io::Result<()> {
+    let converted;
+    let (samples, channels) = match T::samples(img) {
+        Some(samples) => samples,
+        None => {
+            converted = T::to_rgba(img);
+            T::samples(&converted).expect("image was converted to RGBA")
+        }
+    };
+
+    let width = img.width() as usize;
+    let max = T::from_u64((1 << T::BITS) - 1);
+    let mut row = Vec::with_capacity(if channels == 4 { 0 } else { width * 4 });
+    for pixels in samples.chunks_exact((width * channels).max(1)) {
+        if channels == 4 {
+            f(pixels)?;
+            continue;
+        }
+        row.clear();
+        for pixel in pixels.chunks_exact(channels) {
+            match *pixel {
+                [gray] => row.extend_from_slice(&[gray, gray, gray, max]),
+                [gray, alpha] => row.extend_from_slice(&[gray, gray, gray, alpha]),
+                [r, g, b] => row.extend_from_slice(&[r, g, b, max]),
+                _ => unreachable!("samples have 1 to 3 channels"),
+            }
+        }
+        f(&row)?;
+    }
+    Ok(())
+}
+
+/// Returns the colour of an RGBA pixel, blended over `background` if set.
+fn pixel_color<T: Sample>(pixel: &[T], background: Option<[u8; 3]>) -> [T; 3] {
+    match background {
+        Some(background) => {
+            let max = (1u64 << T::BITS) - 1;
+            let alpha: u64 = pixel[3].into();
+            let mut blended = [T::from_u64(0); 3];
+            for (i, channel) in blended.iter_mut().enumerate() {
+                let color: u64 = pixel[i].into();
+                let background = background[i] as u64 * max / 255;
+                *channel =
+                    T::from_u64((color * alpha + background * (max - alpha) + max / 2) / max);
+            }
+            blended
+        }
+        None => [pixel[0], pixel[1], pixel[2]],
+    }
+}
+
+/// What a first pass over the pixels of an image found.
+struct PixelSummary {
+    /// Every pixel has equal red, green and blue channels.
+    gray: bool,
+    /// Every pixel is fully opaque.
+    opaque: bool,
+    /// Every colour channel is either zero or full intensity.
+    bilevel: bool,
+    /// The variance of the alpha channel, with alpha from 0 to 1.
+    alpha_variance: f64,
+}
+
+/// Inspects the pixels of `img`, blended over `background` if set, to decide
+/// how its samples are written.
+fn summarize_pixels<T: Sample>(
+    img: &DynamicImage,
+    background: Option<[u8; 3]>,
+) -> io::Result<PixelSummary> {
+    let max = T::from_u64((1 << T::BITS) - 1);
+    let mut summary = PixelSummary {
+        gray: true,
+        opaque: true,
+        bilevel: true,
+        alpha_variance: 0.0,
+    };
+    // Images without an alpha channel are opaque, so their samples are
+    // inspected as they are, without expanding them to RGBA.
+    if let Some((samples, channels @ (1 | 3))) = T::samples(img) {
+        for pixel in samples.chunks_exact(channels) {
+            summary.gray &= channels == 1 || (pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+            summary.bilevel &= pixel.iter().all(|&c| c == T::from_u64(0) || c == max);
+        }
+        return Ok(summary);
+    }
+    let (mut alpha_sum, mut alpha_square_sum) = (0.0, 0.0);
+    for_each_rgba_row::<T>(img, |row| {
+        for pixel in row.chunks_exact(4) {
+            let color = pixel_color(pixel, background);
+            summary.gray &= color[0] == color[1] && color[1] == color[2];
+            summary.opaque &= pixel[3] == max;
+            summary.bilevel &= color.iter().all(|&c| c == T::from_u64(0) || c == max);
+            let alpha = pixel[3].into() as f64 / max.into() as f64;
+            alpha_sum += alpha;
+            alpha_square_sum += alpha * alpha;
+        }
+        Ok(())
+    })?;
+    let pixels = (img.width() as f64 * img.height() as f64).max(1.0);
+    let alpha_mean = alpha_sum / pixels;
+    summary.alpha_variance = (alpha_square_sum / pixels - alpha_mean * alpha_mean).max(0.0);
+    Ok(summary)
+}
+
+/// Writes the colour and alpha channels of an image, one row at a time.
+///
+/// # Arguments
+///
+/// * `img` - The image, with 8 or 16 bits per channel as `T`.
+/// * `background` - If set, each pixel is blended over this colour.
+/// * `gray` - Whether to write one gray sample per pixel instead of three
+///   identical ones.
+/// * `bilevel` - Whether to pack gray samples that are all 0 or 255 into one
+///   bit per pixel.
+/// * `color` - Where the colour samples are written.
+/// * `alpha` - Where the alpha samples are written, if anywhere.
+///
+/// Samples are serialized big-endian.
+fn write_samples<T: Sample>(
+    img: &DynamicImage,
+    background: Option<[u8; 3]>,
+    gray: bool,
+    bilevel: bool,
+    color: &mut impl Write,
+    mut alpha: Option<&mut SampleWriter>,
+) -> io::Result<()> {
+    let channels = if gray { 1 } else { 3 };
+    let width = img.width();
+    let mut color_row = Vec::new();
+    let mut alpha_row = Vec::new();
+
+    // Gray and RGB images without alpha whose samples are laid out as they
+    // are written are copied row by row, with no RGBA rows or alpha samples.
+    let raw = T::samples(img).filter(|&(_, raw_channels)| raw_channels == channels);
+    if let (Some((samples, _)), None) = (raw, &alpha) {
+        for pixels in samples.chunks_exact((width as usize * channels).max(1)) {
+            color_row.clear();
+            for &sample in pixels {
+                sample.write_to(&mut color_row);
+            }
+            if bilevel {
+                color.write_all(&pack_bilevel(&color_row, width))?;
+            } else {
+                color.write_all(&color_row)?;
+            }
+        }
+        return Ok(());
+    }
+
+    for_each_rgba_row::<T>(img, |row| {
+        color_row.clear();
+        alpha_row.clear();
+        for pixel in row.chunks_exact(4) {
+            for &channel in &pixel_color(pixel, background)[..channels] {
+                channel.write_to(&mut color_row);
+            }
+            if alpha.is_some() {
+                pixel[3].write_to(&mut alpha_row);
+            }
+        }
+        if bilevel {
+            color.write_all(&pack_bilevel(&color_row, width))?;
+        } else {
+            color.write_all(&color_row)?;
+        }
+        if let Some(alpha) = alpha.as_mut() {
+            alpha.write_all(&alpha_row)?;
+        }
+        Ok(())
+    })
+}
+
+/// Returns a copy of `img`, whose colours are premultiplied by alpha, with
+/// straight colours. Fully transparent pixels become black, as they have no
+/// colour to recover.
+fn unpremultiply(img: &DynamicImage) -> DynamicImage {
+    let mut img = match img {
+        DynamicImage::ImageLumaA8(_)
+        | DynamicImage::ImageRgba8(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgba16(_) => img.clone(),
+        other => DynamicImage::ImageRgba8(other.to_rgba8()),
+    };
+    match &mut img {
+        DynamicImage::ImageLumaA8(buffer) => unpremultiply_samples::<u8>(buffer, 2),
+        DynamicImage::ImageRgba8(buffer) => unpremultiply_samples::<u8>(buffer, 4),
+        DynamicImage::ImageLumaA16(buffer) => unpremultiply_samples::<u16>(buffer, 2),
+        DynamicImage::ImageRgba16(buffer) => unpremultiply_samples::<u16>(buffer, 4),
+        _ => unreachable!("image was converted to RGBA"),
+    }
+    img
+}
+
+/// Divides the colour samples of each pixel, of `channels` samples with
+/// alpha last, by its alpha, rounding to the nearest value.
+fn unpremultiply_samples<T: Sample>(samples: &mut [T], channels: usize) {
+    let max = (1u64 << T::BITS) - 1;
+    for pixel in samples.chunks_exact_mut(channels) {
+        let (alpha, colors) = pixel.split_last_mut().expect("pixels have alpha");
+        let alpha: u64 = (*alpha).into();
+        for color in colors {
+            let value: u64 = (*color).into();
+            // Fully transparent pixels have nothing to divide by.
+            let straight = (value * max + alpha / 2).checked_div(alpha).unwrap_or(0);
+            *color = T::from_u64(straight.min(max));
+        }
+    }
+}
+
+/// Writes the alpha channel of `img` at half its resolution, rounded up. Each
+/// sample is the average of a block of two by two pixels, or of the pixels
+/// left at the right and bottom edges.
+fn write_half_alpha<T: Sample>(img: &DynamicImage, alpha: &mut impl Write) -> io::Result<()> {
+    let half_width = img.width().div_ceil(2) as usize;
+    let height = img.height();
+    let mut sums = vec![(0u64, 0u64); half_width];
+    let mut rows = 0;
+    let mut alpha_row = Vec::new();
+    for_each_rgba_row::<T>(img, |row| {
+        for (x, pixel) in row.chunks_exact(4).enumerate() {
+            let (sum, count) = &mut sums[x / 2];
+            *sum += pixel[3].into();
+            *count += 1;
+        }
+        rows += 1;
+        if rows % 2 == 0 || rows == height {
+            alpha_row.clear();
+            for (sum, count) in &mut sums {
+                T::from_u64((*sum + *count / 2) / *count).write_to(&mut alpha_row);
+                (*sum, *count) = (0, 0);
+            }
+            alpha.write_all(&alpha_row)?;
+        }
+        Ok(())
+    })
+}
+
+/// Converts an image from a file to a PDF file.
+///
+/// # Arguments
+///
+/// * `input_path` - The path to the input image file.
+/// * `output_path` - The path to the output PDF file.
+///
+/// # Returns
+///
+/// A `Result` indicating success or failure.
+pub fn img2pdf_from_file<P: AsRef<Path>>(input_path: P, output_path: P) -> Result<()> {
+    Img2Pdf::new().convert_file(input_path, output_path)
+}
+
+/// Converts an image from a file to a PDF file, failing if the PDF file
+/// already exists.
+///
+/// # Arguments
+///
+/// * `input_path` - The path to the input image file.
+/// * `output_path` - The path to the output PDF file, which must not exist.
+///
+/// # Returns
+///
+/// A `Result` indicating success or failure. An existing output file is an
+/// I/O error of kind [`io::ErrorKind::AlreadyExists`], and is left untouched.
+pub fn img2pdf_from_file_no_overwrite<P: AsRef<Path>>(input_path: P, output_path: P) -> Result<()> {
+    Img2Pdf::new()
+        .overwrite(false)
+        .convert_file(input_path, output_path)
+}
+
+/// Converts several image files to a single multi-page PDF file.
+///
+/// Pages appear in exactly the order the inputs are given.
+///
+/// # Arguments
+///
+/// * `inputs` - The paths to the input image files.
+/// * `output` - The path to the output PDF file.
+///
+/// # Returns
+///
+/// A `Result` indicating success or failure. If an input cannot be read,
+/// the error message names the offending path.
+pub fn img2pdf_from_files<P: AsRef<Path>, Q: AsRef<Path>>(inputs: &[P], output: Q) -> Result<()> {
+    Img2Pdf::new().convert_files(inputs, output)
+}
+
+/// Converts each image in a directory to its own PDF file.
+///
+/// # Arguments
+///
+/// * `dir` - The directory of the input images.
+/// * `out_dir` - The directory to write the PDFs to, named after the images.
+/// * `recursive` - Whether to convert the images in subdirectories too,
+///   keeping their relative paths under `out_dir`.
+///
+/// # Returns
+///
+/// A `Result` containing a [`DirectoryReport`] of the PDFs written and the
+/// files skipped because they are not images, or an `Img2PdfError` on
+/// failure.
+pub fn img2pdf_from_directory<P: AsRef<Path>, Q: AsRef<Path>>(
+    dir: P,
+    out_dir: Q,
+    recursive: bool,
+) -> Result<DirectoryReport> {
+    Img2Pdf::new().convert_directory(dir, out_dir, recursive)
+}
+
+/// Converts several image files to a single multi-page PDF file, reporting
+/// progress as it goes.
+///
+/// # Arguments
+///
+/// * `inputs` - The paths to the input image files.
+/// * `output` - The path to the output PDF file.
+/// * `on_progress` - Called as `on_progress(pages_done, total)` after each
+///   page is written, so the last call is `on_progress(total, total)`.
+///
+/// # Returns
+///
+/// A `Result` indicating success or failure. If an input cannot be read,
+/// the error message names the offending path.
+pub fn img2pdf_from_files_with_progress<P: AsRef<Path>, Q: AsRef<Path>>(
+    inputs: &[P],
+    output: Q,
+    on_progress: impl FnMut(usize, usize),
+) -> Result<()> {
+    Img2Pdf::new().convert_files_with_progress(inputs, output, on_progress)
+}
+
+/// Downloads an image and converts it to a PDF as binary data.
+///
+/// The format is detected from the downloaded data, not from the URL or the
+/// `Content-Type` of the response.
+///
+/// # Arguments
+///
+/// * `url` - The `http` or `https` URL of the image.
+///
+/// # Returns
+///
+/// A `Result` containing the PDF data as a `Vec<u8>` on success, or an
+/// `Img2PdfError` on failure. A failed download is an I/O error naming the
+/// URL.
+#[cfg(feature = "url")]
+pub fn img2pdf_from_url(url: &str) -> Result<Vec<u8>> {
+    Img2Pdf::new().convert_url(url)
+}
+
+/// Converts several image files to a single multi-page PDF file, encoding
+/// and compressing the pages concurrently.
+///
+/// # Arguments
+///
+/// * `inputs` - The paths to the input image files.
+/// * `output` - The path to the output PDF file.
+///
+/// # Returns
+///
+/// A `Result` indicating success or failure. The PDF is identical to the one
+/// [`img2pdf_from_files`] writes.
+#[cfg(feature = "rayon")]
+pub fn img2pdf_from_files_parallel<P: AsRef<Path>, Q: AsRef<Path>>(
+    inputs: &[P],
+    output: Q,
+) -> Result<()> {
+    Img2Pdf::new().parallel(true).convert_files(inputs, output)
+}
+
+#[test]
+fn test_img2pdf_from_bytes() {
+    let mut img_file =
+        File::open("examples/sample_image.jpg").expect("Failed to open sample image");
+    let mut img_data = Vec::new();
+    img_file
+        .read_to_end(&mut img_data)
+        .expect("Failed to read image data");
+
+    let pdf_data = img2pdf_from_bytes(&img_data).expect("Failed to convert image to PDF");
+
+    assert!(pdf_data.starts_with(b"%PDF"));
+    assert!(pdf_data.ends_with(b"%%EOF\n"));
+}
+
+#[test]
+fn test_img2pdf_file() {
+    let input_path = "examples/sample_image.jpg";
+    let output_path = "examples/sample_image.pdf";
+    img2pdf_from_file(input_path, output_path).expect("Failed to convert image to PDF");
+
+    let mut pdf_file = File::open(output_path).expect("Failed to open output PDF");
+    let mut pdf_data = Vec::new();
+    pdf_file
+        .read_to_end(&mut pdf_data)
+        .expect("Failed to read PDF data");
+
+    assert!(pdf_data.starts_with(b"%PDF"));
+    assert!(pdf_data.ends_with(b"%%EOF\n"));
+}
+
+#[test]
+fn test_img2pdf_from_multiple_bytes() {
+    let img_data = std::fs::read("examples/sample_image.jpg").expect("Failed to read image data");
+
+    let pdf_data = img2pdf_from_multiple_bytes(&[&img_data, &img_data, &img_data])
+        .expect("Failed to convert images to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+
+    assert!(pdf_data.starts_with(b"%PDF"));
+    assert!(pdf_data.ends_with(b"%%EOF\n"));
+    assert_eq!(pdf.matches("/Type /Page ").count(), 3);
+    assert!(pdf.contains("/Count 3"));
+}
+
+#[test]
+fn test_img2pdf_from_files() {
+    let output_path = std::env::temp_dir().join("img2pdf_test_from_files.pdf");
+    img2pdf_from_files(
+        &["examples/sample_image.jpg", "examples/sample_image.jpg"],
+        &output_path,
+    )
+    .expect("Failed to convert images to PDF");
+
+    let pdf_data = std::fs::read(&output_path).expect("Failed to read PDF data");
+    assert!(pdf_data.starts_with(b"%PDF"));
+    assert!(String::from_utf8_lossy(&pdf_data).contains("/Count 2"));
+
+    match img2pdf_from_files(&["examples/missing.png"], &output_path) {
+        Err(Img2PdfError::Io(e)) => {
+            assert_eq!(e.kind(), io::ErrorKind::NotFound);
+            assert!(e.to_string().contains("examples/missing.png"));
+        }
+        other => panic!("Expected an I/O error, got {:?}", other),
+    }
+}
+
+#[cfg(test)]
+fn encode_png(img: &DynamicImage) -> Vec<u8> {
+    let mut png_data = Vec::new();
+    img.write_to(
+        &mut io::Cursor::new(&mut png_data),
+        image::ImageOutputFormat::Png,
+    )
+    .expect("Failed to encode PNG");
+    png_data
+}
+
+#[test]
+fn test_grayscale_uses_device_gray() {
+    let img = DynamicImage::ImageLuma8(image::GrayImage::from_fn(8, 4, |x, y| {
+        image::Luma([(x * 30 + y) as u8])
+    }));
+
+    let pdf_data = img2pdf_from_bytes(&encode_png(&img)).expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+
+    assert!(pdf.contains("/Width 8 /Height 4 /ColorSpace /DeviceGray"));
+    assert!(!pdf.contains("/DeviceRGB"));
+}
+
+#[test]
+fn test_jpeg_is_embedded_with_dct_decode() {
+    let img_data = std::fs::read("examples/sample_image.jpg").expect("Failed to read image data");
+
+    let pdf_data = img2pdf_from_bytes(&img_data).expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+
+    assert!(pdf.contains("/Filter /DCTDecode"));
+    assert!(!pdf.contains("/SMask"));
+    assert!(pdf_data.windows(img_data.len()).any(|w| w == img_data));
+}
+
+#[test]
+fn test_invalid_image_returns_error() {
+    let err = img2pdf_from_bytes(b"not an image").unwrap_err();
+    assert!(matches!(err, Img2PdfError::Decode(_)));
+    assert!(err.to_string().starts_with("Failed to decode image"));
+}
+
+#[test]
+fn test_empty_input_returns_error() {
+    let err = img2pdf_from_bytes(b"").unwrap_err();
+    assert!(matches!(err, Img2PdfError::Unsupported(_)));
+    assert_eq!(err.to_string(), "Unsupported: image data is empty");
+    assert!(matches!(
+        img2pdf_from_multiple_bytes(&[]),
+        Err(Img2PdfError::Unsupported(_))
+    ));
+
+    let img = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(1, 1, image::Rgb([1, 2, 3])));
+    let pdf_data = img2pdf_from_bytes(&encode_png(&img)).expect("Failed to convert image to PDF");
+    assert!(String::from_utf8_lossy(&pdf_data).contains("/MediaBox [0 0 1 1]"));
+}
+
+#[test]
+fn test_page_size_scales_image_to_fit() {
+    let img = DynamicImage::ImageRgb8(image::RgbImage::new(200, 100));
+
+    let pdf_data = img2pdf_from_bytes_with_page_size(&encode_png(&img), PageSize::A4)
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+
+    assert!(pdf.contains("/MediaBox [0 0 595.28 841.89]"));
+    assert!(pdf.contains("q\n595.28 0 0 297.64 0 272.125 cm\n"));
+
+    let pdf_data = img2pdf_from_bytes_with_page_size(
+        &encode_png(&img),
+        PageSize::Custom {
+            width_pt: 100.0,
+            height_pt: 100.0,
+        },
+    )
+    .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+
+    assert!(pdf.contains("/MediaBox [0 0 100 100]"));
+    assert!(pdf.contains("q\n100 0 0 50 0 25 cm\n"));
+}
+
+#[test]
+fn test_preserve_aspect() {
+    let img_data = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(200, 100)));
+    let converter = Img2Pdf::new()
+        .page_size(PageSize::Custom {
+            width_pt: 100.0,
+            height_pt: 100.0,
+        })
+        .margins(Margins::uniform(10.0));
+    let content_of = |converter: Img2Pdf| {
+        let pdf_data = converter
+            .convert_bytes(&img_data)
+            .expect("Failed to convert image to PDF");
+        let content = content_stream(&pdf_data);
+        String::from_utf8(content).unwrap()
+    };
+
+    // Letterboxed by default, and stretched to the margins otherwise.
+    assert_eq!(
+        content_of(converter.clone()),
+        "q\n80 0 0 40 10 30 cm\n/Img0 Do\nQ"
+    );
+    assert_eq!(
+        content_of(converter.clone().preserve_aspect(true)),
+        content_of(converter.clone())
+    );
+    assert_eq!(
+        content_of(converter.preserve_aspect(false)),
+        "q\n80 0 0 80 10 10 cm\n/Img0 Do\nQ"
+    );
+}
+
+#[test]
+fn test_margins_center_image() {
+    let img_data = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(200, 100)));
+    let page = PageSize::Custom {
+        width_pt: 400.0,
+        height_pt: 300.0,
+    };
+
+    let margins = Margins {
+        top: 0.0,
+        right: 100.0,
+        bottom: 100.0,
+        left: 100.0,
+    };
+    let pdf_data = img2pdf_from_bytes_with_margins(&img_data, page, margins)
+        .expect("Failed to convert image to PDF");
+    assert!(String::from_utf8_lossy(&pdf_data).contains("q\n200 0 0 100 100 150 cm\n"));
+
+    let pdf_data =
+        img2pdf_from_bytes_with_margins(&img_data, PageSize::Fit, Margins::uniform(10.0))
+            .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/MediaBox [0 0 220 120]"));
+    assert!(pdf.contains("q\n200 0 0 100 10 10 cm\n"));
+}
+
+#[test]
+fn test_dpi_sizes_page_physically() {
+    let mut img_data =
+        std::fs::read("examples/sample_image.jpg").expect("Failed to read image data");
+    let info = read_jpeg_info(&img_data).expect("Failed to read JPEG header");
+    assert_eq!(read_dpi(&img_data), None);
+
+    // Mark the JFIF header as 144 DPI, i.e. two pixels per point.
+    img_data[13] = 1;
+    img_data[14..18].copy_from_slice(&[0, 144, 0, 144]);
+    assert_eq!(read_dpi(&img_data), Some((144.0, 144.0)));
+
+    let pdf_data = img2pdf_from_bytes(&img_data).expect("Failed to convert image to PDF");
+    assert!(String::from_utf8_lossy(&pdf_data).contains(&format!(
+        "/MediaBox [0 0 {} {}]",
+        info.width as f32 / 2.0,
+        info.height as f32 / 2.0
+    )));
+
+    let pdf_data =
+        img2pdf_from_bytes_with_dpi(&img_data, Some(36.0)).expect("Failed to convert image to PDF");
+    assert!(String::from_utf8_lossy(&pdf_data).contains(&format!(
+        "/MediaBox [0 0 {} {}]",
+        info.width * 2,
+        info.height * 2
+    )));
+
+    assert!(matches!(
+        img2pdf_from_bytes_with_dpi(&img_data, Some(0.0)),
+        Err(Img2PdfError::Unsupported(_))
+    ));
+}
+
+#[cfg(test)]
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+fn pdf_streams(pdf_data: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut streams = Vec::new();
+    let mut pos = 0;
+    while let Some(offset) = find_bytes(&pdf_data[pos..], b" 0 obj\n") {
+        let dict_start = pos + offset + b" 0 obj\n".len();
+        let object_end = find_bytes(&pdf_data[dict_start..], b"endobj")
+            .map_or(pdf_data.len(), |end| dict_start + end);
+        let Some(dict_len) = find_bytes(&pdf_data[dict_start..object_end], b"\nstream\n") else {
+            pos = object_end;
+            continue;
+        };
+
+        let dict =
+            String::from_utf8_lossy(&pdf_data[dict_start..dict_start + dict_len]).into_owned();
+        let length: usize = dict
+            .split("/Length ")
+            .nth(1)
+            .and_then(|rest| rest.split(|c: char| !c.is_ascii_digit()).next())
+            .and_then(|n| n.parse().ok())
+            .expect("Stream without /Length");
+        let data_start = dict_start + dict_len + b"\nstream\n".len();
+        assert!(
+            pdf_data[data_start + length..].starts_with(b"\nendstream\n"),
+            "/Length {} does not end at endstream",
+            length
+        );
+        streams.push((dict, pdf_data[data_start..data_start + length].to_vec()));
+        pos = data_start + length;
+    }
+    streams
+}
+
+/// Returns the data of the first content stream of the PDF, the one that
+/// starts by saving the graphics state.
+#[cfg(test)]
+fn content_stream(pdf_data: &[u8]) -> Vec<u8> {
+    pdf_streams(pdf_data)
+        .into_iter()
+        .find(|(_, data)| data.starts_with(b"q\n"))
+        .expect("No content stream")
+        .1
+}
+
+#[cfg(test)]
+fn inflate(data: &[u8]) -> Vec<u8> {
+    let mut inflated = Vec::new();
+    flate2::read::ZlibDecoder::new(data)
+        .read_to_end(&mut inflated)
+        .expect("Failed to inflate stream");
+    inflated
+}
+
+#[test]
+fn test_flatten_blends_over_background() {
+    let img = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(2, 1, |x, _| {
+        if x == 0 {
+            image::Rgba([255, 0, 0, 255])
+        } else {
+            image::Rgba([0, 0, 255, 0])
+        }
+    }));
+
+    let pdf_data = img2pdf_from_bytes_flatten(&encode_png(&img), [255, 255, 0])
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(!pdf.contains("/SMask"));
+
+    let (_, data) = pdf_streams(&pdf_data)
+        .into_iter()
+        .find(|(dict, _)| dict.contains("/Subtype /Image"))
+        .expect("No image stream");
+    assert_eq!(inflate(&data), vec![255, 0, 0, 255, 255, 0]);
+}
+
+#[test]
+fn test_gray_alpha_rows_are_split() {
+    let img = DynamicImage::ImageLumaA8(image::GrayAlphaImage::from_fn(3, 2, |x, y| {
+        image::LumaA([(10 * x + 100 * y) as u8, (50 * x) as u8])
+    }));
+
+    let pdf_data = img2pdf_from_bytes(&encode_png(&img)).expect("Failed to convert image to PDF");
+    let streams = pdf_streams(&pdf_data);
+    assert!(streams[0].0.contains("/ColorSpace /DeviceGray"));
+    assert_eq!(inflate(&streams[0].1), [0, 10, 20, 100, 110, 120]);
+    assert!(streams[1].0.contains("/ColorSpace /DeviceGray"));
+    assert_eq!(inflate(&streams[1].1), [0, 50, 100, 0, 50, 100]);
+
+    // One byte of colour per pixel, as in the source, and not three.
+    let pdf_data = Img2Pdf::new()
+        .uncompressed(true)
+        .convert_bytes(&encode_png(&img))
+        .expect("Failed to convert image to PDF");
+    let streams = pdf_streams(&pdf_data);
+    assert!(streams[0]
+        .0
+        .contains("/ColorSpace /DeviceGray /BitsPerComponent 8"));
+    assert!(streams[0].0.contains("/SMask "));
+    assert_eq!(streams[0].1, [0, 10, 20, 100, 110, 120]);
+    assert_eq!(streams[1].1, [0, 50, 100, 0, 50, 100]);
+}
+
+#[test]
+fn test_premultiplied_alpha() {
+    let img = encode_png(&DynamicImage::ImageRgba8(
+        image::RgbaImage::from_vec(3, 1, vec![64, 32, 0, 128, 10, 20, 30, 0, 200, 100, 50, 255])
+            .unwrap(),
+    ));
+    let samples_of = |alpha_mode: AlphaMode| {
+        let pdf_data = Img2Pdf::new()
+            .uncompressed(true)
+            .alpha_mode(alpha_mode)
+            .convert_bytes(&img)
+            .expect("Failed to convert image to PDF");
+        let streams = pdf_streams(&pdf_data);
+        (streams[0].1.clone(), streams[1].1.clone())
+    };
+
+    let (color, alpha) = samples_of(AlphaMode::Premultiplied);
+    // Fully transparent pixels have no colour left to recover.
+    assert_eq!(color, [128, 64, 0, 0, 0, 0, 200, 100, 50]);
+    assert_eq!(alpha, [128, 0, 255]);
+    let (color, alpha) = samples_of(AlphaMode::Straight);
+    assert_eq!(color, [64, 32, 0, 10, 20, 30, 200, 100, 50]);
+    assert_eq!(alpha, [128, 0, 255]);
+}
+
+#[test]
+fn test_opaque_image_has_no_smask() {
+    let opaque = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+        4,
+        4,
+        image::Rgba([10, 20, 30, 255]),
+    ));
+    let pdf_data =
+        img2pdf_from_bytes(&encode_png(&opaque)).expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(!pdf.contains("/SMask"));
+    // Catalog, pages, image, content stream, page and info.
+    assert!(pdf.contains("/Size 7 "));
+
+    let translucent = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+        4,
+        4,
+        image::Rgba([10, 20, 30, 128]),
+    ));
+    let pdf_data =
+        img2pdf_from_bytes(&encode_png(&translucent)).expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/SMask"));
+    assert!(pdf.contains("/Size 8 "));
+}
+
+#[test]
+fn test_builder() {
+    let img_data = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(200, 100)));
+
+    let pdf_data = Img2Pdf::new()
+        .page_size(PageSize::Custom {
+            width_pt: 400.0,
+            height_pt: 300.0,
+        })
+        .margins(Margins::uniform(100.0))
+        .title("A (test) \\ title")
+        .compression(1)
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+
+    assert!(pdf.contains("/MediaBox [0 0 400 300]"));
+    assert!(pdf.contains("q\n200 0 0 100 100 100 cm\n"));
+    assert!(pdf.contains("/Title (A \\(test\\) \\\\ title)"));
+    assert!(pdf.contains("/Info "));
+
+    assert!(matches!(
+        Img2Pdf::new().compression(10).convert_bytes(&img_data),
+        Err(Img2PdfError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn test_img2pdf_write_to_file() {
+    let img_data = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(4, 4)));
+    let path =
+        std::env::temp_dir().join(format!("img2pdf_write_to_file_{}.pdf", std::process::id()));
+    let mut file = File::create(&path).expect("Failed to create file");
+    img2pdf_write_to_file(&img_data, &mut file).expect("Failed to convert image to PDF");
+    drop(file);
+    let pdf_data = std::fs::read(&path).expect("Failed to read PDF");
+    std::fs::remove_file(&path).expect("Failed to remove PDF");
+    assert!(pdf_data.starts_with(b"%PDF-"));
+    assert_eq!(validate_pdf(&pdf_data), Ok(()));
+}
+
+#[test]
+fn test_img2pdf_to_writer() {
+    let img_data = encode_png(&DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+        3,
+        2,
+        image::Rgba([1, 2, 3, 4]),
+    )));
+
+    let mut out = io::Cursor::new(Vec::new());
+    img2pdf_to_writer(&img_data, &mut out).expect("Failed to convert image to PDF");
+    assert!(out.into_inner().ends_with(b"%%EOF\n"));
+
+    let converter = Img2Pdf::new().deterministic(true);
+    let mut out = io::Cursor::new(Vec::new());
+    converter
+        .convert_to_writer(&img_data, &mut out)
+        .expect("Failed to convert image to PDF");
+    assert_eq!(
+        out.into_inner(),
+        converter
+            .convert_bytes(&img_data)
+            .expect("Failed to convert image to PDF")
+    );
+}
+
+#[cfg(feature = "url")]
+#[test]
+fn test_img2pdf_from_url() {
+    let img_data = std::fs::read("examples/sample_image.jpg").expect("Failed to read image data");
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let address = listener.local_addr().unwrap();
+    let served = img_data.clone();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("Failed to accept connection");
+        let mut request = [0u8; 1024];
+        let _ = stream.read(&mut request);
+        // A misleading content type, since the format comes from the data.
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            served.len()
+        )
+        .unwrap();
+        stream.write_all(&served).unwrap();
+    });
+
+    let converter = Img2Pdf::new().deterministic(true);
+    let pdf_data = converter
+        .convert_url(&format!("http://{}/scan", address))
+        .expect("Failed to convert image to PDF");
+    server.join().unwrap();
+    assert_eq!(
+        pdf_data,
+        converter
+            .convert_bytes(&img_data)
+            .expect("Failed to convert image to PDF")
+    );
+
+    // Nothing listens on the port any more.
+    let err =
+        img2pdf_from_url(&format!("http://{}/scan", address)).expect_err("Download should fail");
+    assert!(matches!(err, Img2PdfError::Io(_)));
+}
+
+#[test]
+fn test_convert_directory() {
+    let dir = std::env::temp_dir().join(format!("img2pdf_directory_{}", std::process::id()));
+    let (input, output) = (dir.join("in"), dir.join("out"));
+    std::fs::create_dir_all(input.join("nested")).unwrap();
+    std::fs::copy("examples/sample_image.jpg", input.join("photo.jpg")).unwrap();
+    std::fs::write(
+        input.join("nested/icon.png"),
+        encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(2, 2))),
+    )
+    .unwrap();
+    std::fs::write(input.join("notes.txt"), b"not an image").unwrap();
+
+    let report =
+        img2pdf_from_directory(&input, &output, false).expect("Failed to convert directory");
+    assert_eq!(
+        report.converted,
+        [(input.join("photo.jpg"), output.join("photo.pdf"))]
+    );
+    assert_eq!(report.skipped, [input.join("notes.txt")]);
+    assert!(!output.join("nested").exists());
+
+    let report =
+        img2pdf_from_directory(&input, &output, true).expect("Failed to convert directory");
+    assert_eq!(report.converted.len(), 2);
+    let pdf_data = std::fs::read(output.join("nested/icon.pdf")).expect("Failed to read PDF");
+    assert!(pdf_data.ends_with(b"%%EOF\n"));
+
+    // A link back up the tree is not followed.
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(&input, input.join("nested/loop")).unwrap();
+        let report =
+            img2pdf_from_directory(&input, &output, true).expect("Failed to convert directory");
+        assert_eq!(report.converted.len(), 2);
+    }
+
+    // Nothing is written when two images would share a PDF.
+    std::fs::remove_dir_all(&output).unwrap();
+    std::fs::copy(input.join("nested/icon.png"), input.join("photo.png")).unwrap();
+    let err = img2pdf_from_directory(&input, &output, false)
+        .expect_err("Images sharing a PDF should be rejected");
+    assert!(err.to_string().contains("would both be written to"));
+    assert!(!output.exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_estimate_pdf_size() {
+    let jpeg = std::fs::read("examples/sample_image.jpg").expect("Failed to read image data");
+    let png = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::from_fn(
+        40,
+        30,
+        |x, y| image::Rgb([x as u8 * 6, y as u8 * 8, 128]),
+    )));
+
+    for img_data in [&jpeg, &png] {
+        assert_eq!(
+            estimate_pdf_size(img_data).expect("Failed to estimate PDF size"),
+            img2pdf_from_bytes(img_data)
+                .expect("Failed to convert image to PDF")
+                .len()
+        );
+    }
+    let uncompressed = Img2Pdf::new().compression(0);
+    assert_eq!(
+        uncompressed
+            .estimate_size(&png)
+            .expect("Failed to estimate PDF size"),
+        uncompressed
+            .convert_bytes(&png)
+            .expect("Failed to convert image to PDF")
+            .len()
+    );
+    assert!(estimate_pdf_size(b"not an image").is_err());
+}
+
+#[test]
+fn test_img2pdf_from_reader() {
+    let img_data = std::fs::read("examples/sample_image.jpg").expect("Failed to read image data");
+
+    let pdf_data = img2pdf_from_reader(&mut io::Cursor::new(&img_data))
+        .expect("Failed to convert image to PDF");
+    assert!(pdf_data.ends_with(b"%%EOF\n"));
+
+    let converter = Img2Pdf::new().deterministic(true);
+    assert_eq!(
+        converter
+            .convert_reader(&mut io::Cursor::new(&img_data))
+            .expect("Failed to convert image to PDF"),
+        converter
+            .convert_bytes(&img_data)
+            .expect("Failed to convert image to PDF")
+    );
+}
+
+#[test]
+fn test_compression_level() {
+    let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+        image::Rgb([x as u8, y as u8, 0])
+    }));
+    let img_data = encode_png(&img);
+
+    let stored =
+        img2pdf_from_bytes_with_compression(&img_data, 0).expect("Failed to convert image to PDF");
+    let best =
+        img2pdf_from_bytes_with_compression(&img_data, 9).expect("Failed to convert image to PDF");
+
+    assert!(stored.len() > best.len());
+    let converter = Img2Pdf::new().deterministic(true);
+    assert_eq!(
+        converter
+            .clone()
+            .compression(9)
+            .convert_bytes(&img_data)
+            .expect("Failed to convert image to PDF"),
+        converter
+            .convert_bytes(&img_data)
+            .expect("Failed to convert image to PDF")
+    );
+    let (_, data) = pdf_streams(&stored)
+        .into_iter()
+        .find(|(dict, _)| dict.contains("/Subtype /Image"))
+        .expect("No image stream");
+    assert_eq!(inflate(&data), img.as_bytes());
+}
+
+#[test]
+fn test_uncompressed_streams() {
+    let img = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(3, 2, |x, y| {
+        image::Rgba([x as u8, y as u8, 7, 100 + x as u8])
+    }));
+
+    let pdf_data = Img2Pdf::new()
+        .uncompressed(true)
+        .convert_bytes(&encode_png(&img))
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(!pdf.contains("/Filter"));
+
+    let streams = pdf_streams(&pdf_data);
+    let (dict, data) = &streams[0];
+    assert!(dict.contains("/Length 18 "));
+    assert_eq!(
+        data,
+        &[0, 0, 7, 1, 0, 7, 2, 0, 7, 0, 1, 7, 1, 1, 7, 2, 1, 7]
+    );
+    let (_, mask) = &streams[1];
+    assert_eq!(mask, &[100, 101, 102, 100, 101, 102]);
+}
+
+#[test]
+fn test_info_dictionary() {
+    let img_data = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(1, 1)));
+
+    let pdf_data = Img2Pdf::new()
+        .info(Info {
+            title: Some("Scans (2024)".to_string()),
+            author: Some("C:\\Users".to_string()),
+            keywords: Some("Größe".to_string()),
+            ..Info::default()
+        })
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+
+    assert!(pdf.contains(
+        "<< /Title (Scans \\(2024\\)) /Author (C:\\\\Users) /Keywords <FEFF0047007200F600DF0065> "
+    ));
+    assert!(!pdf.contains("/Subject"));
+    assert!(pdf.contains("/Info "));
+    assert!(pdf.contains(&format!(
+        "/Producer (img2pdf \\(rust\\) {})",
+        env!("CARGO_PKG_VERSION")
+    )));
+
+    let pdf_data = Img2Pdf::new()
+        .producer("Scanning station 4")
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    assert!(String::from_utf8_lossy(&pdf_data).contains("/Producer (Scanning station 4)"));
+}
+
+#[test]
+fn test_creation_and_modification_dates() {
+    let img_data = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(1, 1)));
+
+    // 2024-02-29 13:05:09 UTC
+    let timestamp = UNIX_EPOCH + std::time::Duration::from_secs(1709211909);
+    let pdf_data = Img2Pdf::new()
+        .timestamp(timestamp)
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/CreationDate (D:20240229130509Z) /ModDate (D:20240229130509Z)"));
+
+    assert_eq!(pdf_date(UNIX_EPOCH), "D:19700101000000Z");
+    assert_eq!(xmp_date(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+    let pdf_data = img2pdf_from_bytes(&img_data).expect("Failed to convert image to PDF");
+    assert!(String::from_utf8_lossy(&pdf_data).contains("/CreationDate (D:20"));
+}
+
+#[test]
+fn test_deterministic_output() {
+    let img_data = encode_png(&DynamicImage::ImageRgba8(image::RgbaImage::from_fn(
+        16,
+        16,
+        |x, y| image::Rgba([x as u8 * 16, y as u8 * 16, 128, (x + y) as u8]),
+    )));
+    let converter = Img2Pdf::new().title("Reproducible").deterministic(true);
+
+    let first = converter
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    let second = converter
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+
+    assert_eq!(first, second);
+    assert!(String::from_utf8_lossy(&first).contains("/CreationDate (D:19700101000000Z)"));
+}
+
+#[test]
+fn test_trailer_id() {
+    let img_data = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(2, 2)));
+    let trailer_id = |pdf_data: &[u8]| {
+        let pdf = String::from_utf8_lossy(pdf_data).into_owned();
+        let start = pdf.find("/ID [<").expect("No /ID in trailer") + "/ID [".len();
+        let end = start + pdf[start..].find(']').unwrap();
+        let ids: Vec<String> = pdf[start..end].split(' ').map(str::to_string).collect();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids[0].len(), 34);
+        (ids[0].clone(), ids[1].clone())
+    };
+
+    let converter = Img2Pdf::new().timestamp(UNIX_EPOCH);
+    let (first_a, second_a) = trailer_id(&converter.convert_bytes(&img_data).unwrap());
+    let (first_b, second_b) = trailer_id(&converter.convert_bytes(&img_data).unwrap());
+    assert_eq!(first_a, first_b);
+    assert_ne!(second_a, second_b);
+
+    let (first, second) = trailer_id(
+        &converter
+            .clone()
+            .deterministic(true)
+            .convert_bytes(&img_data)
+            .unwrap(),
+    );
+    assert_eq!(first, first_a);
+    assert_eq!(first, second);
+
+    let (other, _) = trailer_id(&converter.title("Other").convert_bytes(&img_data).unwrap());
+    assert_ne!(other, first_a);
+}
+
+#[test]
+fn test_cmyk_tiff_uses_device_cmyk() {
+    let samples: Vec<u8> = (0..2 * 2 * 4).map(|i| i as u8 * 10).collect();
+    let mut tiff_data = io::Cursor::new(Vec::new());
+    tiff::encoder::TiffEncoder::new(&mut tiff_data)
+        .expect("Failed to create TIFF encoder")
+        .write_image::<tiff::encoder::colortype::CMYK8>(2, 2, &samples)
+        .expect("Failed to encode TIFF");
+
+    let pdf_data = img2pdf_from_bytes(tiff_data.get_ref()).expect("Failed to convert image to PDF");
+    let streams = pdf_streams(&pdf_data);
+    let (dict, data) = &streams[0];
+
+    assert!(dict.contains("/ColorSpace /DeviceCMYK"));
+    assert!(!dict.contains("/SMask"));
+    assert_eq!(inflate(data), samples);
+}
+
+#[test]
+fn test_tiled_cmyk_tiff_is_read_by_bands() {
+    // An uncompressed 20x18 CMYK TIFF in 16x16 tiles, padded at the right and
+    // bottom edges.
+    let (width, height, tile) = (20u32, 18u32, 16u32);
+    let pixel = |x: u32, y: u32| [x as u8, y as u8, (x * y) as u8, 200];
+    let samples: Vec<u8> = (0..height)
+        .flat_map(|y| (0..width).flat_map(move |x| pixel(x, y)))
+        .collect();
+    let mut tiles = Vec::new();
+    for tile_y in (0..height).step_by(tile as usize) {
+        for tile_x in (0..width).step_by(tile as usize) {
+            tiles.push(
+                (tile_y..tile_y + tile)
+                    .flat_map(|y| (tile_x..tile_x + tile).map(move |x| (x, y)))
+                    .flat_map(|(x, y)| {
+                        if x < width && y < height {
+                            pixel(x, y)
+                        } else {
+                            [0xFF; 4]
+                        }
+                    })
+                    .collect::<Vec<u8>>(),
+            );
+        }
+    }
+    let mut tiff_data = b"II*\0\0\0\0\0".to_vec();
+    let mut offsets = Vec::new();
+    for data in &tiles {
+        offsets.push(tiff_data.len() as u32);
+        tiff_data.extend_from_slice(data);
+    }
+    let bits_at = tiff_data.len() as u32;
+    tiff_data.extend_from_slice(&[8, 0, 8, 0, 8, 0, 8, 0]);
+    let offsets_at = tiff_data.len() as u32;
+    tiff_data.extend(offsets.iter().flat_map(|offset| offset.to_le_bytes()));
+    let counts_at = tiff_data.len() as u32;
+    tiff_data.extend(
+        tiles
+            .iter()
+            .flat_map(|data| (data.len() as u32).to_le_bytes()),
+    );
+    let ifd_at = tiff_data.len() as u32;
+    tiff_data[4..8].copy_from_slice(&ifd_at.to_le_bytes());
+    let entries: [(u16, u16, u32, u32); 11] = [
+        (256, 4, 1, width),
+        (257, 4, 1, height),
+        (258, 3, 4, bits_at),
+        (259, 3, 1, 1),
+        (262, 3, 1, 5),
+        (277, 3, 1, 4),
+        (284, 3, 1, 1),
+        (322, 4, 1, tile),
+        (323, 4, 1, tile),
+        (324, 4, tiles.len() as u32, offsets_at),
+        (325, 4, tiles.len() as u32, counts_at),
+    ];
+    tiff_data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (tag, kind, count, value) in entries {
+        tiff_data.extend_from_slice(&tag.to_le_bytes());
+        tiff_data.extend_from_slice(&kind.to_le_bytes());
+        tiff_data.extend_from_slice(&count.to_le_bytes());
+        tiff_data.extend_from_slice(&value.to_le_bytes());
+    }
+    tiff_data.extend_from_slice(&[0; 4]);
+
+    let pdf_data = img2pdf_from_bytes(&tiff_data).expect("Failed to convert image to PDF");
+    let (dict, data) = &pdf_streams(&pdf_data)[0];
+    assert!(dict.contains("/Width 20 /Height 18 /ColorSpace /DeviceCMYK"));
+    assert_eq!(inflate(data), samples);
+
+    // Splitting rewrites the tiles as strips.
+    let pages = split_tiff_pages(&tiff_data, &Options::default())
+        .unwrap()
+        .expect("Failed to split TIFF");
+    let mut decoder = tiff::decoder::Decoder::new(io::Cursor::new(&pages[0])).unwrap();
+    assert_eq!(decoder.get_chunk_type(), tiff::decoder::ChunkType::Strip);
+    assert_eq!(decoder.colortype().unwrap(), tiff::ColorType::CMYK(8));
+    assert_eq!(u8::from_tiff(decoder.read_image().unwrap()), Some(samples));
+}
+
+#[test]
+fn test_adobe_cmyk_jpeg_is_inverted() {
+    // The headers of a 2x2 CMYK JPEG, which is embedded without decoding.
+    let cmyk_jpeg = |app14: &[u8]| {
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend_from_slice(app14);
+        jpeg.extend_from_slice(&[0xFF, 0xC0, 0x00, 0x14, 8, 0, 2, 0, 2, 4]);
+        for id in 1..=4 {
+            jpeg.extend_from_slice(&[id, 0x11, 0]);
+        }
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x0E, 4, 1, 0, 2, 0, 3, 0, 4, 0, 0, 63, 0]);
+        jpeg.extend_from_slice(&[0x00, 0xFF, 0xD9]);
+        jpeg
+    };
+    let adobe = b"\xFF\xEE\x00\x0EAdobe\x00\x64\x00\x00\x00\x00\x00";
+
+    let pdf_data = img2pdf_from_bytes(&cmyk_jpeg(adobe)).expect("Failed to convert image to PDF");
+    let (dict, _) = &pdf_streams(&pdf_data)[0];
+    assert!(dict.contains("/ColorSpace /DeviceCMYK"));
+    assert!(dict.contains("/Decode [1 0 1 0 1 0 1 0]"));
+    assert!(dict.contains("/Filter /DCTDecode"));
+
+    let pdf_data = img2pdf_from_bytes(&cmyk_jpeg(&[])).expect("Failed to convert image to PDF");
+    let (dict, _) = &pdf_streams(&pdf_data)[0];
+    assert!(dict.contains("/ColorSpace /DeviceCMYK"));
+    assert!(!dict.contains("/Decode"));
+}
+
+#[test]
+fn test_tiff_strips_and_compression() {
+    // Tall enough for the encoder to split it into several strips.
+    let samples: Vec<u8> = (0..64 * 200 * 3).map(|i| (i % 251) as u8).collect();
+    let mut tiff_data = io::Cursor::new(Vec::new());
+    let mut encoder =
+        tiff::encoder::TiffEncoder::new(&mut tiff_data).expect("Failed to create TIFF encoder");
+    let mut image = encoder
+        .new_image::<tiff::encoder::colortype::RGB8>(64, 200)
+        .expect("Failed to encode TIFF");
+    image.rows_per_strip(16).unwrap();
+    image.write_data(&samples).expect("Failed to encode TIFF");
+    let mut tiff_data = tiff_data.into_inner();
+    let mut decoder = tiff::decoder::Decoder::new(io::Cursor::new(&tiff_data)).unwrap();
+    assert!(decoder.strip_count().unwrap() > 1);
+
+    let pdf_data = img2pdf_from_bytes(&tiff_data).expect("Failed to convert image to PDF");
+    let (dict, data) = &pdf_streams(&pdf_data)[0];
+    assert!(dict.contains("/Width 64 /Height 200 /ColorSpace /DeviceRGB"));
+    assert_eq!(inflate(data), samples);
+
+    // Rewrite the Compression entry, little-endian SHORT 1, as Group 4 fax.
+    let entry = [0x03, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00];
+    let pos = find_bytes(&tiff_data, &entry).expect("No Compression entry");
+    tiff_data[pos + 8] = 4;
+    let err = img2pdf_from_bytes(&tiff_data).expect_err("Group 4 fax should be rejected");
+    assert_eq!(
+        err.to_string(),
+        "Unsupported: TIFF compression CCITT Group 4 fax (4) is not supported"
+    );
+}
+
+#[test]
+fn test_rgb_images_are_written_without_a_mask() {
+    let img = image::RgbImage::from_fn(5, 3, |x, y| image::Rgb([x as u8 * 40, y as u8 * 90, 1]));
+    let pdf_data = Img2Pdf::new()
+        .uncompressed(true)
+        .convert_bytes(&encode_png(&DynamicImage::ImageRgb8(img.clone())))
+        .expect("Failed to convert image to PDF");
+    let streams = pdf_streams(&pdf_data);
+    assert_eq!(
+        streams
+            .iter()
+            .filter(|(dict, _)| dict.contains("/Subtype /Image"))
+            .count(),
+        1
+    );
+    assert!(!streams[0].0.contains("/SMask"));
+    assert_eq!(streams[0].1, img.into_raw());
+}
+
+#[test]
+fn test_16_bit_images_keep_their_depth() {
+    let img = DynamicImage::ImageRgb16(image::ImageBuffer::from_fn(2, 1, |x, _| {
+        image::Rgb([0x1234 + x as u16, 0xabcd, 0xffff])
+    }));
+
+    let pdf_data = img2pdf_from_bytes(&encode_png(&img)).expect("Failed to convert image to PDF");
+    let streams = pdf_streams(&pdf_data);
+    let (dict, data) = &streams[0];
+
+    assert!(dict.contains("/ColorSpace /DeviceRGB /BitsPerComponent 16"));
+    assert_eq!(
+        inflate(data),
+        vec![0x12, 0x34, 0xab, 0xcd, 0xff, 0xff, 0x12, 0x35, 0xab, 0xcd, 0xff, 0xff]
+    );
+
+    let img = DynamicImage::ImageLumaA16(image::ImageBuffer::from_pixel(
+        2,
+        2,
+        image::LumaA([0x8000, 0x0102]),
+    ));
+    let pdf_data = img2pdf_from_bytes(&encode_png(&img)).expect("Failed to convert image to PDF");
+    let streams = pdf_streams(&pdf_data);
+    assert!(streams[0]
+        .0
+        .contains("/ColorSpace /DeviceGray /BitsPerComponent 16"));
+    assert!(streams[1]
+        .0
+        .contains("/ColorSpace /DeviceGray /BitsPerComponent 16"));
+    assert_eq!(inflate(&streams[1].1), [0x01, 0x02].repeat(4));
+}
+
+#[test]
+fn test_bilevel_images_are_packed() {
+    let img = DynamicImage::ImageLuma8(image::GrayImage::from_fn(10, 2, |x, y| {
+        image::Luma([if (x + y) % 2 == 0 { 255 } else { 0 }])
+    }));
+
+    let pdf_data = img2pdf_from_bytes(&encode_png(&img)).expect("Failed to convert image to PDF");
+    let (dict, data) = &pdf_streams(&pdf_data)[0];
+
+    assert!(dict.contains("/ColorSpace /DeviceGray /BitsPerComponent 1"));
+    assert_eq!(
+        inflate(data),
+        vec![0b1010_1010, 0b1000_0000, 0b0101_0101, 0b0100_0000]
+    );
+}
+
+#[test]
+fn test_palette_png_uses_indexed_color_space() {
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, 3, 2);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(vec![255, 0, 0, 0, 255, 0, 0, 0, 255]);
+    let indices = [0, 1, 2, 2, 1, 0];
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&indices))
+        .expect("Failed to encode PNG");
+
+    let pdf_data = img2pdf_from_bytes(&png_data).expect("Failed to convert image to PDF");
+    let (dict, data) = &pdf_streams(&pdf_data)[0];
+
+    assert!(dict
+        .contains("/ColorSpace [/Indexed /DeviceRGB 2 <FF000000FF000000FF>] /BitsPerComponent 8"));
+    assert_eq!(inflate(data), indices);
+}
+
+#[test]
+fn test_decode_array() {
+    let rgb = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::from_fn(
+        4,
+        4,
+        |x, _| image::Rgb([x as u8 * 60, 0, 255]),
+    )));
+    let gray = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(4, 4)));
+    let dict = |converter: Img2Pdf, img_data: &[u8]| {
+        let pdf_data = converter
+            .convert_bytes(img_data)
+            .expect("Failed to convert image to PDF");
+        pdf_streams(&pdf_data)[0].0.clone()
+    };
+
+    assert!(dict(Img2Pdf::new().decode(&[1.0, 0.0]), &rgb).contains("/Decode [1 0 1 0 1 0]"));
+    assert!(dict(Img2Pdf::new().decode(&[1.0, 0.0]), &gray).contains("/Decode [1 0]"));
+    assert!(
+        dict(Img2Pdf::new().decode(&[0.0, 1.0, 1.0, 0.0, 0.0, 0.5]), &rgb)
+            .contains("/Decode [0 1 1 0 0 0.5]")
+    );
+    assert!(!dict(Img2Pdf::new(), &rgb).contains("/Decode"));
+
+    // Palettes are inverted rather than their indices.
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, 3, 1);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(vec![255, 0, 0, 0, 255, 0, 0, 0, 255]);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&[0, 1, 2]))
+        .expect("Failed to encode PNG");
+    let indexed = dict(Img2Pdf::new().decode(&[1.0, 0.0]), &png_data);
+    assert!(indexed.contains("[/Indexed /DeviceRGB 2 <00FFFFFF00FFFFFF00>]"));
+    assert!(!indexed.contains("/Decode"));
+
+    let err = Img2Pdf::new()
+        .decode(&[1.0, 0.0, 1.0, 0.0, 1.0, 0.0])
+        .convert_bytes(&gray)
+        .expect_err("A /Decode array for RGB should not fit a gray image");
+    assert!(err
+        .to_string()
+        .contains("has 6 values, but /DeviceGray images need 2"));
+    assert!(Img2Pdf::new().decode(&[1.0]).convert_bytes(&rgb).is_err());
+
+    // The mismatch is found before the image is written.
+    let mut out = Vec::new();
+    let err = Img2Pdf::new()
+        .decode(&[1.0, 0.0, 1.0, 0.0])
+        .convert_to_writer(&gray, &mut out)
+        .expect_err("A /Decode array for two components should not fit a gray image");
+    assert!(matches!(err, Img2PdfError::Unsupported(_)));
+    assert!(find_bytes(&out, b"/XObject").is_none());
+    let lab = PassthroughColorSpace::Lab {
+        white_point: [0.9642, 1.0, 0.8249],
+        range: [-128.0, 127.0, -128.0, 127.0],
+    };
+    assert!(Img2Pdf::new()
+        .decode(&[1.0, 0.0])
+        .convert_samples(&[255, 128, 128], 1, 1, &lab)
+        .is_err());
+}
+
+#[test]
+fn test_icc_profile_is_embedded() {
+    let mut profile = vec![0u8; 128];
+    profile[16..20].copy_from_slice(b"RGB ");
+    profile[64..68].copy_from_slice(b"test");
+
+    // Gray pixels, which stay RGB because the profile describes RGB.
+    let img = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(2, 2, image::Rgb([90, 90, 90])));
+    let png_data = encode_png(&img);
+    let mut iccp = b"iCCPtest\0\0".to_vec();
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&profile).unwrap();
+    iccp.extend(encoder.finish().unwrap());
+    let mut crc = flate2::Crc::new();
+    crc.update(&iccp);
+    let mut chunk = ((iccp.len() - 4) as u32).to_be_bytes().to_vec();
+    chunk.extend_from_slice(&iccp);
+    chunk.extend_from_slice(&crc.sum().to_be_bytes());
+    // The chunk goes right after the 8-byte signature and the IHDR chunk.
+    let png_data = [&png_data[..33], &chunk, &png_data[33..]].concat();
+
+    let pdf_data = img2pdf_from_bytes(&png_data).expect("Failed to convert image to PDF");
+    let streams = pdf_streams(&pdf_data);
+    assert!(streams[0]
+        .0
+        .contains("/ColorSpace [/ICCBased 3 0 R] /BitsPerComponent 8"));
+    assert_eq!(inflate(&streams[0].1), [90; 12]);
+    assert!(streams[1]
+        .0
+        .starts_with("<< /N 3 /Alternate /DeviceRGB /Filter /FlateDecode"));
+    assert_eq!(inflate(&streams[1].1), profile);
+
+    // JPEGs split the profile across APP2 segments, which may come in any
+    // order.
+    let mut jpeg = Vec::new();
+    img.write_to(
+        &mut io::Cursor::new(&mut jpeg),
+        image::ImageOutputFormat::Jpeg(90),
+    )
+    .expect("Failed to encode JPEG");
+    let app2 = |sequence: u8, piece: &[u8]| {
+        let mut segment = vec![0xFF, 0xE2];
+        segment.extend_from_slice(&(piece.len() as u16 + 16).to_be_bytes());
+        segment.extend_from_slice(b"ICC_PROFILE\0");
+        segment.extend_from_slice(&[sequence, 2]);
+        segment.extend_from_slice(piece);
+        segment
+    };
+    let jpeg = [
+        &jpeg[..2],
+        &app2(2, &profile[100..]),
+        &app2(1, &profile[..100]),
+        &jpeg[2..],
+    ]
+    .concat();
+
+    let pdf_data = img2pdf_from_bytes(&jpeg).expect("Failed to convert image to PDF");
+    let streams = pdf_streams(&pdf_data);
+    assert!(streams[0].0.contains("/ColorSpace [/ICCBased 3 0 R]"));
+    assert_eq!(inflate(&streams[1].1), profile);
+
+    // Without a profile, the device colour space is used.
+    let pdf_data = img2pdf_from_bytes(&encode_png(&img)).expect("Failed to convert image to PDF");
+    assert!(pdf_streams(&pdf_data)[0]
+        .0
+        .contains("/ColorSpace /DeviceGray"));
+}
+
+#[cfg(test)]
+fn with_exif_orientation(jpeg: &[u8], orientation: u16) -> Vec<u8> {
+    let mut app1 = b"\xFF\xE1\x00\x22Exif\0\0MM\x00\x2A\x00\x00\x00\x08\x00\x01".to_vec();
+    app1.extend_from_slice(&[0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01]);
+    app1.extend_from_slice(&orientation.to_be_bytes());
+    app1.extend_from_slice(&[0; 6]);
+    [&jpeg[..2], &app1, &jpeg[2..]].concat()
+}
+
+#[test]
+fn test_jpeg_passthrough_is_byte_exact() {
+    let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 8, |x, y| {
+        image::Rgb([x as u8 * 16, y as u8 * 32, 128])
+    }));
+    let mut jpeg = Vec::new();
+    img.write_to(
+        &mut io::Cursor::new(&mut jpeg),
+        image::ImageOutputFormat::Jpeg(90),
+    )
+    .expect("Failed to encode JPEG");
+    // Upright EXIF metadata and a comment, which must both survive.
+    let jpeg = with_exif_orientation(&jpeg, 1);
+    let jpeg = [&jpeg[..2], b"\xFF\xFE\x00\x09archive", &jpeg[2..]].concat();
+
+    let pdf_data = img2pdf_from_bytes(&jpeg).expect("Failed to convert image to PDF");
+    let (dict, data) = &pdf_streams(&pdf_data)[0];
+    assert!(dict.contains("/Filter /DCTDecode"));
+    assert_eq!(data, &jpeg);
+}
+
+#[test]
+fn test_strip_metadata() {
+    let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 8, |x, y| {
+        image::Rgb([x as u8 * 16, y as u8 * 32, 128])
+    }));
+    let mut plain = Vec::new();
+    img.write_to(
+        &mut io::Cursor::new(&mut plain),
+        image::ImageOutputFormat::Jpeg(90),
+    )
+    .expect("Failed to encode JPEG");
+    let jpeg = with_exif_orientation(&plain, 1);
+    let jpeg = [&jpeg[..2], b"\xFF\xFE\x00\x09archive", &jpeg[2..]].concat();
+
+    let pdf_data = Img2Pdf::new()
+        .strip_metadata(true)
+        .convert_bytes(&jpeg)
+        .expect("Failed to convert image to PDF");
+    let (dict, data) = &pdf_streams(&pdf_data)[0];
+    assert!(dict.contains("/Filter /DCTDecode"));
+    // Only the segments go: the encoder wrote none but the JFIF header.
+    assert_eq!(data, &plain);
+    assert!(find_bytes(&pdf_data, b"Exif").is_none());
+    assert!(find_bytes(&pdf_data, b"archive").is_none());
+}
+
+#[test]
+fn test_exif_orientation_is_applied() {
+    // A 32x16 image with a bright top-left quadrant.
+    let img = DynamicImage::ImageLuma8(image::GrayImage::from_fn(32, 16, |x, y| {
+        image::Luma([if x < 16 && y < 8 { 200 } else { 50 }])
+    }));
+    let mut jpeg = Vec::new();
+    img.write_to(
+        &mut io::Cursor::new(&mut jpeg),
+        image::ImageOutputFormat::Jpeg(100),
+    )
+    .expect("Failed to encode JPEG");
+
+    // The expected page size and the quadrant the bright corner ends up in,
+    // as (right, bottom).
+    let expected = [
+        (1, (32, 16), (false, false)),
+        (2, (32, 16), (true, false)),
+        (3, (32, 16), (true, true)),
+        (4, (32, 16), (false, true)),
+        (5, (16, 32), (false, false)),
+        (6, (16, 32), (true, false)),
+        (7, (16, 32), (true, true)),
+        (8, (16, 32), (false, true)),
+    ];
+    for (orientation, (width, height), (right, bottom)) in expected {
+        let pdf_data = img2pdf_from_bytes(&with_exif_orientation(&jpeg, orientation))
+            .expect("Failed to convert image to PDF");
+        let (dict, data) = &pdf_streams(&pdf_data)[0];
+        assert!(
+            dict.contains(&format!("/Width {} /Height {} ", width, height)),
+            "orientation {}: {}",
+            orientation,
+            dict
+        );
+
+        // Upright JPEGs are embedded as they are.
+        let samples = if dict.contains("/DCTDecode") {
+            image::load_from_memory(data)
+                .expect("Failed to decode JPEG")
+                .to_luma8()
+                .into_raw()
+        } else {
+            inflate(data)
+        };
+        let x = if right { width - 1 } else { 0 };
+        let y = if bottom { height - 1 } else { 0 };
+        assert!(samples[y * width + x] > 128, "orientation {}", orientation);
+        assert!(samples[(height - 1 - y) * width + (width - 1 - x)] < 128);
+    }
+
+    let pdf_data = Img2Pdf::new()
+        .auto_orient(false)
+        .convert_bytes(&with_exif_orientation(&jpeg, 6))
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/Width 32 /Height 16 "));
+    assert!(pdf.contains("/DCTDecode"));
+}
+
+#[test]
+fn test_user_unit_for_large_pages() {
+    let png = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(100, 50)));
+    // 400 by 200 inches at a quarter of a dot per inch.
+    let (pdf_data, report) = Img2Pdf::new()
+        .dpi(0.25)
+        .convert_multiple_bytes_with_report(&[&png])
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.starts_with("%PDF-1.4"));
+    assert!(pdf.contains("/Version /1.6"));
+    assert!(pdf.contains("/MediaBox [0 0 14400 7200]"));
+    assert!(pdf.contains("/UserUnit 2"));
+    assert!(pdf.contains("0.5 0 0 0.5 0 0 cm\nq\n28800 0 0 14400 0 0 cm"));
+    assert_eq!(report.page_sizes, [(28800.0, 14400.0)]);
+    assert_eq!(validate_pdf(&pdf_data), Ok(()));
+
+    let pdf_data = Img2Pdf::new()
+        .user_unit(2.0)
+        .convert_bytes(&png)
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.starts_with("%PDF-1.6"));
+    assert!(!pdf.contains("/Version"));
+    assert!(pdf.contains("/MediaBox [0 0 50 25]"));
+    // Pages of a usual size need none.
+    let pdf_data = img2pdf_from_bytes(&png).expect("Failed to convert image to PDF");
+    assert!(find_bytes(&pdf_data, b"/UserUnit").is_none());
+    assert!(Img2Pdf::new().user_unit(0.0).convert_bytes(&png).is_err());
+}
+
+#[test]
+fn test_max_pixels() {
+    let png = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(10, 10)));
+    assert!(Img2Pdf::new().max_pixels(100).convert_bytes(&png).is_ok());
+    let err = Img2Pdf::new()
+        .max_pixels(99)
+        .convert_bytes(&png)
+        .expect_err("Too many pixels should be rejected");
+    assert!(err
+        .to_string()
+        .contains("image of 10x10 pixels exceeds the limit of 99 pixels"));
+
+    // A PNG header claiming 100000x100000 pixels is rejected before
+    // anything is allocated for them.
+    let mut bomb = png.clone();
+    bomb[16..24].copy_from_slice(&[0, 1, 0x86, 0xA0, 0, 1, 0x86, 0xA0]);
+    let mut crc = flate2::Crc::new();
+    crc.update(&bomb[12..29]);
+    bomb[29..33].copy_from_slice(&crc.sum().to_be_bytes());
+    let err = img2pdf_from_bytes(&bomb).expect_err("A decompression bomb should be rejected");
+    assert!(err
+        .to_string()
+        .contains("exceeds the limit of 100000000 pixels"));
+
+    // A JPEG over the limit is still embedded as it is, but not decoded for
+    // its thumbnail.
+    let jpeg = std::fs::read("examples/sample_image.jpg").expect("Failed to read image");
+    let converter = Img2Pdf::new().thumbnails(true);
+    let pdf_data = converter.convert_bytes(&jpeg).unwrap();
+    assert!(find_bytes(&pdf_data, b"/Thumb").is_some());
+    let pdf_data = converter.max_pixels(1000).convert_bytes(&jpeg).unwrap();
+    assert!(find_bytes(&pdf_data, b"/DCTDecode").is_some());
+    assert!(find_bytes(&pdf_data, b"/Thumb").is_none());
+}
+
+#[test]
+fn test_pixel_aspect_shapes_the_page() {
+    let placement = |pdf_data: &[u8]| {
+        let pdf = String::from_utf8_lossy(pdf_data);
+        let content = pdf.split("q\n").nth(1).expect("No content stream");
+        content.split(" cm").next().unwrap().to_string()
+    };
+
+    // A JFIF header without a unit still gives the pixels twice as wide as
+    // tall, as in an anamorphic video frame.
+    let img = DynamicImage::ImageRgb8(image::RgbImage::new(16, 8));
+    let mut jpeg = Vec::new();
+    img.write_to(
+        &mut io::Cursor::new(&mut jpeg),
+        image::ImageOutputFormat::Jpeg(90),
+    )
+    .expect("Failed to encode JPEG");
+    assert_eq!(&jpeg[6..11], b"JFIF\0");
+    jpeg[13..18].copy_from_slice(&[0, 0, 1, 0, 2]);
+    let pdf_data = img2pdf_from_bytes(&jpeg).expect("Failed to convert image to PDF");
+    assert_eq!(placement(&pdf_data), "32 0 0 8 0 0");
+
+    // A resolution setting keeps the shape of the pixels of a PNG.
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, 10, 10);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: 5906,
+        yppu: 2953,
+        unit: png::Unit::Meter,
+    }));
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&[0; 100]))
+        .expect("Failed to encode PNG");
+    let pdf_data = Img2Pdf::new()
+        .dpi(72.0)
+        .convert_bytes(&png_data)
+        .expect("Failed to convert image to PDF");
+    assert_eq!(placement(&pdf_data), "5 0 0 10 0 0");
+    // Square pixels are the default.
+    let png = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(10, 10)));
+    let pdf_data = img2pdf_from_bytes(&png).expect("Failed to convert image to PDF");
+    assert_eq!(placement(&pdf_data), "10 0 0 10 0 0");
+}
+
+#[test]
+fn test_exif_rotation_shapes_the_page() {
+    // A landscape JPEG holding a portrait photo, turned by orientation 6.
+    let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(48, 24, |x, _| {
+        image::Rgb([x as u8 * 5, 100, 150])
+    }));
+    let mut jpeg = Vec::new();
+    img.write_to(
+        &mut io::Cursor::new(&mut jpeg),
+        image::ImageOutputFormat::Jpeg(90),
+    )
+    .expect("Failed to encode JPEG");
+
+    let pdf_data = img2pdf_from_bytes(&with_exif_orientation(&jpeg, 6))
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    let media_box: Vec<f32> = pdf
+        .split("/MediaBox [")
+        .nth(1)
+        .and_then(|rest| rest.split(']').next())
+        .expect("No MediaBox")
+        .split_whitespace()
+        .map(|n| n.parse().unwrap())
+        .collect();
+    assert_eq!(media_box, [0.0, 0.0, 24.0, 48.0]);
+    assert!(media_box[3] > media_box[2]);
+    assert!(pdf.contains("q\n24 0 0 48 0 0 cm\n"));
+}
+
+#[test]
+fn test_jpeg_quality_reencodes_images() {
+    let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+        image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x ^ y) * 4) as u8])
+    }));
+    let img_data = encode_png(&img);
+
+    let convert = |quality| {
+        Img2Pdf::new()
+            .jpeg_quality(quality)
+            .convert_bytes(&img_data)
+            .expect("Failed to convert image to PDF")
+    };
+    let (low, high) = (convert(10), convert(95));
+    let (dict, data) = &pdf_streams(&high)[0];
+    assert!(dict.contains("/ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /DCTDecode"));
+    let decoded = image::load_from_memory(data).expect("Failed to decode JPEG");
+    assert_eq!(decoded.dimensions(), (64, 64));
+    assert!(low.len() < high.len());
+
+    let translucent = encode_png(&DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+        4,
+        4,
+        image::Rgba([255, 0, 0, 128]),
+    )));
+    assert!(matches!(
+        Img2Pdf::new().jpeg_quality(80).convert_bytes(&translucent),
+        Err(Img2PdfError::Unsupported(_))
+    ));
+    let flattened = Img2Pdf::new()
+        .jpeg_quality(80)
+        .flatten([255, 255, 255])
+        .convert_bytes(&translucent)
+        .expect("Failed to convert image to PDF");
+    assert!(String::from_utf8_lossy(&flattened).contains("/DCTDecode"));
+    assert!(matches!(
+        Img2Pdf::new().jpeg_quality(0).convert_bytes(&img_data),
+        Err(Img2PdfError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn test_progress_is_reported_per_page() {
+    let inputs = ["examples/sample_image.jpg"; 3];
+    let output = std::env::temp_dir().join("img2pdf_test_progress.pdf");
+
+    let mut calls = Vec::new();
+    img2pdf_from_files_with_progress(&inputs, &output, |done, total| calls.push((done, total)))
+        .expect("Failed to convert images to PDF");
+    std::fs::remove_file(&output).ok();
+
+    assert_eq!(calls, [(1, 3), (2, 3), (3, 3)]);
+}
+
+#[test]
+fn test_orientation_turns_the_page() {
+    let img_data = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(200, 100)));
+
+    let pdf_data = Img2Pdf::new()
+        .orientation(Orientation::Portrait)
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/MediaBox [0 0 100 200]"));
+    assert!(pdf.contains("q\n100 0 0 50 0 75 cm\n"));
+
+    let pdf_data = Img2Pdf::new()
+        .page_size(PageSize::A4)
+        .orientation(Orientation::Landscape)
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    assert!(String::from_utf8_lossy(&pdf_data).contains("/MediaBox [0 0 841.89 595.28]"));
+}
+
+/// Parses the last classic xref section of a PDF into `(object id, offset)`
+/// pairs for the objects in use.
+#[cfg(test)]
+fn xref_offsets(pdf: &[u8]) -> Vec<(u32, usize)> {
+    let text = String::from_utf8_lossy(pdf);
+    let startxref = text.rfind("startxref\n").expect("No startxref") + "startxref\n".len();
+    let xref_start: usize = text[startxref..]
+        .lines()
+        .next()
+        .and_then(|line| line.parse().ok())
+        .expect("Invalid startxref");
+    assert!(pdf[xref_start..].starts_with(b"xref\n"));
+
+    let mut lines = pdf[xref_start + "xref\n".len()..].split(|&b| b == b'\n');
+    let mut offsets = Vec::new();
+    loop {
+        let subsection = String::from_utf8_lossy(lines.next().expect("No xref subsection"));
+        if subsection == "trailer" {
+            return offsets;
+        }
+        let (first, count) = subsection.split_once(' ').expect("Invalid xref subsection");
+        let (first, count): (u32, usize) = (first.parse().unwrap(), count.parse().unwrap());
+
+        for (id, line) in (first..).zip(lines.by_ref().take(count)) {
+            // Each entry is exactly 20 bytes, including the end of line.
+            assert_eq!(line.len(), 19, "xref entry {} has the wrong length", id);
+            let line = String::from_utf8_lossy(line);
+            if line.ends_with("n ") {
+                offsets.push((id, line[..10].parse().unwrap()));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_xref_offsets_point_at_their_objects() {
+    let translucent = encode_png(&DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+        4,
+        4,
+        image::Rgba([255, 0, 0, 128]),
+    )));
+    let jpeg = std::fs::read("examples/sample_image.jpg").expect("Failed to read image data");
+    let pdf_data = img2pdf_from_multiple_bytes(&[&jpeg, &translucent, &jpeg])
+        .expect("Failed to convert images to PDF");
+
+    let offsets = xref_offsets(&pdf_data);
+    let size = String::from_utf8_lossy(&pdf_data)
+        .split("/Size ")
+        .nth(1)
+        .and_then(|rest| rest.split(' ').next()?.parse::<usize>().ok())
+        .expect("No /Size");
+    assert_eq!(offsets.len(), size - 1);
+    for (id, offset) in offsets {
+        let marker = format!("{} 0 obj\n", id);
+        assert!(
+            pdf_data[offset..].starts_with(marker.as_bytes()),
+            "object {} is not at offset {}",
+            id,
+            offset
+        );
+    }
+}
+
+#[test]
+fn test_xref_stream() {
+    let jpeg = std::fs::read("examples/sample_image.jpg").expect("Failed to read image data");
+    let pdf_data = Img2Pdf::new()
+        .xref_stream(true)
+        .convert_multiple_bytes(&[&jpeg, &jpeg])
+        .expect("Failed to convert images to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+
+    assert!(pdf_data.starts_with(b"%PDF-1.5\n"));
+    assert!(!pdf.contains("\nxref\n") && !pdf.contains("trailer"));
+
+    let startxref = pdf.rfind("startxref\n").unwrap() + "startxref\n".len();
+    let xref_start: usize = pdf[startxref..].lines().next().unwrap().parse().unwrap();
+    let (dict, rows) = pdf_streams(&pdf_data)
+        .into_iter()
+        .find(|(dict, _)| dict.contains("/Type /XRef"))
+        .expect("No cross-reference stream");
+    assert!(dict.contains("/Root ") && dict.contains("/Info ") && dict.contains("/ID ["));
+
+    let width: usize = dict
+        .split("/W [1 ")
+        .nth(1)
+        .and_then(|rest| rest.split(' ').next()?.parse().ok())
+        .expect("No /W");
+    let size: usize = dict
+        .split("/Size ")
+        .nth(1)
+        .and_then(|rest| rest.split(' ').next()?.parse().ok())
+        .expect("No /Size");
+    let rows = inflate(&rows);
+    assert_eq!(rows.len(), size * (width + 3));
+
+    let mut found_self = false;
+    for (id, row) in rows.chunks_exact(width + 3).enumerate().skip(1) {
+        assert_eq!(row[0], 1);
+        let offset = row[1..1 + width]
+            .iter()
+            .fold(0usize, |offset, &b| offset << 8 | b as usize);
+        assert!(pdf_data[offset..].starts_with(format!("{} 0 obj\n", id).as_bytes()));
+        found_self |= offset == xref_start;
+    }
+    assert!(found_self);
+}
+
+#[test]
+fn test_object_streams() {
+    let img_data = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(3, 2)));
+    let images = vec![img_data.as_slice(); 150];
+    let converter = Img2Pdf::new().deterministic(true).xref_stream(true);
+    let unpacked = converter
+        .convert_multiple_bytes(&images)
+        .expect("Failed to convert images to PDF");
+    let pdf_data = converter
+        .object_streams(true)
+        .convert_multiple_bytes(&images)
+        .expect("Failed to convert images to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+
+    assert!(pdf_data.starts_with(b"%PDF-1.5\n"));
+    assert_eq!(validate_pdf(&pdf_data), Ok(()));
+    assert!(pdf_data.len() < unpacked.len());
+    // The pages, the page tree, the catalog and the information dictionary
+    // are only in the object streams; the images are not.
+    assert!(!pdf.contains("/Type /Page") && !pdf.contains("/Type /Catalog"));
+    assert_eq!(pdf.matches("/Subtype /Image").count(), 150);
+
+    let object_streams: Vec<_> = pdf_streams(&pdf_data)
+        .into_iter()
+        .filter(|(dict, _)| dict.contains("/Type /ObjStm"))
+        .collect();
+    assert_eq!(object_streams.len(), 2);
+    let mut packed = String::new();
+    for (dict, data) in &object_streams {
+        let count: usize = dict
+            .split("/N ")
+            .nth(1)
+            .and_then(|rest| rest.split(' ').next()?.parse().ok())
+            .expect("No /N");
+        let first: usize = dict
+            .split("/First ")
+            .nth(1)
+            .and_then(|rest| rest.split(' ').next()?.parse().ok())
+            .expect("No /First");
+        let data = inflate(data);
+        let header = std::str::from_utf8(&data[..first]).unwrap();
+        assert_eq!(header.split_whitespace().count(), 2 * count);
+        assert!(count <= OBJECTS_PER_STREAM);
+        packed.push_str(&String::from_utf8_lossy(&data[first..]));
+    }
+    assert_eq!(packed.matches("/Type /Page ").count(), 150);
+    assert!(packed.contains("/Type /Pages") && packed.contains("/Type /Catalog"));
+    assert!(packed.contains("/CreationDate"));
+
+    let err = Img2Pdf::new()
+        .object_streams(true)
+        .pdf_version(1, 4)
+        .convert_bytes(&img_data)
+        .expect_err("PDF 1.4 should be rejected");
+    assert!(err.to_string().contains("object streams need PDF 1.5"));
+    for converter in [
+        Img2Pdf::new().linearize(true),
+        Img2Pdf::new().encrypt(Encryption {
+            user_password: "user".to_string(),
+            owner_password: String::new(),
+            method: EncryptionMethod::Rc4,
+        }),
+    ] {
+        assert!(converter
+            .object_streams(true)
+            .convert_bytes(&img_data)
+            .is_err());
+    }
+}
+
+#[test]
+fn test_content_stream_length_is_exact() {
+    let img_data = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(3, 2)));
+    let pdf_data = img2pdf_from_bytes(&img_data).expect("Failed to convert image to PDF");
+
+    let (dict, content) = pdf_streams(&pdf_data)
+        .into_iter()
+        .find(|(dict, _)| !dict.contains("/Subtype"))
+        .expect("No content stream");
+    assert_eq!(dict, "<< /Length 27 >>");
+    assert_eq!(content, b"q\n3 0 0 2 0 0 cm\n/Img0 Do\nQ");
+}
+
+#[test]
+fn test_tiled_pages() {
+    let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(5, 3, |x, y| {
+        image::Rgb([x as u8 * 50, y as u8 * 100, 7])
+    }));
+    let img_data = encode_png(&img);
+
+    let pdf_data = img2pdf_tiled(&img_data, 2, 2).expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    let media_boxes: Vec<&str> = pdf
+        .split("/MediaBox [0 0 ")
+        .skip(1)
+        .map(|rest| rest.split(']').next().unwrap())
+        .collect();
+    assert_eq!(media_boxes, ["2 2", "2 2", "1 2", "2 1", "2 1", "1 1"]);
+
+    // The third tile of the first row is the top-right column.
+    let images: Vec<_> = pdf_streams(&pdf_data)
+        .into_iter()
+        .filter(|(dict, _)| dict.contains("/Subtype /Image"))
+        .map(|(_, data)| inflate(&data))
+        .collect();
+    assert_eq!(images[2], [200, 0, 7, 200, 100, 7]);
+
+    let pdf_data = Img2Pdf::new()
+        .convert_tiled(&img_data, 3, 2, 1)
+        .expect("Failed to convert image to PDF");
+    assert!(String::from_utf8_lossy(&pdf_data).contains("/Count 4"));
+    assert!(Img2Pdf::new().convert_tiled(&img_data, 2, 2, 2).is_err());
+
+    // Tiles keep the resolution of the image: a 600x300 image at 300 DPI is
+    // 144x72 pt, so each 300x300 tile is 72 pt square.
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, 600, 300);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: 11811,
+        yppu: 11811,
+        unit: png::Unit::Meter,
+    }));
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&[0; 600 * 300]))
+        .expect("Failed to encode PNG");
+    let pdf_data = img2pdf_tiled(&png_data, 300, 300).expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    let sizes: Vec<Vec<f32>> = pdf
+        .split("/MediaBox [0 0 ")
+        .skip(1)
+        .map(|rest| {
+            let size = rest.split(']').next().unwrap();
+            size.split(' ').map(|n| n.parse().unwrap()).collect()
+        })
+        .collect();
+    assert_eq!(sizes.len(), 2);
+    for size in sizes {
+        assert!(
+            size.iter().all(|&side| (side - 72.0).abs() < 0.01),
+            "{:?}",
+            size
+        );
+    }
+}
+
+#[test]
+fn test_target_dpi_downsamples_for_the_page() {
+    let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(1000, 500, |x, y| {
+        image::Rgb([x as u8, y as u8, 7])
+    }));
+    let img_data = encode_png(&img);
+    let convert = |converter: Img2Pdf| {
+        let pdf_data = converter
+            .convert_bytes(&img_data)
+            .expect("Failed to convert image to PDF");
+        String::from_utf8_lossy(&pdf_data).into_owned()
+    };
+
+    // At 72 DPI the image is 1000 pt wide, and 36 DPI needs half the pixels.
+    let pdf = convert(Img2Pdf::new().target_dpi(36.0));
+    assert!(pdf.contains("/Width 500 /Height 250 "));
+    assert!(pdf.contains("/MediaBox [0 0 1000 500]"));
+
+    // On a letter page the image is 8.5 inches wide.
+    let pdf = convert(Img2Pdf::new().page_size(PageSize::Letter).target_dpi(100.0));
+    assert!(pdf.contains("/Width 850 "));
+    assert!(pdf.contains("/MediaBox [0 0 612 792]"));
+
+    let pdf = convert(Img2Pdf::new().target_dpi(300.0));
+    assert!(pdf.contains("/Width 1000 /Height 500 "));
+    assert!(Img2Pdf::new()
+        .target_dpi(0.0)
+        .convert_bytes(&img_data)
+        .is_err());
+}
+
+#[test]
+fn test_max_dimension_downscales_large_images() {
+    let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(4000, 100, |x, _| {
+        image::Rgb([(x % 256) as u8, 0, 0])
+    }));
+    let pdf_data = img2pdf_from_bytes_with_max_dimension(&encode_png(&img), 2000)
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/Width 2000 /Height 50 "));
+    assert!(pdf.contains("/MediaBox [0 0 4000 100]"));
+
+    let jpeg = std::fs::read("examples/sample_image.jpg").expect("Failed to read image data");
+    let pdf_data = img2pdf_from_bytes_with_max_dimension(&jpeg, 100_000)
+        .expect("Failed to convert image to PDF");
+    assert!(pdf_data.windows(jpeg.len()).any(|w| w == jpeg));
+}
+
+#[test]
+fn test_all_frames_become_pages() {
+    let mut gif_data = Vec::new();
+    {
+        let mut encoder = image::codecs::gif::GifEncoder::new(&mut gif_data);
+        for shade in [0, 100, 200] {
+            let frame = image::RgbaImage::from_pixel(4, 3, image::Rgba([shade, 0, 0, 255]));
+            encoder
+                .encode_frame(image::Frame::new(frame))
+                .expect("Failed to encode GIF frame");
+        }
+    }
+
+    let count = |converter: Img2Pdf, img_data: &[u8]| {
+        let pdf_data = converter
+            .convert_bytes(img_data)
+            .expect("Failed to convert image to PDF");
+        String::from_utf8_lossy(&pdf_data)
+            .split("/Count ")
+            .nth(1)
+            .and_then(|rest| rest.split(' ').next()?.parse::<usize>().ok())
+            .expect("No /Count")
+    };
+    assert_eq!(count(Img2Pdf::new(), &gif_data), 1);
+    assert_eq!(count(Img2Pdf::new().all_frames(true), &gif_data), 3);
+
+    let mut tiff_data = io::Cursor::new(Vec::new());
+    {
+        let mut encoder =
+            tiff::encoder::TiffEncoder::new(&mut tiff_data).expect("Failed to create TIFF");
+        encoder
+            .write_image::<tiff::encoder::colortype::RGB8>(2, 2, &[255; 12])
+            .expect("Failed to encode TIFF page");
+        encoder
+            .write_image::<tiff::encoder::colortype::Gray8>(3, 1, &[0, 128, 255])
+            .expect("Failed to encode TIFF page");
+    }
+    let tiff_data = tiff_data.into_inner();
+    assert_eq!(count(Img2Pdf::new(), &tiff_data), 1);
+    assert_eq!(count(Img2Pdf::new().all_frames(true), &tiff_data), 2);
+}
+
+#[test]
+fn test_split_pages_keep_their_metadata() {
+    use tiff::encoder::{colortype, Rational};
+    use tiff::tags::{ResolutionUnit, Tag};
+
+    let media_boxes = |converter: Img2Pdf, img_data: &[u8]| -> Vec<String> {
+        let pdf_data = converter
+            .convert_bytes(img_data)
+            .expect("Failed to convert image to PDF");
+        String::from_utf8_lossy(&pdf_data)
+            .split("/MediaBox [")
+            .skip(1)
+            .map(|rest| rest.split(']').next().unwrap().to_string())
+            .collect()
+    };
+
+    // Each page of a 300 DPI TIFF keeps its size, and the second one its
+    // orientation.
+    let mut tiff_data = io::Cursor::new(Vec::new());
+    {
+        let mut encoder =
+            tiff::encoder::TiffEncoder::new(&mut tiff_data).expect("Failed to create TIFF");
+        for (width, height, orientation) in [(600, 300, 1u16), (300, 150, 6)] {
+            let mut image = encoder
+                .new_image::<colortype::Gray8>(width, height)
+                .expect("Failed to encode TIFF page");
+            image.resolution(ResolutionUnit::Inch, Rational { n: 300, d: 1 });
+            image
+                .encoder()
+                .write_tag(Tag::Orientation, orientation)
+                .expect("Failed to encode TIFF page");
+            image
+                .write_data(&vec![128; (width * height) as usize])
+                .expect("Failed to encode TIFF page");
+        }
+    }
+    let tiff_data = tiff_data.into_inner();
+    assert_eq!(media_boxes(Img2Pdf::new(), &tiff_data), ["0 0 144 72"]);
+    assert_eq!(
+        media_boxes(Img2Pdf::new().all_frames(true), &tiff_data),
+        ["0 0 144 72", "0 0 36 72"]
+    );
+
+    // The frames of a GIF keep its pixel aspect ratio, here 2:1.
+    let mut gif_data = Vec::new();
+    {
+        let mut encoder = image::codecs::gif::GifEncoder::new(&mut gif_data);
+        for shade in [0, 200] {
+            let frame = image::RgbaImage::from_pixel(4, 3, image::Rgba([shade, 0, 0, 255]));
+            encoder
+                .encode_frame(image::Frame::new(frame))
+                .expect("Failed to encode GIF frame");
+        }
+    }
+    gif_data[12] = 113;
+    assert_eq!(media_boxes(Img2Pdf::new(), &gif_data), ["0 0 8 3"]);
+    assert_eq!(
+        media_boxes(Img2Pdf::new().all_frames(true), &gif_data),
+        ["0 0 8 3", "0 0 8 3"]
+    );
+}
+
+#[test]
+fn test_append_image_to_pdf() {
+    let jpeg = std::fs::read("examples/sample_image.jpg").expect("Failed to read image data");
+    let png = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(30, 20)));
+    let original = img2pdf_from_bytes(&jpeg).expect("Failed to convert image to PDF");
+    let original_xref = parse::last_startxref(&original).unwrap();
+
+    let updated = append_image_to_pdf(&original, &png).expect("Failed to append image");
+    assert!(updated.starts_with(&original));
+    let update = String::from_utf8_lossy(&updated[original.len()..]);
+    assert!(update.contains(&format!("/Prev {}", original_xref)));
+    assert!(update.contains("/Kids [ 4 0 R 9 0 R ] /Count 2"));
+    assert!(update.contains("/MediaBox [0 0 30 20]"));
+
+    // The new section lists the new objects and the rewritten page tree.
+    let offsets = xref_offsets(&updated);
+    assert_eq!(offsets.first().map(|&(id, _)| id), Some(1));
+    for (id, offset) in offsets {
+        assert!(offset >= original.len());
+        assert!(updated[offset..].starts_with(format!("{} 0 obj\n", id).as_bytes()));
+    }
+    let id = |pdf: &[u8]| {
+        let trailer = pdf.windows(7).rposition(|w| w == b"trailer").unwrap();
+        parse::dict_id(parse::read_dict(pdf, trailer).unwrap()).unwrap()
+    };
+    assert_eq!(id(&updated)[0], id(&original)[0]);
+    assert_ne!(id(&updated)[1], id(&original)[1]);
+
+    let twice = append_image_to_pdf(&updated, &png).expect("Failed to append image");
+    assert!(String::from_utf8_lossy(&twice[updated.len()..]).contains("/Count 3"));
+
+    // Entries the pages inherit from the page tree are kept.
+    let trailer = parse::read_dict(&original, original_xref).unwrap();
+    let pages = parse::object_dict(&original, 1).expect("No /Pages");
+    let pages = parse::with_entry(pages, "/Rotate", "90");
+    let pages = parse::with_entry(&pages, "/Resources", "<< /ProcSet [/PDF /ImageC] >>");
+    let mut inherited = original.clone();
+    let pages_offset = inherited.len();
+    write!(inherited, "1 0 obj\n{}\nendobj\n", pages).unwrap();
+    let xref = inherited.len();
+    write!(
+        inherited,
+        "xref\n1 1\n{:010} 00000 n \ntrailer\n<< /Size {} /Root {} 0 R /Prev {} >>\n\
+        startxref\n{}\n%%EOF\n",
+        pages_offset,
+        parse::dict_int(trailer, "/Size").unwrap(),
+        parse::dict_ref(trailer, "/Root").unwrap(),
+        original_xref,
+        xref
+    )
+    .unwrap();
+    let updated = append_image_to_pdf(&inherited, &png).expect("Failed to append image");
+    let update = String::from_utf8_lossy(&updated[inherited.len()..]);
+    assert!(update.contains(
+        "/Kids [ 4 0 R 9 0 R ] /Count 2 /Rotate 90 /Resources << /ProcSet [/PDF /ImageC] >>"
+    ));
+
+    let streamed = Img2Pdf::new()
+        .xref_stream(true)
+        .convert_bytes(&jpeg)
+        .expect("Failed to convert image to PDF");
+    let updated = append_image_to_pdf(&streamed, &png).expect("Failed to append image");
+    let update = String::from_utf8_lossy(&updated[streamed.len()..]);
+    assert!(update.contains("/Type /XRef") && update.contains("/Index [1 1 "));
+    assert!(!update.contains("trailer"));
+
+    assert!(matches!(
+        append_image_to_pdf(b"not a pdf", &png),
+        Err(Img2PdfError::Unsupported(_))
+    ));
+
+    // A trailer nested far deeper than any real file is rejected, not
+    // followed until the stack runs out.
+    let mut nested = b"%PDF-1.4\nxref\n0 1\n0000000000 65535 f \ntrailer\n<< /ID ".to_vec();
+    nested.extend_from_slice(&[b'['; 100_000]);
+    nested.extend_from_slice(&[b']'; 100_000]);
+    nested.extend_from_slice(b" /Size 1 >>\nstartxref\n9\n%%EOF\n");
+    assert!(matches!(
+        append_image_to_pdf(&nested, &png),
+        Err(Img2PdfError::Unsupported(_))
+    ));
+
+    // New objects are numbered past every listed one, whatever `/Size` says.
+    let size = format!("/Size {} ", parse::dict_int(trailer, "/Size").unwrap());
+    let size_at = original_xref + find_bytes(&original[original_xref..], size.as_bytes()).unwrap();
+    let with_size = |wrong: &str| {
+        [
+            &original[..size_at],
+            wrong.as_bytes(),
+            &original[size_at + size.len()..],
+        ]
+        .concat()
+    };
+    for wrong in ["/Size 1 ", "/Size 3 "] {
+        let updated = append_image_to_pdf(&with_size(wrong), &png).expect("Failed to append image");
+        assert_eq!(validate_pdf(&updated), Ok(()));
+    }
+    let huge = with_size("/Size 4294967296 ");
+    assert!(matches!(
+        append_image_to_pdf(&huge, &png),
+        Err(Img2PdfError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn test_interpolate_and_intent() {
+    let img_data = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(3, 2)));
+
+    let pdf_data = img2pdf_from_bytes(&img_data).expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(!pdf.contains("/Interpolate") && !pdf.contains("/Intent"));
+
+    let pdf_data = Img2Pdf::new()
+        .interpolate(true)
+        .intent(RenderingIntent::Perceptual)
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    let (dict, _) = &pdf_streams(&pdf_data)[0];
+    assert!(dict.contains("/Interpolate true /Intent /Perceptual"));
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_parallel_conversion_matches_serial() {
+    let images: Vec<Vec<u8>> = (0..20u8)
+        .map(|i| {
+            encode_png(&DynamicImage::ImageRgb8(image::RgbImage::from_fn(
+                10 + i as u32,
+                8,
+                |x, y| image::Rgb([i * 10, (x * 8) as u8, y as u8 * 30]),
+            )))
+        })
+        .collect();
+    let images: Vec<&[u8]> = images.iter().map(Vec::as_slice).collect();
+
+    let serial = Img2Pdf::new()
+        .deterministic(true)
+        .convert_multiple_bytes(&images)
+        .expect("Failed to convert images to PDF");
+    let mut pages = Vec::new();
+    let parallel = Img2Pdf::new()
+        .deterministic(true)
+        .parallel(true)
+        .convert_multiple_bytes_with_progress(&images, |done, _| pages.push(done))
+        .expect("Failed to convert images to PDF");
+
+    assert_eq!(parallel, serial);
+    assert_eq!(pages, (1..=20).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_image_xobject() {
+    let img = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(3, 2, |x, _| {
+        image::Rgba([200, 10 * x as u8, 0, 100 * x as u8])
+    }));
+
+    let (bytes, meta) = image_xobject(&img, 7).expect("Failed to serialize image");
+    assert_eq!(
+        meta,
+        ImageMeta {
+            width: 3,
+            height: 2,
+            color_space: "/DeviceRGB".to_string(),
+            has_mask: true,
+            mask_offset: meta.mask_offset,
+        }
+    );
+    assert!(bytes.starts_with(b"7 0 obj\n<< /Type /XObject /Subtype /Image /Width 3 /Height 2"));
+    assert!(find_bytes(&bytes, b"/SMask 8 0 R").is_some());
+    let mask_offset = meta.mask_offset.expect("No soft mask");
+    assert!(bytes[mask_offset..].starts_with(b"8 0 obj\n"));
+    assert!(bytes.ends_with(b"endobj\n"));
+
+    let opaque = DynamicImage::ImageLuma8(image::GrayImage::new(4, 4));
+    let (bytes, meta) = image_xobject(&opaque, 1).expect("Failed to serialize image");
+    assert_eq!(meta.color_space, "/DeviceGray");
+    assert!(!meta.has_mask && meta.mask_offset.is_none());
+    assert_eq!(pdf_streams(&[b"\n".as_slice(), &bytes].concat()).len(), 1);
+}
+
+#[test]
+fn test_contact_sheet() {
+    let wide = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(20, 10)));
+    let tall = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(10, 20)));
+    let images = [wide.as_slice(), tall.as_slice(), wide.as_slice()];
+
+    let pdf_data = img2pdf_contact_sheet(&images, 2, 50.0).expect("Failed to build contact sheet");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/Count 1 >>"));
+    assert!(pdf.contains("/MediaBox [0 0 100 100]"));
+    assert!(pdf.contains("/XObject << /Img0 2 0 R /Img1 3 0 R /Img2 4 0 R >>"));
+    let content = content_stream(&pdf_data);
+    assert_eq!(
+        String::from_utf8(content).unwrap(),
+        "q\n50 0 0 25 0 62.5 cm\n/Img0 Do\nQ\n\
+         q\n25 0 0 50 62.5 50 cm\n/Img1 Do\nQ\n\
+         q\n50 0 0 25 0 12.5 cm\n/Img2 Do\nQ"
+    );
+
+    // Fixed-size pages continue the grid on the next page.
+    let pdf_data = Img2Pdf::new()
+        .page_size(PageSize::Custom {
+            width_pt: 100.0,
+            height_pt: 60.0,
+        })
+        .convert_contact_sheet(&images, 2, 50.0)
+        .expect("Failed to build contact sheet");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/Count 2 >>"));
+    assert!(pdf.contains("q\n50 0 0 25 0 22.5 cm\n/Img0 Do\nQ"));
+
+    assert!(img2pdf_contact_sheet(&images, 0, 50.0).is_err());
+    assert!(Img2Pdf::new()
+        .page_size(PageSize::A4)
+        .convert_contact_sheet(&images, 20, 50.0)
+        .is_err());
+}
+
+#[test]
+fn test_packed_pages() {
+    let small = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(100, 50)));
+    let wide = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(500, 100)));
+    let converter = Img2Pdf::new().page_size(PageSize::Custom {
+        width_pt: 250.0,
+        height_pt: 130.0,
+    });
+
+    // Two rows of two fit with 10 pt of spacing, and the fifth image, which
+    // is too wide for the page, is scaled down and starts a new page.
+    let images = [small.as_slice(); 4]
+        .into_iter()
+        .chain([wide.as_slice()])
+        .collect::<Vec<_>>();
+    let pdf_data = converter
+        .convert_packed(&images, 10.0)
+        .expect("Failed to pack images");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/Count 2 >>"));
+    let contents: Vec<String> = pdf_streams(&pdf_data)
+        .into_iter()
+        .filter(|(_, data)| data.starts_with(b"q\n"))
+        .map(|(_, data)| String::from_utf8(data).unwrap())
+        .collect();
+    assert_eq!(
+        contents,
+        [
+            "q\n100 0 0 50 0 80 cm\n/Img0 Do\nQ\n\
+             q\n100 0 0 50 110 80 cm\n/Img1 Do\nQ\n\
+             q\n100 0 0 50 0 20 cm\n/Img2 Do\nQ\n\
+             q\n100 0 0 50 110 20 cm\n/Img3 Do\nQ",
+            "q\n250 0 0 50 0 80 cm\n/Img0 Do\nQ",
+        ]
+    );
+
+    // Pages sized to fit are A4.
+    let pdf_data = img2pdf_packed(&[&small, &small], 0.0).expect("Failed to pack images");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/Count 1 >>") && pdf.contains("/MediaBox [0 0 595.28 841.89]"));
+
+    assert!(img2pdf_packed(&[&small], -1.0).is_err());
+    assert!(img2pdf_packed(&[], 0.0).is_err());
+}
+
+#[test]
+fn test_border_is_stroked_around_the_image() {
+    let img_data = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(100, 50)));
+    let content_of = |border: Border| {
+        let pdf_data = Img2Pdf::new()
+            .border(border)
+            .convert_bytes(&img_data)
+            .expect("Failed to convert image to PDF");
+        let content = content_stream(&pdf_data);
+        String::from_utf8(content).unwrap()
+    };
+
+    let border = Border {
+        width_pt: 2.0,
+        color: [255, 0, 51],
+        outside: false,
+    };
+    assert_eq!(
+        content_of(border),
+        "q\n100 0 0 50 0 0 cm\n/Img0 Do\nQ\nq\n1 0 0.2 RG\n2 w\n0 0 100 50 re\nS\nQ"
+    );
+    assert!(content_of(Border {
+        outside: true,
+        ..border
+    })
+    .ends_with("\n2 w\n-1 -1 102 52 re\nS\nQ"));
+
+    assert!(Img2Pdf::new()
+        .border(Border {
+            width_pt: -1.0,
+            ..border
+        })
+        .convert_bytes(&img_data)
+        .is_err());
+}
+
+#[test]
+fn test_background_fills_the_page() {
+    let img_data = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(10, 10)));
+    let pdf_data = Img2Pdf::new()
+        .margins(Margins::uniform(5.0))
+        .background([0, 51, 255])
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    let content = content_stream(&pdf_data);
+    assert_eq!(
+        String::from_utf8(content).unwrap(),
+        "q\n0 0.2 1 rg\n0 0 20 20 re\nf\nQ\nq\n10 0 0 10 5 5 cm\n/Img0 Do\nQ"
+    );
+}
+
+#[test]
+fn test_convert_into_reuses_the_buffer() {
+    let img_data = std::fs::read("examples/sample_image.jpg").expect("Failed to read image");
+    let converter = Img2Pdf::new().deterministic(true);
+    let expected = converter
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+
+    let mut out = b"previous contents".to_vec();
+    converter
+        .convert_into(&[&img_data], &mut out)
+        .expect("Failed to convert image to PDF");
+    assert_eq!(out, expected);
+    let capacity = out.capacity();
+    assert!(capacity >= img_data.len());
+    converter
+        .convert_into(&[&img_data], &mut out)
+        .expect("Failed to convert image to PDF");
+    assert_eq!(out, expected);
+    assert_eq!(out.capacity(), capacity);
+}
+
+#[test]
+fn test_png_predictor() {
+    // Streams with Predictor 15 hold the same data as a PNG's IDAT chunks.
+    fn to_png(dict: &str, data: &[u8], color_type: u8) -> DynamicImage {
+        let number = |key: &str| -> u32 {
+            dict.split(key)
+                .nth(1)
+                .unwrap()
+                .split(' ')
+                .nth(1)
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+        let mut header = number("/Width").to_be_bytes().to_vec();
+        header.extend_from_slice(&number("/Height").to_be_bytes());
+        header.extend_from_slice(&[number("/BitsPerComponent") as u8, color_type, 0, 0, 0]);
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, chunk) in [(b"IHDR", &header[..]), (b"IDAT", data), (b"IEND", &[])] {
+            png.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+            let mut crc = flate2::Crc::new();
+            crc.update(kind);
+            crc.update(chunk);
+            png.extend_from_slice(kind);
+            png.extend_from_slice(chunk);
+            png.extend_from_slice(&crc.sum().to_be_bytes());
+        }
+        image::load_from_memory(&png).expect("Failed to decode predicted samples")
+    }
+
+    let img = image::RgbaImage::from_fn(40, 30, |x, y| {
+        image::Rgba([
+            (x * 3) as u8,
+            (y * 4) as u8,
+            (x + y) as u8 * 2,
+            (x * 6) as u8,
+        ])
+    });
+    let img_data = encode_png(&DynamicImage::ImageRgba8(img.clone()));
+    let pdf_data = Img2Pdf::new()
+        .png_predictor(true)
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    let streams = pdf_streams(&pdf_data);
+    let (dict, data) = &streams[0];
+    assert!(dict.contains(
+        "/Filter /FlateDecode /DecodeParms << /Predictor 15 /Colors 3 /BitsPerComponent 8 /Columns 40 >>"
+    ));
+    let rgb = DynamicImage::ImageRgba8(img.clone()).to_rgb8();
+    assert_eq!(to_png(dict, data, 2).to_rgb8(), rgb);
+    let (dict, data) = &streams[1];
+    assert!(
+        dict.contains("/DecodeParms << /Predictor 15 /Colors 1 /BitsPerComponent 8 /Columns 40 >>")
+    );
+    let alpha: Vec<u8> = img.pixels().map(|pixel| pixel[3]).collect();
+    assert_eq!(to_png(dict, data, 0).to_luma8().into_raw(), alpha);
+
+    // Smooth images shrink, and 16-bit samples predict from whole pixels.
+    let plain = img2pdf_from_bytes(&img_data).expect("Failed to convert image to PDF");
+    assert!(pdf_data.len() < plain.len());
+    assert!(!String::from_utf8_lossy(&plain).contains("/DecodeParms"));
+    let img = image::ImageBuffer::from_fn(20, 10, |x, y| {
+        image::Rgb([x as u16 * 3000, y as u16 * 500, 7])
+    });
+    let img = DynamicImage::ImageRgb16(img);
+    let pdf_data = Img2Pdf::new()
+        .png_predictor(true)
+        .convert_bytes(&encode_png(&img))
+        .expect("Failed to convert image to PDF");
+    let (dict, data) = &pdf_streams(&pdf_data)[0];
+    assert!(dict.contains("/Colors 3 /BitsPerComponent 16 /Columns 20 >>"));
+    assert_eq!(to_png(dict, data, 2).to_rgb16(), img.to_rgb16());
+}
+
+#[test]
+fn test_passthrough_color_spaces() {
+    let lab = PassthroughColorSpace::Lab {
+        white_point: [0.9642, 1.0, 0.8249],
+        range: [-128.0, 127.0, -128.0, 127.0],
+    };
+    let samples = [255, 128, 128, 50, 200, 30];
+    let pdf_data = Img2Pdf::new()
+        .uncompressed(true)
+        .convert_samples(&samples, 2, 1, &lab)
+        .expect("Failed to convert samples to PDF");
+    let (dict, data) = &pdf_streams(&pdf_data)[0];
+    assert!(dict.contains(
+        "/ColorSpace [/Lab << /WhitePoint [0.9642 1 0.8249] /Range [-128 127 -128 127] >>] /BitsPerComponent 8"
+    ));
+    assert_eq!(data, &samples);
+
+    let spot = PassthroughColorSpace::DeviceN {
+        colorants: vec![
+            ("PANTONE 185 C".to_string(), [0.0, 0.9, 0.8, 0.0]),
+            ("Gold".to_string(), [0.0, 0.2, 0.7, 0.1]),
+        ],
+    };
+    let pdf_data = img2pdf_from_samples(&[255, 0, 0, 255], 2, 1, &spot)
+        .expect("Failed to convert samples to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/ColorSpace [/DeviceN [/PANTONE#20185#20C /Gold] /DeviceCMYK 3 0 R]"));
+    assert!(pdf.contains("/FunctionType 4 /Domain [0 1 0 1] /Range [0 1 0 1 0 1 0 1]"));
+
+    // The tints are added up per CMYK component; `n - i + component index`
+    // reaches tint i below the components done and the running sum.
+    let one = [("Spot".to_string(), [0.0, 1.0, 0.5, 0.0])];
+    assert_eq!(
+        PassthroughColorSpace::tint_transform(&one),
+        "{ 0 1 index 0 mul add dup 1 gt { pop 1 } if 0 2 index 1 mul add dup 1 gt { pop 1 } if \
+         0 3 index 0.5 mul add dup 1 gt { pop 1 } if 0 4 index 0 mul add dup 1 gt { pop 1 } if \
+         5 4 roll pop }"
+    );
+
+    assert!(img2pdf_from_samples(&samples[..5], 2, 1, &lab).is_err());
+    assert!(img2pdf_from_samples(&samples, u32::MAX, u32::MAX, &lab).is_err());
+    let unlit = PassthroughColorSpace::Lab {
+        white_point: [f32::NAN, 1.0, 0.8249],
+        range: [-128.0, 127.0, -128.0, 127.0],
+    };
+    assert!(img2pdf_from_samples(&samples, 2, 1, &unlit).is_err());
+    let murky = PassthroughColorSpace::DeviceN {
+        colorants: vec![("Spot".to_string(), [0.0, f32::NAN, 0.5, 0.0])],
+    };
+    assert!(img2pdf_from_samples(&[255, 0], 2, 1, &murky).is_err());
+    let none = PassthroughColorSpace::DeviceN {
+        colorants: Vec::new(),
+    };
+    assert!(img2pdf_from_samples(&[], 1, 1, &none).is_err());
+}
+
+#[test]
+fn test_image_resource_name() {
+    let img_data = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(2, 2)));
+    let resources_and_content = |converter: Img2Pdf| {
+        let pdf_data = converter
+            .convert_bytes(&img_data)
+            .expect("Failed to convert image to PDF");
+        let pdf = String::from_utf8_lossy(&pdf_data);
+        let resource = pdf
+            .split("/XObject << ")
+            .nth(1)
+            .and_then(|rest| rest.split(' ').next())
+            .expect("No XObject resources")
+            .to_string();
+        let content = content_stream(&pdf_data);
+        let drawn = String::from_utf8(content)
+            .unwrap()
+            .split('\n')
+            .find_map(|line| line.strip_suffix(" Do").map(str::to_string))
+            .expect("No image drawn");
+        (resource, drawn)
+    };
+
+    let (resource, drawn) = resources_and_content(Img2Pdf::new());
+    assert_eq!(resource, "/Img0");
+    assert_eq!(drawn, resource);
+    let (resource, drawn) = resources_and_content(Img2Pdf::new().image_resource_name("/Scan"));
+    assert_eq!(resource, "/Scan0");
+    assert_eq!(drawn, resource);
+    assert!(Img2Pdf::new()
+        .image_resource_name("two words")
+        .convert_bytes(&img_data)
+        .is_err());
+}
+
+#[test]
+fn test_img2pdf_from_raw() {
+    let rgba = [255, 0, 0, 255, 0, 0, 255, 128];
+    let pdf_data = Img2Pdf::new()
+        .uncompressed(true)
+        .convert_raw(2, 1, &rgba, PixelFormat::Rgba8)
+        .expect("Failed to convert pixels to PDF");
+    let streams = pdf_streams(&pdf_data);
+    assert!(streams[0]
+        .0
+        .contains("/Width 2 /Height 1 /ColorSpace /DeviceRGB"));
+    assert_eq!(streams[0].1, [255, 0, 0, 0, 0, 255]);
+    assert_eq!(streams[1].1, [255, 128]);
+
+    // Raw pixels come out the same as the PNG of them.
+    let gray: Vec<u8> = (0..12).map(|i| i * 20).collect();
+    let png = encode_png(&DynamicImage::ImageLuma8(
+        image::GrayImage::from_raw(4, 3, gray.clone()).unwrap(),
+    ));
+    let converter = Img2Pdf::new().deterministic(true);
+    let from_raw = |format| converter.convert_raw(4, 3, &gray, format);
+    let from_png = converter.convert_bytes(&png).unwrap();
+    assert_eq!(
+        pdf_streams(&from_raw(PixelFormat::Gray8).unwrap()),
+        pdf_streams(&from_png)
+    );
+
+    let err = from_raw(PixelFormat::Rgb8).expect_err("Too few bytes should be rejected");
+    assert_eq!(
+        err.to_string(),
+        "Unsupported: 4x3 Rgb8 pixels need 36 bytes, got 12"
+    );
+    assert!(img2pdf_from_raw(0, 3, &[], PixelFormat::GrayA8).is_err());
+}
+
+#[test]
+fn test_img2pdf_from_image() {
+    let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(4, 3, |x, y| {
+        image::Rgb([x as u8 * 60, y as u8 * 80, 200])
+    }));
+    let converter = Img2Pdf::new().deterministic(true);
+    let from_png = converter.convert_bytes(&encode_png(&img)).unwrap();
+    let from_image = converter
+        .convert_image(img)
+        .expect("Failed to convert image to PDF");
+    assert_eq!(pdf_streams(&from_image), pdf_streams(&from_png));
+    assert_eq!(validate_pdf(&from_image), Ok(()));
+
+    let empty = DynamicImage::ImageLuma8(image::GrayImage::new(0, 3));
+    let err = img2pdf_from_image(empty).expect_err("An empty image should be rejected");
+    assert_eq!(err.to_string(), "Unsupported: image has no area (0x3)");
+}
+
+#[test]
+fn test_build_pdf_from_mixed_sources() {
+    let cover = vec![200u8; 6 * 4 * 3];
+    let scan = "examples/sample_image.jpg";
+    let png = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(10, 5)));
+    let pdf_data = build_pdf(&[
+        PageSource::Raw {
+            width: 6,
+            height: 4,
+            pixels: cover,
+            format: PixelFormat::Rgb8,
+        },
+        PageSource::File(PathBuf::from(scan)),
+        PageSource::Bytes(png),
+    ])
+    .expect("Failed to build PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/Count 3"));
+    let media_boxes: Vec<&str> = pdf
+        .split("/MediaBox [0 0 ")
+        .skip(1)
+        .map(|rest| rest.split(']').next().unwrap())
+        .collect();
+    assert_eq!(media_boxes, ["6 4", "1200 1600", "10 5"]);
+
+    let missing = PageSource::File(PathBuf::from("examples/missing.png"));
+    let err = build_pdf(&[missing]).expect_err("A missing file should be rejected");
+    assert!(err.to_string().contains("examples/missing.png"));
+    assert!(build_pdf(&[]).is_err());
+}
+
+#[test]
+fn test_page_labels() {
+    let png = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(10, 5)));
+    let pdf_data = Img2Pdf::new()
+        .page_labels([
+            PageLabel::literal(0, "Cover"),
+            PageLabel::numbered(1, NumberingStyle::LowerRoman),
+            PageLabel {
+                first_page: 3,
+                style: Some(NumberingStyle::Decimal),
+                prefix: "A-".to_string(),
+                start: 7,
+            },
+        ])
+        .convert_multiple_bytes(&[&png, &png, &png, &png])
+        .expect("Failed to convert images to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains(
+        "/PageLabels << /Nums [0 << /P (Cover) >> 1 << /S /r >> 3 << /S /D /P (A-) /St 7 >>] >>"
+    ));
+
+    let convert = |labels: Vec<PageLabel>| {
+        Img2Pdf::new()
+            .page_labels(labels)
+            .convert_bytes(&png)
+            .expect_err("Invalid page labels should be rejected")
+            .to_string()
+    };
+    let err = convert(vec![PageLabel::numbered(1, NumberingStyle::Decimal)]);
+    assert!(err.contains("must start at page 0"));
+    let err = convert(vec![
+        PageLabel::numbered(0, NumberingStyle::Decimal),
+        PageLabel::numbered(0, NumberingStyle::UpperLetters),
+    ]);
+    assert!(err.contains("must be in order"));
+    let err = convert(vec![PageLabel {
+        start: 0,
+        ..PageLabel::numbered(0, NumberingStyle::Decimal)
+    }]);
+    assert!(err.contains("numbered from 1"));
+}
+
+#[test]
+fn test_outline_bookmarks_each_page() {
+    let png = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(10, 5)));
+    let pdf_data = Img2Pdf::new()
+        .convert_named(&[("cover.png", &png), ("", &png), ("back.png", &png)])
+        .expect("Failed to convert images to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    let kids = pdf
+        .split("/Kids [ ")
+        .nth(1)
+        .unwrap()
+        .split(" ]")
+        .next()
+        .unwrap();
+    let pages: Vec<&str> = kids.split(" 0 R").map(str::trim).collect();
+    // The catalog points at the outline, which is the parent of the items.
+    let catalog = pdf.split("/Type /Catalog").nth(1).unwrap();
+    let outline = catalog
+        .split("/Outlines ")
+        .nth(1)
+        .unwrap()
+        .split(' ')
+        .next()
+        .unwrap();
+    assert!(catalog.contains("/PageMode /UseOutlines"));
+    assert!(pdf.contains(&format!("{} 0 obj\n<< /Type /Outlines", outline)));
+    assert!(pdf.contains(&format!("/Title (cover.png) /Parent {} 0 R", outline)));
+    assert!(pdf.contains(&format!("/Dest [{} 0 R /Fit]", pages[0])));
+    assert!(!pdf.contains(&format!("/Dest [{} 0 R /Fit]", pages[1])));
+    assert!(pdf.contains("/Title (back.png) /Parent"));
+    assert!(pdf.contains(&format!("/Dest [{} 0 R /Fit]", pages[2])));
+
+    let pages = [
+        PageSource::File(PathBuf::from("examples/sample_image.jpg")),
+        PageSource::Bytes(png.clone()),
+    ];
+    let pdf_data = Img2Pdf::new()
+        .outline(true)
+        .convert_pages(&pages)
+        .expect("Failed to build PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/Title (sample_image.jpg)"));
+    assert!(pdf.contains("/Count 1 >>"));
+    // Without the option, file names make no outline.
+    let pdf_data = build_pdf(&pages).expect("Failed to build PDF");
+    assert!(find_bytes(&pdf_data, b"/Outlines").is_none());
+}
+
+#[test]
+#[cfg(target_pointer_width = "64")]
+fn test_xref_table_offsets_are_limited() {
+    let writer = || {
+        let mut pdf = PdfWriter::new(Vec::new(), "1.5").unwrap();
+        let id = pdf.reserve_object_id();
+        *pdf.offsets.last_mut().unwrap() = 10_000_000_000;
+        let trailer = Trailer {
+            root_object_id: id,
+            info_object_id: None,
+            id: None,
+            encrypt_object_id: None,
+            prev: None,
+        };
+        (pdf, trailer)
+    };
+
+    let (pdf, trailer) = writer();
+    assert_eq!(pdf.table_overflow(), Some(10_000_000_000));
+    let err = pdf
+        .finish(&trailer)
+        .expect_err("An 11-digit offset should be rejected");
+    assert!(err.to_string().contains("does not fit the 10 digits"));
+    let (pdf, trailer) = writer();
+    assert!(pdf.finish_with_xref_stream(&trailer).is_ok());
+}
+
+#[test]
+fn test_thumbnails() {
+    let img = image::RgbaImage::from_fn(424, 212, |x, _| {
+        if x < 212 {
+            image::Rgba([255, 0, 0, 255])
+        } else {
+            image::Rgba([0, 0, 0, 0])
+        }
+    });
+    let img_data = encode_png(&DynamicImage::ImageRgba8(img));
+    let pdf_data = Img2Pdf::new()
+        .thumbnails(true)
+        .uncompressed(true)
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    let text = String::from_utf8_lossy(&pdf_data);
+    assert!(text.contains(" /Thumb 4 0 R"));
+
+    let (dict, data) = pdf_streams(&pdf_data)
+        .into_iter()
+        .find(|(dict, _)| dict.starts_with("<< /Width 106"))
+        .expect("No thumbnail");
+    assert_eq!(
+        dict,
+        "<< /Width 106 /Height 53 /ColorSpace /DeviceRGB /BitsPerComponent 8 /Length 16854 >>"
+    );
+    // The transparent half is white.
+    assert_eq!(&data[..3], [255, 0, 0]);
+    assert_eq!(&data[data.len() - 3..], [255, 255, 255]);
+
+    let plain = img2pdf_from_bytes(&img_data).expect("Failed to convert image to PDF");
+    assert!(!String::from_utf8_lossy(&plain).contains("/Thumb"));
+}
+
+#[test]
+fn test_transparent_page() {
+    let img_data = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(10, 10)));
+    let converter = Img2Pdf::new()
+        .margins(Margins::uniform(5.0))
+        .transparent_page(true);
+    let pdf_data = converter
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+
+    let page = pdf.split("/Type /Page ").nth(1).expect("No page");
+    assert!(page.contains("/Group << /S /Transparency /CS /DeviceRGB >>"));
+    let content = content_stream(&pdf_data);
+    assert_eq!(
+        String::from_utf8(content).unwrap(),
+        "q\n10 0 0 10 5 5 cm\n/Img0 Do\nQ"
+    );
+
+    let err = converter
+        .clone()
+        .background([0, 51, 255])
+        .convert_bytes(&img_data)
+        .expect_err("A transparent page with a background should be rejected");
+    assert_eq!(
+        err.to_string(),
+        "Unsupported: a transparent page cannot have a background"
+    );
+
+    let plain = img2pdf_from_bytes(&img_data).expect("Failed to convert image to PDF");
+    assert!(!String::from_utf8_lossy(&plain).contains("/Group"));
+    assert!(converter.pdfa(true).convert_bytes(&img_data).is_err());
+}
+
+/// A 1x1 lossless WebP image.
+#[cfg(test)]
+const WEBP_PIXEL: &[u8] =
+    b"RIFF\x1a\0\0\0WEBPVP8L\x0d\0\0\0\x2f\0\0\0\x10\x07\x10\x11\x11\x88\x88\xfe\x07\0";
+
+/// The start of an AVIF file, up to its brand.
+#[cfg(test)]
+const AVIF_HEADER: &[u8] = b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf";
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_webp_is_decoded() {
+    let pdf_data = img2pdf_from_bytes(WEBP_PIXEL).expect("Failed to convert WebP to PDF");
+    assert!(find_bytes(&pdf_data, b"/Width 1 /Height 1").is_some());
+}
+
+#[cfg(not(feature = "webp"))]
+#[test]
+fn test_webp_needs_its_feature() {
+    let error = img2pdf_from_bytes(WEBP_PIXEL).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Unsupported: format WebP not supported; enable feature `webp`"
+    );
+}
+
+#[cfg(feature = "avif")]
+#[test]
+fn test_avif_is_handed_to_its_decoder() {
+    // The header alone is not a valid image, but it reaches the decoder.
+    let error = img2pdf_from_bytes(AVIF_HEADER).unwrap_err();
+    assert!(!error.to_string().contains("enable feature"));
+}
+
+#[cfg(not(feature = "avif"))]
+#[test]
+fn test_avif_needs_its_feature() {
+    let error = img2pdf_from_bytes(AVIF_HEADER).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Unsupported: format AVIF not supported; enable feature `avif`"
+    );
+}
+
+#[test]
+fn test_pdf_version() {
+    let img_data = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(2, 2)));
+
+    let pdf_data = Img2Pdf::new()
+        .pdf_version(1, 7)
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    assert!(pdf_data.starts_with(b"%PDF-1.7\n"));
+    let xref_stream = Img2Pdf::new().xref_stream(true).convert_bytes(&img_data);
+    assert!(xref_stream.unwrap().starts_with(b"%PDF-1.5\n"));
+
+    assert!(Img2Pdf::new()
+        .pdf_version(1, 4)
+        .xref_stream(true)
+        .convert_bytes(&img_data)
+        .is_err());
+    assert!(Img2Pdf::new()
+        .pdf_version(1, 9)
+        .convert_bytes(&img_data)
+        .is_err());
+
+    // Appending raises the version through the catalog.
+    let existing = img2pdf_from_bytes(&img_data).expect("Failed to convert image to PDF");
+    let updated = Img2Pdf::new()
+        .pdf_version(1, 6)
+        .append_bytes(&existing, &img_data)
+        .expect("Failed to append image");
+    let catalog = parse::object_dict(&updated, 5).expect("No catalog");
+    assert_eq!(parse::dict_version(catalog), Some((1, 6)));
+    assert_eq!(parse::dict_ref(catalog, "/Pages"), Some(1));
+}
+
+#[test]
+fn test_header_has_binary_comment() {
+    let img_data = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(2, 2)));
+    let pdf_data = img2pdf_from_bytes(&img_data).expect("Failed to convert image to PDF");
+
+    assert_eq!(&pdf_data[..4], b"%PDF");
+    let second_line = &pdf_data[find_bytes(&pdf_data, b"\n").unwrap() + 1..];
+    assert_eq!(second_line[0], b'%');
+    assert!(second_line[1..5].iter().all(|&b| b >= 128));
+    assert_eq!(second_line[5], b'\n');
+}
+
+#[test]
+fn test_encryption() {
+    let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(8, 8, |x, y| {
+        image::Rgb([(x * 30) as u8, (y * 30) as u8, 7])
+    }));
+    let img_data = encode_png(&img);
+    let plain = Img2Pdf::new()
+        .deterministic(true)
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    let encrypt = |method| {
+        Img2Pdf::new()
+            .deterministic(true)
+            .title("Secret")
+            .encrypt(Encryption {
+                user_password: "user".to_string(),
+                owner_password: String::new(),
+                method,
+            })
+            .convert_bytes(&img_data)
+            .expect("Failed to convert image to PDF")
+    };
+
+    let image_stream = |pdf_data: &[u8]| {
+        pdf_streams(pdf_data)
+            .into_iter()
+            .find(|(dict, _)| dict.contains("/Subtype /Image"))
+            .expect("No image stream")
+            .1
+    };
+    let plain_stream = image_stream(&plain);
+
+    let aes = encrypt(EncryptionMethod::Aes128);
+    let pdf = String::from_utf8_lossy(&aes);
+    assert!(aes.starts_with(b"%PDF-1.6\n"));
+    assert!(pdf.contains("/Encrypt 7 0 R"));
+    let encrypt_dict = parse::object_dict(&aes, 7).expect("No encryption dictionary");
+    assert!(encrypt_dict.contains("/Filter /Standard /V 4 /R 4"));
+    assert!(encrypt_dict.contains("/CFM /AESV2"));
+    // Strings are encrypted too, into hex strings.
+    assert!(!pdf.contains("(D:") && !pdf.contains("(Secret)"));
+    // An initialization vector, then the padded data.
+    let aes_stream = image_stream(&aes);
+    assert_eq!(aes_stream.len(), 16 + (plain_stream.len() / 16 + 1) * 16);
+
+    let rc4 = encrypt(EncryptionMethod::Rc4);
+    assert!(rc4.starts_with(b"%PDF-1.4\n"));
+    let encrypt_dict = parse::object_dict(&rc4, 7).expect("No encryption dictionary");
+    assert!(encrypt_dict.contains("/Filter /Standard /V 2 /R 3 /Length 128"));
+    let rc4_stream = image_stream(&rc4);
+    assert_eq!(rc4_stream.len(), plain_stream.len());
+    assert_ne!(rc4_stream, plain_stream);
+
+    assert!(Img2Pdf::new()
+        .pdf_version(1, 5)
+        .encrypt(Encryption {
+            user_password: "user".to_string(),
+            owner_password: String::new(),
+            method: EncryptionMethod::Aes128,
+        })
+        .convert_bytes(&img_data)
+        .is_err());
+    assert!(Img2Pdf::new().append_bytes(&aes, &img_data).is_err());
+}
+
+#[test]
+fn test_linearized() {
+    let gray = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(4, 4)));
+    let rgba = encode_png(&DynamicImage::ImageRgba8(image::RgbaImage::from_fn(
+        4,
+        4,
+        |x, y| image::Rgba([9, 8, 7, (x * y * 10) as u8]),
+    )));
+    let pdf_data = Img2Pdf::new()
+        .linearize(true)
+        .title("Linearized (3 0 R)")
+        .convert_multiple_bytes(&[&rgba, &gray, &rgba])
+        .expect("Failed to convert images to PDF");
+    let text_at = |offset: usize| String::from_utf8_lossy(&pdf_data[offset..]).into_owned();
+
+    // The linearization dictionary is the first object.
+    let header_end = find_bytes(&pdf_data, b"%\xE2\xE3\xCF\xD3\n").unwrap() + 6;
+    let first_object = text_at(header_end).lines().next().unwrap().to_string();
+    let linearization_id: u32 = first_object
+        .strip_suffix(" 0 obj")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let linearization = parse::read_dict(&pdf_data, header_end).unwrap();
+    let int = |key| parse::dict_int(linearization, key).unwrap() as usize;
+    assert_eq!(int("/Linearized"), 1);
+    assert_eq!(int("/L"), pdf_data.len());
+    assert_eq!(int("/N"), 3);
+
+    // Both cross-reference tables list offsets of the objects they number.
+    let xref_table = |offset: usize| -> Vec<(u32, usize)> {
+        let table = text_at(offset);
+        assert!(table.starts_with("xref\n"));
+        let mut lines = table.lines().skip(1);
+        let section: Vec<u32> = lines
+            .next()
+            .unwrap()
+            .split(' ')
+            .map(|n| n.parse().unwrap())
+            .collect();
+        (section[0]..section[0] + section[1])
+            .zip(lines)
+            .filter(|(id, _)| *id != 0)
+            .map(|(id, line)| (id, line[..10].parse().unwrap()))
+            .collect()
+    };
+    let first_xref = parse::last_startxref(&pdf_data).unwrap();
+    assert!(find_bytes(&pdf_data, b"xref\n").unwrap() == first_xref);
+    let first_trailer = parse::read_dict(&pdf_data, first_xref).unwrap();
+    let main_xref = parse::dict_int(first_trailer, "/Prev").unwrap() as usize;
+    let size = parse::dict_int(first_trailer, "/Size").unwrap() as u32;
+    let first_entries = xref_table(first_xref);
+    let main_entries = xref_table(main_xref);
+    assert_eq!(first_entries[0], (linearization_id, header_end));
+    assert_eq!(first_entries.last().unwrap().0, size - 1);
+    assert_eq!(main_entries[0].0, 1);
+    assert_eq!(main_entries.len() + 1, linearization_id as usize);
+    assert_eq!(
+        int("/T"),
+        main_xref + find_bytes(&pdf_data[main_xref..], b"\n0000000000 65535 f").unwrap()
+    );
+    let entries: Vec<(u32, usize)> = first_entries.into_iter().chain(main_entries).collect();
+    for &(id, offset) in &entries {
+        assert!(text_at(offset).starts_with(&format!("{} 0 obj\n", id)));
+    }
+    let offset_of = |id| entries.iter().find(|entry| entry.0 == id).unwrap().1;
+
+    // The hint stream and the first page with its objects end at `/E`, and
+    // the other pages come after it.
+    let hint_offset: usize = linearization
+        .split("/H [ ")
+        .nth(1)
+        .and_then(|rest| rest.split(' ').next()?.parse().ok())
+        .expect("No /H");
+    let hint = parse::read_dict(&pdf_data, hint_offset).unwrap();
+    assert!(hint.starts_with("<< /S "));
+    assert!(pdf_data[..int("/E")].ends_with(b"endobj\n"));
+    let first_page = int("/O") as u32;
+    let page = parse::read_dict(&pdf_data, offset_of(first_page)).unwrap();
+    assert!(page.contains("/Type /Page "));
+    let contents = parse::dict_ref(page, "/Contents").unwrap();
+    assert!(offset_of(contents) < int("/E"));
+    let catalog = parse::read_dict(
+        &pdf_data,
+        offset_of(parse::dict_ref(first_trailer, "/Root").unwrap()),
+    )
+    .unwrap();
+    let pages = parse::read_dict(
+        &pdf_data,
+        offset_of(parse::dict_ref(catalog, "/Pages").unwrap()),
+    )
+    .unwrap();
+    let kids = parse::dict_refs(pages, "/Kids").unwrap();
+    assert_eq!(kids[0], first_page);
+    assert!(kids[1..].iter().all(|&kid| offset_of(kid) >= int("/E")));
+
+    // The title only looks like a reference, so it is left alone.
+    assert!(text_at(0).contains("/Title (Linearized \\(3 0 R\\))"));
+    assert!(Img2Pdf::new()
+        .linearize(true)
+        .xref_stream(true)
+        .convert_bytes(&gray)
+        .is_err());
+}
+
+#[test]
+fn test_pdfa() {
+    let rgba = DynamicImage::ImageRgba16(image::ImageBuffer::from_fn(4, 4, |x, _| {
+        image::Rgba([60000, 0, 0, if x < 2 { 0 } else { 65535 }])
+    }));
+    let img_data = encode_png(&rgba);
+    let pdf_data = Img2Pdf::new()
+        .pdfa(true)
+        .title("Archive")
+        .timestamp(UNIX_EPOCH + std::time::Duration::from_secs(1709211909))
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+
+    assert!(pdf_data.starts_with(b"%PDF-1.4\n"));
+    // Transparency is flattened onto white, and 16-bit samples reduced.
+    let streams = pdf_streams(&pdf_data);
+    let (image_dict, samples) = &streams[0];
+    assert!(image_dict.contains("/BitsPerComponent 8"));
+    assert!(!pdf.contains("/SMask"));
+    assert_eq!(&inflate(samples)[..6], [255, 255, 255, 255, 255, 255]);
+
+    let catalog = pdf.split("/Type /Catalog").nth(1).expect("No catalog");
+    assert!(catalog.contains("/OutputIntents [<< /Type /OutputIntent /S /GTS_PDFA1"));
+    let (_, profile) = streams
+        .iter()
+        .find(|(dict, _)| dict.starts_with("<< /N 3 "))
+        .expect("No output profile");
+    assert_eq!(&inflate(profile)[36..40], b"acsp");
+    let (_, metadata) = streams
+        .iter()
+        .find(|(dict, _)| dict.contains("/Type /Metadata /Subtype /XML"))
+        .expect("No metadata");
+    let metadata = String::from_utf8_lossy(metadata);
+    assert!(metadata.contains("<pdfaid:part>1</pdfaid:part><pdfaid:conformance>B"));
+    assert!(metadata.contains("<xmp:CreateDate>2024-02-29T13:05:09Z</xmp:CreateDate>"));
+    assert!(pdf.contains("/CreationDate (D:20240229130509Z)"));
+
+    for conversion in [
+        Img2Pdf::new().pdfa(true).pdf_version(1, 7),
+        Img2Pdf::new().pdfa(true).interpolate(true),
+    ] {
+        assert!(conversion.convert_bytes(&img_data).is_err());
+    }
+}
+
+#[test]
+fn test_xmp_metadata() {
+    let img_data = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(2, 2)));
+    let pdf_data = Img2Pdf::new()
+        .xmp_metadata(true)
+        .info(Info {
+            title: Some("Harbour at dusk".to_string()),
+            author: Some("Port authority".to_string()),
+            ..Info::default()
+        })
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+
+    let catalog = pdf.split("/Type /Catalog").nth(1).expect("No catalog");
+    let metadata_id = catalog
+        .split("/Metadata ")
+        .nth(1)
+        .and_then(|rest| rest.split(' ').next()?.parse::<u32>().ok())
+        .expect("No /Metadata");
+    let metadata = parse::object_dict(&pdf_data, metadata_id).expect("No metadata stream");
+    assert!(metadata.contains("/Type /Metadata /Subtype /XML"));
+    assert!(pdf.contains("<rdf:li xml:lang=\"x-default\">Harbour at dusk</rdf:li>"));
+    assert!(pdf.contains("<rdf:li>Port authority</rdf:li>"));
+    assert!(pdf.contains("/Title (Harbour at dusk)"));
+    // Not a PDF/A file.
+    assert!(!pdf.contains("pdfaid") && !pdf.contains("/OutputIntents"));
+
+    let plain = img2pdf_from_bytes(&img_data).expect("Failed to convert image to PDF");
+    assert!(!String::from_utf8_lossy(&plain).contains("/Metadata"));
+}
+
+#[test]
+fn test_tagged_figures() {
+    let img_data = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::new(2, 2)));
+    let pdf_data = Img2Pdf::new()
+        .language("en-US")
+        .alt_texts(["A lighthouse at night", ""])
+        .background([0, 0, 0])
+        .convert_multiple_bytes(&[&img_data, &img_data, &img_data])
+        .expect("Failed to convert images to PDF");
+    let pdf = String::from_utf8_lossy(&pdf_data);
+
+    let catalog = pdf.split("/Type /Catalog").nth(1).expect("No catalog");
+    assert!(catalog.contains("/MarkInfo << /Marked true >> /StructTreeRoot "));
+    assert!(catalog.contains("/Lang (en-US)"));
+    assert!(pdf.contains("/Type /StructTreeRoot"));
+    assert!(pdf.contains("/S /Document"));
+    assert_eq!(pdf.matches("/S /Figure").count(), 1);
+    assert!(pdf.contains("/K 0 /Alt (A lighthouse at night)"));
+    assert!(pdf.contains("/Nums [0 ["));
+
+    // Only the first page has a figure, and everything else is decoration.
+    let contents: Vec<String> = pdf_streams(&pdf_data)
+        .into_iter()
+        .filter(|(dict, _)| dict.starts_with("<< /Length"))
+        .map(|(_, data)| String::from_utf8(data).unwrap())
+        .collect();
+    assert_eq!(contents.len(), 3);
+    assert!(contents[0].starts_with("/Artifact BMC\nq\n0 0 0 rg"));
+    assert!(contents[0].contains("EMC\n/Figure <</MCID 0>> BDC\nq\n"));
+    assert!(contents[1].ends_with("Do\nQ\nEMC") && !contents[1].contains("/Figure"));
+    assert_eq!(pdf.matches("/StructParents 0 >>").count(), 1);
+    assert!(!pdf.contains("/StructParents 1"));
+    assert!(!pdf.contains("/Tabs"));
+
+    let pdf_data = Img2Pdf::new()
+        .alt_texts(["A lighthouse at night"])
+        .pdf_version(1, 5)
+        .convert_bytes(&img_data)
+        .expect("Failed to convert image to PDF");
+    assert!(String::from_utf8_lossy(&pdf_data).contains("/StructParents 0 /Tabs /S"));
+}
+
+#[test]
+fn test_conversion_report() {
+    let jpeg = std::fs::read("examples/sample_image.jpg").expect("Failed to read image data");
+    let png = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::from_fn(
+        20,
+        10,
+        |x, _| image::Rgb([x as u8 * 10, 0, 0]),
+    )));
+
+    let converter = Img2Pdf::new().deterministic(true);
+    let (pdf_data, report) = converter
+        .convert_multiple_bytes_with_report(&[&jpeg, &png])
+        .expect("Failed to convert images to PDF");
+    assert_eq!(report.page_count, 2);
+    assert_eq!(report.page_sizes[1], (20.0, 10.0));
+    assert_eq!(report.byte_size, pdf_data.len());
+    assert!(report.compressed && report.passthrough);
+    assert_eq!(
+        pdf_data,
+        converter.convert_multiple_bytes(&[&jpeg, &png]).unwrap()
+    );
+
+    let (_, report) = img2pdf_from_bytes_with_report(&jpeg).unwrap();
+    assert!(!report.compressed && report.passthrough);
+    let (_, report) = Img2Pdf::new()
+        .uncompressed(true)
+        .convert_multiple_bytes_with_report(&[&png])
+        .unwrap();
+    assert!(!report.compressed && !report.passthrough);
+}
+
+#[test]
+fn test_downsampled_mask() {
+    // An opaque image with a faint shadow along its bottom row.
+    let shadowed = encode_png(&DynamicImage::ImageRgba8(image::RgbaImage::from_fn(
+        5,
+        3,
+        |_, y| image::Rgba([200, 0, 0, if y == 2 { 205 } else { 255 }]),
+    )));
+    let converter = Img2Pdf::new().downsample_masks(0.01);
+
+    let streams = pdf_streams(&converter.convert_bytes(&shadowed).unwrap());
+    assert!(streams[0].0.contains("/Width 5 /Height 3"));
+    assert!(streams[0].0.contains("/SMask"));
+    assert!(streams[1].0.contains("/Width 3 /Height 2"));
+    assert_eq!(inflate(&streams[1].1), [255, 255, 255, 205, 205, 205]);
+
+    // Masks that vary a lot, or with no threshold set, keep their resolution.
+    let checkered = encode_png(&DynamicImage::ImageRgba8(image::RgbaImage::from_fn(
+        4,
+        4,
+        |x, y| image::Rgba([0, 0, 0, if (x + y) % 2 == 0 { 0 } else { 255 }]),
+    )));
+    let streams = pdf_streams(&converter.convert_bytes(&checkered).unwrap());
+    assert!(streams[1].0.contains("/Width 4 /Height 4"));
+    let streams = pdf_streams(&img2pdf_from_bytes(&shadowed).unwrap());
+    assert!(streams[1].0.contains("/Width 5 /Height 3"));
+
+    assert!(Img2Pdf::new()
+        .downsample_masks(-1.0)
+        .convert_bytes(&shadowed)
+        .is_err());
+}
+
+#[test]
+fn test_color_key_mask() {
+    let key = [255, 0, 255];
+    let img = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(4, 2, |x, _| {
+        if x == 0 {
+            image::Rgba([255, 0, 255, 0])
+        } else {
+            image::Rgba([10, 20, 30, 255])
+        }
+    }));
+
+    let pdf_data = img2pdf_with_color_key(&encode_png(&img), key).unwrap();
+    let streams = pdf_streams(&pdf_data);
+    assert!(streams[0].0.contains("/Mask [255 255 0 0 255 255]"));
+    assert!(!String::from_utf8_lossy(&pdf_data).contains("/SMask"));
+
+    // Gray images take a gray key, and their samples can hold no other.
+    let gray = encode_png(&DynamicImage::ImageLuma8(image::GrayImage::from_fn(
+        2,
+        2,
+        |x, _| image::Luma([x as u8 * 100]),
+    )));
+    let streams = pdf_streams(&img2pdf_with_color_key(&gray, [100, 100, 100]).unwrap());
+    assert!(streams[0].0.contains("/Mask [100 100]"));
+    let streams = pdf_streams(&img2pdf_with_color_key(&gray, key).unwrap());
+    assert!(!streams[0].0.contains("/Mask"));
+}
+
+#[test]
+fn test_no_overwrite() {
+    let output_path = std::env::temp_dir().join("img2pdf_test_no_overwrite.pdf");
+    std::fs::write(&output_path, b"keep me").unwrap();
+
+    let err = img2pdf_from_file_no_overwrite(Path::new("examples/sample_image.jpg"), &output_path)
+        .unwrap_err();
+    match err {
+        Img2PdfError::Io(e) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
+        other => panic!("Expected an I/O error, got {:?}", other),
+    }
+    assert_eq!(std::fs::read(&output_path).unwrap(), b"keep me");
+
+    std::fs::remove_file(&output_path).unwrap();
+    img2pdf_from_file_no_overwrite(Path::new("examples/sample_image.jpg"), &output_path)
+        .expect("Failed to convert image to PDF");
+    assert!(std::fs::read(&output_path).unwrap().starts_with(b"%PDF"));
+    std::fs::remove_file(&output_path).unwrap();
+}
+
+#[test]
+fn test_failed_conversion_leaves_no_partial_file() {
+    let dir = std::env::temp_dir().join("img2pdf_test_atomic");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+    let bad_input = dir.join("bad.png");
+    std::fs::write(&bad_input, b"\x89PNG\r\n\x1a\n not really").unwrap();
+    let output_path = dir.join("out.pdf");
+
+    assert!(img2pdf_from_file(&bad_input, &output_path).is_err());
+    assert!(!output_path.exists());
+    std::fs::write(&output_path, b"previous").unwrap();
+    assert!(img2pdf_from_file(&bad_input, &output_path).is_err());
+    assert_eq!(std::fs::read(&output_path).unwrap(), b"previous");
+
+    img2pdf_from_file(Path::new("examples/sample_image.jpg"), &output_path).unwrap();
+    assert!(std::fs::read(&output_path).unwrap().starts_with(b"%PDF"));
+    let bad_data = std::fs::read(&bad_input).unwrap();
+    assert!(Img2Pdf::new()
+        .convert_multiple_bytes_to_file(&[&bad_data], &output_path, |_, _| {})
+        .is_err());
+    assert!(std::fs::read(&output_path).unwrap().starts_with(b"%PDF"));
+    // Only the input and the output are left.
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_validate_pdf() {
+    let img_data = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(4, 4)));
+    let pdf_data = img2pdf_from_bytes(&img_data).unwrap();
+    assert_eq!(validate_pdf(&pdf_data), Ok(()));
+    for converter in [
+        Img2Pdf::new().xref_stream(true),
+        Img2Pdf::new().linearize(true),
+    ] {
+        let pdf_data = converter.convert_multiple_bytes(&[&img_data, &img_data]);
+        assert_eq!(validate_pdf(&pdf_data.unwrap()), Ok(()));
+    }
+    let appended = append_image_to_pdf(&pdf_data, &img_data).unwrap();
+    assert_eq!(validate_pdf(&appended), Ok(()));
+
+    assert!(validate_pdf(&pdf_data[1..]).unwrap_err().contains("header"));
+    assert!(validate_pdf(&pdf_data[..pdf_data.len() - 40]).is_err());
+    let object = find_bytes(&pdf_data, b"\n3 0 obj").unwrap() + 1;
+    let mut renumbered = pdf_data.clone();
+    renumbered[object] = b'9';
+    assert_eq!(
+        validate_pdf(&renumbered),
+        Err(format!("object 3 is not at offset {}", object))
+    );
+    let size = find_bytes(&pdf_data, b"/Size 7").unwrap();
+    let mut resized = pdf_data.clone();
+    resized[size + 6] = b'8';
+    assert!(validate_pdf(&resized).unwrap_err().contains("/Size is 8"));
+
+    // Hostile nesting is reported, not followed until the stack runs out.
+    for (open, close) in ["[", "<< /A "].into_iter().zip(["]", ">>"]) {
+        let pdf = format!(
+            "%PDF-1.4\nxref\n0 1\n0000000000 65535 f \ntrailer\n<< /ID {}{} /Size 1 >>\n\
+            startxref\n9\n%%EOF\n",
+            open.repeat(100_000),
+            close.repeat(100_000)
+        );
+        assert_eq!(
+            validate_pdf(pdf.as_bytes()),
+            Err("trailer has no /Size".to_string())
+        );
+    }
+}
+
+#[test]
+fn test_page_rotation() {
+    let jpeg = std::fs::read("examples/sample_image.jpg").expect("Failed to read image data");
+    let rotated = with_exif_orientation(&jpeg, 6);
+
+    // A page rotation leaves the JPEG as it is, EXIF orientation or not.
+    let pdf_data = Img2Pdf::new()
+        .page_rotation(-270)
+        .convert_bytes(&rotated)
+        .unwrap();
+    assert!(find_bytes(&pdf_data, &rotated).is_some());
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/MediaBox [0 0 1200 1600] /Contents"));
+    assert!(pdf.contains(" /Rotate 90 >>"));
+
+    let pdf_data = Img2Pdf::new()
+        .page_rotation(360)
+        .convert_bytes(&jpeg)
+        .unwrap();
+    assert!(!String::from_utf8_lossy(&pdf_data).contains("/Rotate"));
+    assert!(Img2Pdf::new()
+        .page_rotation(45)
+        .convert_bytes(&jpeg)
+        .is_err());
+}
+
+#[test]
+fn test_crop_box() {
+    let img_data = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::new(100, 50)));
+    let insets = Margins {
+        top: 4.0,
+        right: 3.0,
+        bottom: 2.0,
+        left: 1.0,
+    };
+    let pdf_data = Img2Pdf::new()
+        .crop_box(insets)
+        .convert_bytes(&img_data)
+        .unwrap();
+    let pdf = String::from_utf8_lossy(&pdf_data);
+    assert!(pdf.contains("/MediaBox [0 0 100 50]"));
+    assert!(pdf.contains(" /CropBox [1 2 97 46]"));
+    assert!(!String::from_utf8_lossy(&img2pdf_from_bytes(&img_data).unwrap()).contains("/CropBox"));
+
+    for insets in [Margins::uniform(25.0), Margins::uniform(-1.0)] {
+        let err = Img2Pdf::new()
+            .crop_box(insets)
+            .convert_bytes(&img_data)
+            .unwrap_err();
+        assert!(err.to_string().contains("does not fit"));
+    }
 }
@@ -0,0 +1,419 @@
+//! Linearized PDF (ISO 32000-1, annex F), also known as "fast web view".
+//!
+//! A linearized file starts with everything the first page needs, so
+//! viewers can display it before the rest of the file arrives. The objects
+//! are written as usual first, held back in memory, then reordered and
+//! renumbered here:
+//!
+//! 1. the linearization dictionary and the first-page cross-reference
+//!    section,
+//! 2. the catalog and the hint stream,
+//! 3. the first page and the objects only it uses,
+//! 4. every other page with its objects, in order,
+//! 5. the remaining objects, such as the page tree and the document
+//!    information, and the main cross-reference section.
+//!
+//! The objects of the first part get the highest numbers, so that the main
+//! cross-reference section covers objects 0 to `n`. Pages share no objects
+//! in the files this crate writes, which keeps the hint tables simple.
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{parse, Trailer};
+
+/// An object as written before linearization.
+struct Object<'a> {
+    /// The offset of the object in the held back output.
+    offset: usize,
+    /// Its dictionary, whose references are renumbered.
+    dict: &'a str,
+    /// Everything after the dictionary: the stream, if any, and `endobj`.
+    rest: &'a [u8],
+}
+
+/// The values of the linearization dictionary and the offsets that depend on
+/// the layout. They are found by laying the file out until they settle,
+/// since the dictionary and the first-page trailer contain some of them.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+    file_length: usize,
+    hint_offset: usize,
+    hint_length: usize,
+    first_page_end: usize,
+    main_xref_offset: usize,
+    /// The offset of the end of line before the first entry of the main
+    /// cross-reference table.
+    main_xref_first_entry: usize,
+}
+
+/// Reorders the objects written to `held`, which starts at offset `start` of
+/// the file, into a linearized file and returns everything after the header.
+///
+/// `objects` lists the id and offset in `held` of every written object, and
+/// `page_object_ids` the pages in order. The trailer must not have an
+/// `/Encrypt` or `/Prev` entry, since renumbering would change the keys of
+/// encrypted objects.
+pub(crate) fn linearize(
+    start: usize,
+    held: &[u8],
+    objects: &[(u32, usize)],
+    page_object_ids: &[u32],
+    trailer: &Trailer,
+) -> Vec<u8> {
+    let mut by_offset = objects.to_vec();
+    by_offset.sort_unstable_by_key(|&(_, offset)| offset);
+    let mut parsed = BTreeMap::new();
+    for (index, &(id, offset)) in by_offset.iter().enumerate() {
+        let end = by_offset.get(index + 1).map_or(held.len(), |&(_, end)| end);
+        let object = &held[offset..end];
+        let dict = parse::read_dict(object, 0).expect("object without dictionary");
+        let dict_end = object.windows(2).position(|w| w == b"<<").unwrap() + dict.len();
+        parsed.insert(
+            id,
+            Object {
+                offset,
+                dict,
+                rest: &object[dict_end..],
+            },
+        );
+    }
+
+    // Each page owns the objects it references, directly or not. The page
+    // tree is reachable from every page through `/Parent`, so it is not
+    // followed, nor is the catalog.
+    let catalog_id = trailer.root_object_id;
+    let mut assigned: HashSet<u32> = [catalog_id].into_iter().collect();
+    assigned.extend(parse::dict_ref(parsed[&catalog_id].dict, "/Pages"));
+    let pages: Vec<Vec<u32>> = page_object_ids
+        .iter()
+        .map(|&page_id| {
+            let mut owned = Vec::new();
+            let mut pending = vec![page_id];
+            while let Some(id) = pending.pop() {
+                if !parsed.contains_key(&id) || !assigned.insert(id) {
+                    continue;
+                }
+                owned.push(id);
+                parse::map_refs(parsed[&id].dict, |reference| {
+                    pending.push(reference);
+                    reference
+                });
+            }
+            // The page object comes first, then the others in file order.
+            owned[1..].sort_unstable_by_key(|id| parsed[id].offset);
+            owned
+        })
+        .collect();
+
+    // Everything but the first page section keeps its order, numbered from 1.
+    let owned: HashSet<u32> = pages.iter().flatten().copied().collect();
+    let first_page = &pages[0];
+    let rest: Vec<u32> = pages[1..]
+        .iter()
+        .flatten()
+        .copied()
+        .chain(
+            by_offset
+                .iter()
+                .map(|&(id, _)| id)
+                .filter(|id| *id != catalog_id && !owned.contains(id)),
+        )
+        .collect();
+    let mut renumbered: BTreeMap<u32, u32> = BTreeMap::new();
+    for (new_id, &id) in (1..).zip(&rest) {
+        renumbered.insert(id, new_id);
+    }
+    let linearization_id = rest.len() as u32 + 1;
+    renumbered.insert(catalog_id, linearization_id + 1);
+    for (new_id, &id) in (linearization_id + 2..).zip(first_page) {
+        renumbered.insert(id, new_id);
+    }
+    let hint_id = linearization_id + 2 + first_page.len() as u32;
+    let size = hint_id as usize + 1;
+
+    let rendered: BTreeMap<u32, Vec<u8>> = parsed
+        .iter()
+        .filter_map(|(id, object)| {
+            let new_id = *renumbered.get(id)?;
+            let mut bytes = format!("{} 0 obj\n", new_id).into_bytes();
+            let dict = parse::map_refs(object.dict, |reference| {
+                renumbered.get(&reference).copied().unwrap_or(reference)
+            });
+            bytes.extend_from_slice(dict.as_bytes());
+            bytes.extend_from_slice(object.rest);
+            Some((new_id, bytes))
+        })
+        .collect();
+    let renumber = |id: &u32| renumbered[id];
+    let page_objects: Vec<Vec<u32>> = pages
+        .iter()
+        .map(|page| page.iter().map(renumber).collect())
+        .collect();
+    let first_page_trailer = Trailer {
+        root_object_id: renumbered[&catalog_id],
+        info_object_id: trailer.info_object_id.map(|id| renumbered[&id]),
+        id: trailer.id,
+        encrypt_object_id: None,
+        prev: None,
+    };
+
+    let mut layout = Layout::default();
+    loop {
+        let (file, measured) = lay_out(
+            start,
+            &layout,
+            &rendered,
+            &page_objects,
+            &first_page_trailer,
+            size,
+            linearization_id,
+            hint_id,
+        );
+        if measured == layout {
+            return file;
+        }
+        layout = measured;
+    }
+}
+
+/// Lays the file out with the values of `layout`, and returns it with the
+/// values measured while doing so.
+#[allow(clippy::too_many_arguments)]
+fn lay_out(
+    start: usize,
+    layout: &Layout,
+    rendered: &BTreeMap<u32, Vec<u8>>,
+    pages: &[Vec<u32>],
+    trailer: &Trailer,
+    size: usize,
+    linearization_id: u32,
+    hint_id: u32,
+) -> (Vec<u8>, Layout) {
+    let mut file = Vec::new();
+    let mut offsets: BTreeMap<u32, usize> = BTreeMap::new();
+    let mut measured = Layout::default();
+
+    offsets.insert(linearization_id, start);
+    file.extend_from_slice(
+        format!(
+            "{} 0 obj\n<< /Linearized 1 /L {} /H [ {} {} ] /O {} /E {} /N {} /T {} >>\nendobj\n",
+            linearization_id,
+            layout.file_length,
+            layout.hint_offset,
+            layout.hint_length,
+            pages[0][0],
+            layout.first_page_end,
+            pages.len(),
+            layout.main_xref_first_entry
+        )
+        .as_bytes(),
+    );
+
+    // The first-page section lists the objects up to the end of the first
+    // page, all of fixed width, so the offsets after it are known already.
+    let first_xref_offset = start + file.len();
+    let first_trailer = Trailer {
+        prev: Some(layout.main_xref_offset),
+        ..*trailer
+    };
+    let first_trailer = format!(
+        "trailer\n<< {} >>\nstartxref\n0\n%%EOF\n",
+        first_trailer.entries(size)
+    );
+    let first_xref_header = format!(
+        "xref\n{} {}\n",
+        linearization_id,
+        size as u32 - linearization_id
+    );
+    let first_xref_length =
+        first_xref_header.len() + 20 * (size - linearization_id as usize) + first_trailer.len();
+
+    let catalog_id = linearization_id + 1;
+    let catalog_offset = first_xref_offset + first_xref_length;
+    offsets.insert(catalog_id, catalog_offset);
+    let first_page: Vec<&[u8]> = pages[0].iter().map(|id| &rendered[id][..]).collect();
+    let hint = hint_stream(
+        hint_id,
+        // Offsets in the hint tables leave the hint stream out.
+        catalog_offset + rendered[&catalog_id].len(),
+        &first_page,
+        pages,
+        rendered,
+    );
+    measured.hint_offset = catalog_offset + rendered[&catalog_id].len();
+    measured.hint_length = hint.len();
+    offsets.insert(hint_id, measured.hint_offset);
+
+    let mut position = measured.hint_offset + hint.len();
+    for &id in &pages[0] {
+        offsets.insert(id, position);
+        position += rendered[&id].len();
+    }
+    measured.first_page_end = position;
+
+    let mut xref = first_xref_header;
+    for id in linearization_id..size as u32 {
+        xref.push_str(&format!("{:010} 00000 n \n", offsets[&id]));
+    }
+    xref.push_str(&first_trailer);
+    file.extend_from_slice(xref.as_bytes());
+    file.extend_from_slice(&rendered[&catalog_id]);
+    file.extend_from_slice(&hint);
+    for id in &pages[0] {
+        file.extend_from_slice(&rendered[id]);
+    }
+
+    for id in 1..linearization_id {
+        offsets.insert(id, start + file.len());
+        file.extend_from_slice(&rendered[&id]);
+    }
+
+    measured.main_xref_offset = start + file.len();
+    let mut xref = format!("xref\n0 {}", linearization_id);
+    measured.main_xref_first_entry = measured.main_xref_offset + xref.len();
+    xref.push_str("\n0000000000 65535 f \n");
+    for id in 1..linearization_id {
+        xref.push_str(&format!("{:010} 00000 n \n", offsets[&id]));
+    }
+    xref.push_str(&format!(
+        "trailer\n<< /Size {} >>\nstartxref\n{}\n%%EOF\n",
+        linearization_id, first_xref_offset
+    ));
+    file.extend_from_slice(xref.as_bytes());
+    measured.file_length = start + file.len();
+    (file, measured)
+}
+
+/// Writes the hint stream object, with the page offset hint table and the
+/// shared object hint table.
+///
+/// `first_page_offset` is the offset of the first page object as if the
+/// hint stream were not there.
+fn hint_stream(
+    hint_id: u32,
+    first_page_offset: usize,
+    first_page: &[&[u8]],
+    pages: &[Vec<u32>],
+    rendered: &BTreeMap<u32, Vec<u8>>,
+) -> Vec<u8> {
+    let counts: Vec<u64> = pages.iter().map(|page| page.len() as u64).collect();
+    let lengths: Vec<u64> = pages
+        .iter()
+        .map(|page| page.iter().map(|id| rendered[id].len() as u64).sum())
+        .collect();
+    let (least_count, count_bits) = range(&counts);
+    let (least_length, length_bits) = range(&lengths);
+
+    // The page offset hint table (F.4.1). No objects are shared, and as
+    // other writers do, the content stream items repeat the page lengths.
+    let mut bits = BitWriter::default();
+    bits.write(least_count, 32);
+    bits.write(first_page_offset as u64, 32);
+    bits.write(count_bits.into(), 16);
+    bits.write(least_length, 32);
+    bits.write(length_bits.into(), 16);
+    bits.write(0, 32);
+    bits.write(0, 16);
+    bits.write(least_length, 32);
+    bits.write(length_bits.into(), 16);
+    for _ in 0..3 {
+        bits.write(0, 16);
+    }
+    bits.write(1, 16);
+    for count in &counts {
+        bits.write(count - least_count, count_bits);
+    }
+    bits.align();
+    for length in &lengths {
+        bits.write(length - least_length, length_bits);
+    }
+    bits.align();
+    for length in &lengths {
+        bits.write(length - least_length, length_bits);
+    }
+    bits.align();
+    let shared_offset = bits.bytes.len();
+
+    // The shared object hint table (F.4.2) still lists the objects of the
+    // first page, as groups of one object each.
+    let group_lengths: Vec<u64> = first_page
+        .iter()
+        .map(|object| object.len() as u64)
+        .collect();
+    let (least_group_length, group_length_bits) = range(&group_lengths);
+    bits.write(0, 32);
+    bits.write(0, 32);
+    bits.write(first_page.len() as u64, 32);
+    bits.write(first_page.len() as u64, 32);
+    bits.write(0, 16);
+    bits.write(least_group_length, 32);
+    bits.write(group_length_bits.into(), 16);
+    for length in &group_lengths {
+        bits.write(length - least_group_length, group_length_bits);
+    }
+    bits.align();
+    for _ in first_page {
+        bits.write(0, 1);
+    }
+    bits.align();
+
+    let mut object = format!(
+        "{} 0 obj\n<< /S {} /Length {} >>\nstream\n",
+        hint_id,
+        shared_offset,
+        bits.bytes.len()
+    )
+    .into_bytes();
+    object.extend_from_slice(&bits.bytes);
+    object.extend_from_slice(b"\nendstream\nendobj\n");
+    object
+}
+
+/// Returns the least of `values` and the number of bits needed for the
+/// difference to the greatest.
+fn range(values: &[u64]) -> (u64, u32) {
+    let least = values.iter().copied().min().unwrap_or(0);
+    let greatest = values.iter().copied().max().unwrap_or(0);
+    (least, u64::BITS - (greatest - least).leading_zeros())
+}
+
+/// Packs values of any width, most significant bit first, as the hint
+/// tables store them.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// The bits still free in the last byte.
+    free: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, width: u32) {
+        for bit in (0..width).rev() {
+            if self.free == 0 {
+                self.bytes.push(0);
+                self.free = 8;
+            }
+            self.free -= 1;
+            if value >> bit & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 1 << self.free;
+            }
+        }
+    }
+
+    /// Pads to a whole byte, so the next value starts a new one.
+    fn align(&mut self) {
+        self.free = 0;
+    }
+}
+
+#[test]
+fn test_bit_writer() {
+    let mut bits = BitWriter::default();
+    bits.write(0b101, 3);
+    bits.write(0xFF, 6);
+    bits.align();
+    bits.write(0x1234, 16);
+    assert_eq!(bits.bytes, [0b1011_1111, 0b1000_0000, 0x12, 0x34]);
+    assert_eq!(range(&[5, 9, 7]), (5, 3));
+    assert_eq!(range(&[4, 4]), (4, 0));
+}
@@ -0,0 +1,726 @@
+//! Just enough PDF parsing to find our way around an existing file.
+//!
+//! This is not a general PDF parser. It reads the objects and dictionaries
+//! this crate writes itself, and files laid out the same way: a dictionary
+//! per object, with direct values and `N 0 R` references.
+
+use std::collections::BTreeMap;
+
+/// Returns the offset recorded by the last `startxref` in the file.
+pub(crate) fn last_startxref(pdf: &[u8]) -> Option<usize> {
+    let keyword = b"startxref";
+    let start = pdf
+        .windows(keyword.len())
+        .rposition(|window| window == keyword)?
+        + keyword.len();
+    let digits: String = pdf[start..]
+        .iter()
+        .map(|&b| b as char)
+        .skip_while(|c| c.is_ascii_whitespace())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+/// Returns the dictionary starting at the first `<<` at or after `pos`,
+/// including its delimiters. Nested dictionaries, strings and hex strings
+/// are skipped over.
+pub(crate) fn read_dict(pdf: &[u8], pos: usize) -> Option<&str> {
+    let start = pos + pdf.get(pos..)?.windows(2).position(|w| w == b"<<")?;
+    let mut depth = 0;
+    let mut i = start;
+    while i < pdf.len() {
+        match pdf[i] {
+            b'<' if pdf.get(i + 1) == Some(&b'<') => {
+                depth += 1;
+                i += 2;
+            }
+            b'>' if pdf.get(i + 1) == Some(&b'>') => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return std::str::from_utf8(&pdf[start..i]).ok();
+                }
+            }
+            b'<' | b'(' => i = skip_string(pdf, i)?,
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Returns the offset just past the literal or hex string starting at `i`.
+fn skip_string(pdf: &[u8], mut i: usize) -> Option<usize> {
+    if pdf[i] == b'<' {
+        return Some(i + pdf[i..].iter().position(|&b| b == b'>')? + 1);
+    }
+    // Literal strings may nest balanced parentheses and escape unbalanced
+    // ones.
+    let mut parens = 0;
+    loop {
+        match *pdf.get(i)? {
+            b'\\' => i += 1,
+            b'(' => parens += 1,
+            b')' => parens -= 1,
+            _ => {}
+        }
+        i += 1;
+        if parens == 0 {
+            return Some(i);
+        }
+    }
+}
+
+/// Returns `text` with the object number `N` of every `N 0 R` reference
+/// replaced by `renumber(N)`. Strings are copied unchanged.
+pub(crate) fn map_refs(text: &str, mut renumber: impl FnMut(u32) -> u32) -> String {
+    let bytes = text.as_bytes();
+    let mut mapped = String::with_capacity(text.len());
+    let (mut copied, mut i) = (0, 0);
+    while i < bytes.len() {
+        match bytes[i] {
+            b'<' if bytes.get(i + 1) == Some(&b'<') => i += 2,
+            b'<' | b'(' => i = skip_string(bytes, i).unwrap_or(bytes.len()),
+            b'0'..=b'9' if i == 0 || is_token_end(bytes[i - 1]) => match reference(bytes, i) {
+                Some((id, id_end, end)) => {
+                    mapped.push_str(&text[copied..i]);
+                    mapped.push_str(&renumber(id).to_string());
+                    copied = id_end;
+                    i = end;
+                }
+                None => i += 1,
+            },
+            _ => i += 1,
+        }
+    }
+    mapped.push_str(&text[copied..]);
+    mapped
+}
+
+/// Reads an `N 0 R` reference starting at `i`, and returns `N` with the
+/// offsets where its digits and the whole reference end.
+fn reference(bytes: &[u8], i: usize) -> Option<(u32, usize, usize)> {
+    let digits = |from: usize| {
+        from + bytes[from..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+    };
+    let spaces = |from: usize| {
+        from + bytes[from..]
+            .iter()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count()
+    };
+    let id_end = digits(i);
+    let generation = spaces(id_end);
+    let generation_end = digits(generation);
+    let keyword = spaces(generation_end);
+    let is_reference = id_end < generation
+        && &bytes[generation..generation_end] == b"0"
+        && generation_end < keyword
+        && bytes.get(keyword) == Some(&b'R')
+        && bytes.get(keyword + 1).is_none_or(|&b| is_token_end(b));
+    let id = std::str::from_utf8(&bytes[i..id_end]).ok()?.parse().ok()?;
+    is_reference.then_some((id, id_end, keyword + 1))
+}
+
+/// Whether `b` ends a token: white space or a delimiter other than `/`,
+/// which starts names such as `/Im2`.
+fn is_token_end(b: u8) -> bool {
+    b.is_ascii_whitespace() || b"[]<>(){}".contains(&b)
+}
+
+/// Returns the dictionary of the current definition of object `id`, at the
+/// offset the cross-reference sections give for it.
+///
+/// Objects in object streams are not read.
+pub(crate) fn object_dict(pdf: &[u8], id: u32) -> Option<&str> {
+    let (entries, _) = read_xref(pdf).ok()?;
+    let XrefEntry::InUse(offset, generation) = *entries.get(&id)? else {
+        return None;
+    };
+    let marker = format!("{} {} obj", id, generation);
+    let object = pdf.get(offset..)?.strip_prefix(marker.as_bytes())?;
+    let start = skip_space(object, 0);
+    if !object[start..].starts_with(b"<<") {
+        return None;
+    }
+    read_dict(pdf, offset + marker.len() + start)
+}
+
+/// Returns the offset of the first byte at or after `i` that is not white
+/// space or part of a comment.
+fn skip_space(bytes: &[u8], mut i: usize) -> usize {
+    while let Some(&b) = bytes.get(i) {
+        if b == b'%' {
+            i += bytes[i..]
+                .iter()
+                .position(|&b| b == b'\n' || b == b'\r')
+                .unwrap_or(bytes.len() - i);
+        } else if b.is_ascii_whitespace() || b == 0 {
+            i += 1;
+        } else {
+            break;
+        }
+    }
+    i
+}
+
+/// The deepest nesting of arrays and dictionaries that is read. Real files
+/// stay far below it; deeper objects are rejected rather than read.
+const MAX_NESTING: usize = 256;
+
+/// Returns the offset just past the object starting at `i`: a dictionary,
+/// an array, a string, a name, an `N 0 R` reference or another single token.
+///
+/// Nested arrays and dictionaries are tracked with a stack of the
+/// delimiters that close them, up to [`MAX_NESTING`] deep.
+fn skip_object(bytes: &[u8], mut i: usize) -> Option<usize> {
+    let token_end = |from: usize| {
+        from + bytes[from..]
+            .iter()
+            .take_while(|&&b| !is_token_end(b) && b != b'/')
+            .count()
+    };
+    let mut closers: Vec<&[u8]> = Vec::new();
+    loop {
+        if let Some(&close) = closers.last() {
+            i = skip_space(bytes, i);
+            if bytes.get(i..)?.starts_with(close) {
+                i += close.len();
+                closers.pop();
+                if closers.is_empty() {
+                    return Some(i);
+                }
+                continue;
+            }
+        }
+        match *bytes.get(i)? {
+            b'<' if bytes.get(i + 1) == Some(&b'<') => {
+                closers.push(b">>");
+                i += 2;
+            }
+            b'[' => {
+                closers.push(b"]");
+                i += 1;
+            }
+            b'<' | b'(' => i = skip_string(bytes, i)?,
+            b'/' => i = token_end(i + 1),
+            b'0'..=b'9' => i = reference(bytes, i).map_or_else(|| token_end(i), |(_, _, end)| end),
+            b if is_token_end(b) => return None,
+            _ => i = token_end(i),
+        }
+        if closers.len() > MAX_NESTING {
+            return None;
+        }
+        if closers.is_empty() {
+            return Some(i);
+        }
+    }
+}
+
+/// Returns the offsets in `dict` where the `key` entry starts, where its raw
+/// value starts, and where the value ends.
+///
+/// The dictionary is read object by object, so keys are only matched at the
+/// top level, and values run to their end whatever they contain.
+fn entry_offsets(dict: &str, key: &str) -> Option<(usize, usize, usize)> {
+    let bytes = dict.as_bytes();
+    if !bytes.starts_with(b"<<") {
+        return None;
+    }
+    let mut i = 2;
+    loop {
+        // Past the last entry comes the closing `>>`, or garbage.
+        let key_start = skip_space(bytes, i);
+        if bytes.get(key_start) != Some(&b'/') {
+            return None;
+        }
+        let key_end = skip_object(bytes, key_start)?;
+        let value_start = skip_space(bytes, key_end);
+        let value_end = skip_object(bytes, value_start)?;
+        if &dict[key_start..key_end] == key {
+            return Some((key_start, value_start, value_end));
+        }
+        i = value_end;
+    }
+}
+
+/// Returns the raw value of the `key` entry of `dict`.
+fn dict_value<'a>(dict: &'a str, key: &str) -> Option<&'a str> {
+    let (_, start, end) = entry_offsets(dict, key)?;
+    Some(&dict[start..end])
+}
+
+/// Returns `dict` with its `key` entry set to the raw `value`, replacing the
+/// current entry or adding one at the end.
+pub(crate) fn with_entry(dict: &str, key: &str, value: &str) -> String {
+    match entry_offsets(dict, key) {
+        Some((start, _, end)) => format!("{}{} {}{}", &dict[..start], key, value, &dict[end..]),
+        None => format!(
+            "{} {} {} >>",
+            dict.trim_end_matches(">>").trim_end(),
+            key,
+            value
+        ),
+    }
+}
+
+/// Reads an integer entry of `dict`.
+pub(crate) fn dict_int(dict: &str, key: &str) -> Option<u64> {
+    dict_value(dict, key)?.parse().ok()
+}
+
+/// Reads an `N 0 R` reference entry of `dict` and returns `N`.
+pub(crate) fn dict_ref(dict: &str, key: &str) -> Option<u32> {
+    let mut parts = dict_value(dict, key)?.split_whitespace();
+    let id = parts.next()?.parse().ok()?;
+    (parts.next()? == "0" && parts.next()? == "R").then_some(id)
+}
+
+/// Reads the object ids of an array of references, such as `/Kids`.
+pub(crate) fn dict_refs(dict: &str, key: &str) -> Option<Vec<u32>> {
+    let array = dict_value(dict, key)?.strip_prefix('[')?;
+    let array = &array[..array.find(']')?];
+    let parts: Vec<&str> = array.split_whitespace().collect();
+    parts
+        .chunks(3)
+        .map(|reference| match reference {
+            [id, "0", "R"] => id.parse().ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Reads a `/Version` entry of `dict`, such as `/1.7`, as major and minor
+/// version numbers.
+pub(crate) fn dict_version(dict: &str) -> Option<(u8, u8)> {
+    parse_version(dict_value(dict, "/Version")?.strip_prefix('/')?)
+}
+
+/// Reads the version of the `%PDF-x.y` header at the start of the file.
+pub(crate) fn header_version(pdf: &[u8]) -> Option<(u8, u8)> {
+    let header = pdf.strip_prefix(b"%PDF-")?;
+    let end = header
+        .iter()
+        .position(|&b| !(b.is_ascii_digit() || b == b'.'))
+        .unwrap_or(header.len());
+    parse_version(std::str::from_utf8(&header[..end]).ok()?)
+}
+
+fn parse_version(version: &str) -> Option<(u8, u8)> {
+    let (major, minor) = version.split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Reads the two hex strings of the `/ID` entry of a trailer dictionary.
+pub(crate) fn dict_id(dict: &str) -> Option<[Vec<u8>; 2]> {
+    let array = dict_value(dict, "/ID")?;
+    let mut strings = array
+        .split(['<', '>'])
+        .map(str::trim)
+        .filter(|part| !part.is_empty() && !matches!(*part, "[" | "]"));
+    let mut decode = || -> Option<Vec<u8>> {
+        let hex = strings.next()?;
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect()
+    };
+    Some([decode()?, decode()?])
+}
+
+/// Reads an array of integers entry of `dict`, such as `/W`.
+fn dict_ints(dict: &str, key: &str) -> Option<Vec<u64>> {
+    let array = dict_value(dict, key)?.strip_prefix('[')?;
+    array[..array.find(']')?]
+        .split_whitespace()
+        .map(|number| number.parse().ok())
+        .collect()
+}
+
+/// An entry of a cross-reference section.
+#[derive(Clone, Copy)]
+enum XrefEntry {
+    Free,
+    /// An object at an offset, with its generation number.
+    InUse(usize, u64),
+    /// An object in the object stream with this id.
+    Compressed(u32),
+}
+
+/// Checks the structure of a PDF: its header and end-of-file marker, that
+/// `startxref` and each `/Prev` point at a cross-reference section, that
+/// every object the sections list starts where they say, and that the
+/// trailer's `/Size` is one more than the highest object number, with every
+/// number below it listed.
+pub(crate) fn validate(pdf: &[u8]) -> Result<(), String> {
+    if header_version(pdf).is_none() {
+        return Err("no %PDF- header".to_string());
+    }
+    let tail = &pdf[pdf.len().saturating_sub(32)..];
+    if !tail.windows(5).any(|window| window == b"%%EOF") {
+        return Err("no %%EOF at the end".to_string());
+    }
+
+    let (entries, size) = read_xref(pdf)?;
+    let listed = entries
+        .keys()
+        .next_back()
+        .map_or(0, |&last| last as u64 + 1);
+    if listed != size || entries.len() as u64 != size {
+        return Err(format!(
+            "trailer /Size is {}, but the cross-reference sections list {} objects below {}",
+            size,
+            entries.len(),
+            listed
+        ));
+    }
+    for (&id, &entry) in &entries {
+        match entry {
+            XrefEntry::Free => {}
+            XrefEntry::InUse(offset, generation) => {
+                let marker = format!("{} {} obj", id, generation);
+                if !pdf
+                    .get(offset..)
+                    .is_some_and(|object| object.starts_with(marker.as_bytes()))
+                {
+                    return Err(format!("object {} is not at offset {}", id, offset));
+                }
+            }
+            XrefEntry::Compressed(stream_id) => {
+                if !matches!(entries.get(&stream_id), Some(XrefEntry::InUse(..))) {
+                    return Err(format!(
+                        "object {} is in object stream {}, which is not in use",
+                        id, stream_id
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads the cross-reference sections from `startxref` back along the
+/// `/Prev` chain, and returns the current entry of each object with the
+/// `/Size` of the newest trailer.
+fn read_xref(pdf: &[u8]) -> Result<(BTreeMap<u32, XrefEntry>, u64), String> {
+    // Sections are read newest first, and the newest entry for each object
+    // is the current one.
+    let mut entries = BTreeMap::new();
+    let mut size = None;
+    let mut visited = Vec::new();
+    let mut next = Some(last_startxref(pdf).ok_or("no startxref")?);
+    while let Some(offset) = next {
+        if visited.contains(&offset) {
+            return Err(format!(
+                "cross-reference sections loop at offset {}",
+                offset
+            ));
+        }
+        visited.push(offset);
+        let XrefSection {
+            entries: section,
+            trailer,
+        } = read_xref_section(pdf, offset)?;
+        for (id, entry) in section {
+            entries.entry(id).or_insert(entry);
+        }
+        size.get_or_insert(dict_int(trailer, "/Size").ok_or("trailer has no /Size")?);
+        next = dict_int(trailer, "/Prev").map(|prev| prev as usize);
+    }
+    Ok((entries, size.unwrap_or_default()))
+}
+
+/// Returns one more than the highest object number the cross-reference
+/// sections list, which is the `/Size` of a trailer that is right.
+pub(crate) fn xref_size(pdf: &[u8]) -> Option<u64> {
+    let (entries, _) = read_xref(pdf).ok()?;
+    Some(
+        entries
+            .keys()
+            .next_back()
+            .map_or(0, |&last| last as u64 + 1),
+    )
+}
+
+/// A cross-reference table or stream.
+struct XrefSection<'a> {
+    entries: Vec<(u32, XrefEntry)>,
+    /// The trailer dictionary, or the stream dictionary of a stream.
+    trailer: &'a str,
+}
+
+/// Reads the cross-reference table or stream at `offset`.
+fn read_xref_section(pdf: &[u8], offset: usize) -> Result<XrefSection<'_>, String> {
+    let section = pdf
+        .get(offset..)
+        .ok_or_else(|| format!("cross-reference offset {} is past the end", offset))?;
+    if section.starts_with(b"xref") {
+        let trailer_start = section
+            .windows(7)
+            .position(|window| window == b"trailer")
+            .ok_or("cross-reference table has no trailer")?;
+        let table = std::str::from_utf8(&section[4..trailer_start])
+            .map_err(|_| "cross-reference table is not text")?;
+        let malformed = || format!("malformed cross-reference table at offset {}", offset);
+        let tokens: Vec<&str> = table.split_whitespace().collect();
+        let mut tokens = tokens.as_slice();
+        let mut entries = Vec::new();
+        while let [first, count, rest @ ..] = tokens {
+            let first: u32 = first.parse().map_err(|_| malformed())?;
+            let count: u32 = count.parse().map_err(|_| malformed())?;
+            // Each entry is three tokens, so the count is bounded by those left.
+            let end = first.checked_add(count).ok_or_else(malformed)?;
+            let subsection_len = (count as usize)
+                .checked_mul(3)
+                .filter(|&len| len <= rest.len())
+                .ok_or_else(malformed)?;
+            let (subsection, rest) = rest.split_at(subsection_len);
+            tokens = rest;
+            for (id, entry) in (first..end).zip(subsection.chunks_exact(3)) {
+                let [position, generation, kind] = *entry else {
+                    unreachable!("the chunks have three tokens")
+                };
+                let entry = match kind {
+                    "n" => XrefEntry::InUse(
+                        position.parse().map_err(|_| malformed())?,
+                        generation.parse().map_err(|_| malformed())?,
+                    ),
+                    "f" => XrefEntry::Free,
+                    _ => return Err(malformed()),
+                };
+                entries.push((id, entry));
+            }
+        }
+        if !tokens.is_empty() {
+            return Err(malformed());
+        }
+        let trailer = read_dict(pdf, offset + trailer_start).ok_or("no trailer dictionary")?;
+        return Ok(XrefSection { entries, trailer });
+    }
+
+    // Otherwise `startxref` points at a cross-reference stream object.
+    let not_xref = || format!("no cross-reference section at offset {}", offset);
+    let header_end = section
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or_else(not_xref)?;
+    if !section[..header_end].ends_with(b" obj") {
+        return Err(not_xref());
+    }
+    let dict = read_dict(pdf, offset).ok_or_else(not_xref)?;
+    if !dict.contains("/Type /XRef") {
+        return Err(not_xref());
+    }
+    let dict_end = dict.as_ptr() as usize - pdf.as_ptr() as usize + dict.len();
+    let malformed = || format!("malformed cross-reference stream at offset {}", offset);
+    let after_dict = &pdf[dict_end..];
+    let keyword = after_dict
+        .windows(6)
+        .position(|window| window == b"stream")
+        .ok_or_else(malformed)?;
+    let mut data_start = dict_end + keyword + 6;
+    if pdf.get(data_start) == Some(&b'\r') {
+        data_start += 1;
+    }
+    data_start += 1;
+    let length = dict_int(dict, "/Length")
+        .and_then(|length| usize::try_from(length).ok())
+        .ok_or_else(malformed)?;
+    let data = data_start
+        .checked_add(length)
+        .and_then(|data_end| pdf.get(data_start..data_end))
+        .ok_or_else(malformed)?;
+    let rows = if dict.contains("/FlateDecode") {
+        let mut rows = Vec::new();
+        std::io::Read::read_to_end(&mut flate2::read::ZlibDecoder::new(data), &mut rows)
+            .map_err(|_| malformed())?;
+        rows
+    } else {
+        data.to_vec()
+    };
+
+    // No field is wider than the 8 bytes of a `u64`.
+    let widths = dict_ints(dict, "/W")
+        .filter(|widths| widths.len() == 3 && widths.iter().all(|&width| width <= 8))
+        .ok_or_else(malformed)?;
+    let size = dict_int(dict, "/Size").ok_or_else(malformed)?;
+    let index = dict_ints(dict, "/Index").unwrap_or(vec![0, size]);
+    let row_width = widths.iter().sum::<u64>() as usize;
+    let mut rows = rows.chunks_exact(row_width.max(1));
+    let mut entries = Vec::new();
+    for range in index.chunks(2) {
+        let [first, count] = *range else {
+            return Err(malformed());
+        };
+        // Each entry is a row, so the count is bounded by the rows left.
+        let end = first
+            .checked_add(count)
+            .filter(|&end| end <= u64::from(u32::MAX) + 1 && count <= rows.len() as u64)
+            .ok_or_else(malformed)?;
+        for id in first..end {
+            let row = rows.next().ok_or_else(malformed)?;
+            let mut fields = [0u64; 3];
+            let mut start = 0;
+            for (field, &width) in fields.iter_mut().zip(&widths) {
+                *field = row[start..start + width as usize]
+                    .iter()
+                    .fold(0, |value, &b| value << 8 | b as u64);
+                start += width as usize;
+            }
+            // A type field of width zero means type 1.
+            let kind = if widths[0] == 0 { 1 } else { fields[0] };
+            let entry = match kind {
+                0 => XrefEntry::Free,
+                1 => XrefEntry::InUse(fields[1] as usize, fields[2]),
+                2 => XrefEntry::Compressed(fields[1] as u32),
+                _ => return Err(malformed()),
+            };
+            entries.push((id as u32, entry));
+        }
+    }
+    Ok(XrefSection {
+        entries,
+        trailer: dict,
+    })
+}
+
+#[test]
+fn test_dict_entries() {
+    let pages = "<< /Type /Pages /Kids [ 3 0 R 7 0 R ] /Count 2 /PagesX 4 \
+        /Resources << /Font << /F1 9 0 R >> /ProcSet [/PDF /Text] >> /Rotate 90 >>";
+    assert_eq!(dict_value(pages, "/Type"), Some("/Pages"));
+    assert_eq!(dict_refs(pages, "/Kids"), Some(vec![3, 7]));
+    assert_eq!(dict_int(pages, "/Count"), Some(2));
+    assert_eq!(dict_ref(pages, "/Count"), None);
+    assert_eq!(dict_int(pages, "/PagesX"), Some(4));
+    assert_eq!(
+        dict_value(pages, "/Resources"),
+        Some("<< /Font << /F1 9 0 R >> /ProcSet [/PDF /Text] >>")
+    );
+    // Keys of nested dictionaries are not entries of this one.
+    assert_eq!(dict_value(pages, "/F1"), None);
+    assert_eq!(
+        with_entry(pages, "/Kids", "[ 3 0 R ]"),
+        "<< /Type /Pages /Kids [ 3 0 R ] /Count 2 /PagesX 4 \
+        /Resources << /Font << /F1 9 0 R >> /ProcSet [/PDF /Text] >> /Rotate 90 >>"
+    );
+
+    let pdf = b"trailer\n<< /Size 9 /Root 5 0 R /Title (a (nested) \\) >> string) /ID [<0AFF> <1234>] >>\n";
+
+    let trailer = read_dict(pdf, 0).expect("No trailer");
+    assert!(trailer.ends_with("[<0AFF> <1234>] >>"));
+    assert_eq!(dict_int(trailer, "/Size"), Some(9));
+    assert_eq!(dict_ref(trailer, "/Root"), Some(5));
+    assert_eq!(dict_id(trailer), Some([vec![0x0A, 0xFF], vec![0x12, 0x34]]));
+}
+
+#[test]
+fn test_object_dict_follows_the_xref() {
+    // The data of object 1 contains what looks like a later object 2.
+    let mut pdf = String::from("%PDF-1.4\n");
+    let first = pdf.len();
+    pdf.push_str(
+        "1 0 obj\n<< /Length 26 >>\nstream\n\n2 0 obj\n<< /Fake true >>\nendstream\nendobj\n",
+    );
+    let second = pdf.len();
+    pdf.push_str("2 0 obj\n<< /Real true >>\nendobj\n");
+    let xref = pdf.len();
+    pdf.push_str(&format!(
+        "xref\n0 3\n0000000000 65535 f \n{:010} 00000 n \n{:010} 00000 n \n\
+        trailer\n<< /Size 3 >>\nstartxref\n{}\n%%EOF\n",
+        first, second, xref
+    ));
+
+    assert_eq!(object_dict(pdf.as_bytes(), 2), Some("<< /Real true >>"));
+    assert_eq!(object_dict(pdf.as_bytes(), 1), Some("<< /Length 26 >>"));
+    assert_eq!(object_dict(pdf.as_bytes(), 3), None);
+}
+
+#[test]
+fn test_deep_nesting_is_rejected() {
+    let nested = |depth: usize| {
+        format!(
+            "<< /ID {}{} /Size 1 >>",
+            "[".repeat(depth),
+            "]".repeat(depth)
+        )
+    };
+    assert_eq!(dict_int(&nested(MAX_NESTING), "/Size"), Some(1));
+    assert_eq!(dict_int(&nested(MAX_NESTING + 1), "/Size"), None);
+    // Far deeper than the stack could recurse.
+    assert_eq!(dict_int(&nested(100_000), "/Size"), None);
+}
+
+#[test]
+fn test_xref_ranges_are_checked() {
+    let table = |subsection: &str| {
+        format!(
+            "xref\n{}\n0000000000 65535 f \ntrailer\n<< /Size 1 >>\n",
+            subsection
+        )
+    };
+    for subsection in ["4294967295 2", "0 4000000000"] {
+        let pdf = table(subsection);
+        assert!(read_xref_section(pdf.as_bytes(), 0).is_err());
+    }
+    assert_eq!(
+        read_xref_section(table("0 1").as_bytes(), 0).map(|section| section.entries.len()),
+        Ok(1)
+    );
+
+    let stream = |entries: &str| {
+        format!(
+            "1 0 obj\n<< /Type /XRef /Size 1 {} >>\nstream\n\x01\x00\x00\nendstream\nendobj\n",
+            entries
+        )
+    };
+    for entries in [
+        "/W [1 1 1] /Length 18446744073709551615",
+        "/W [1 1 1] /Length 3 /Index [18446744073709551615 2]",
+        "/W [1 1 1] /Length 3 /Index [0 18446744073709551615]",
+        "/W [1 1 18446744073709551615] /Length 3",
+    ] {
+        let pdf = stream(entries);
+        assert!(read_xref_section(pdf.as_bytes(), 0).is_err(), "{}", entries);
+    }
+    assert!(read_xref_section(stream("/W [1 1 1] /Length 3").as_bytes(), 0).is_ok());
+}
+
+#[test]
+fn test_map_refs() {
+    let dict = "<< /Type /Page /Parent 1 0 R /Contents [4 0 R 12 0 R] /Title (3 0 R) /Im5 5 0 R >>";
+    assert_eq!(
+        map_refs(dict, |id| id + 100),
+        "<< /Type /Page /Parent 101 0 R /Contents [104 0 R 112 0 R] /Title (3 0 R) /Im5 105 0 R >>"
+    );
+    let mut ids = Vec::new();
+    map_refs(
+        "<< /Width 8 /Height 16 /SMask 3 0 R /Decode [0 1] >>",
+        |id| {
+            ids.push(id);
+            id
+        },
+    );
+    assert_eq!(ids, [3]);
+}
+
+#[test]
+fn test_versions() {
+    assert_eq!(header_version(b"%PDF-1.4\n%\xE2\xE3"), Some((1, 4)));
+    assert_eq!(header_version(b"%PDF-2.0"), Some((2, 0)));
+    assert_eq!(header_version(b"PDF-1.4"), None);
+
+    let catalog = "<< /Type /Catalog /Version /1.5 /Pages 2 0 R >>";
+    assert_eq!(dict_version(catalog), Some((1, 5)));
+    assert_eq!(
+        with_entry(catalog, "/Version", "/1.7"),
+        "<< /Type /Catalog /Version /1.7 /Pages 2 0 R >>"
+    );
+    assert_eq!(
+        with_entry("<< /Type /Catalog /Pages 2 0 R >>", "/Version", "/1.7"),
+        "<< /Type /Catalog /Pages 2 0 R /Version /1.7 >>"
+    );
+}
@@ -0,0 +1,131 @@
+//! A compact sRGB ICC profile (version 2.1), built at run time.
+//!
+//! PDF/A needs an output intent with an embedded profile. Building it here,
+//! from the primaries and transfer curve of IEC 61966-2-1, avoids shipping a
+//! binary file with the crate. Version 2 profiles are what PDF 1.4 readers,
+//! and so PDF/A-1, expect.
+
+/// The name of the output condition the profile describes.
+pub(crate) const DESCRIPTION: &str = "sRGB IEC61966-2.1";
+
+/// The sRGB primaries, adapted to the D50 white point of the profile
+/// connection space with the Bradford transform.
+const RED: [f64; 3] = [0.436_074_7, 0.222_504_5, 0.013_932_2];
+const GREEN: [f64; 3] = [0.385_064_9, 0.716_878_6, 0.097_104_5];
+const BLUE: [f64; 3] = [0.143_080_4, 0.060_616_9, 0.714_173_3];
+const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+/// The number of entries of the tabulated transfer curve.
+const CURVE_POINTS: usize = 1024;
+
+/// Returns the bytes of the profile.
+pub(crate) fn srgb_profile() -> Vec<u8> {
+    let curve = curve();
+    let tags: [(&[u8; 4], Vec<u8>); 9] = [
+        (b"desc", description(DESCRIPTION)),
+        (b"cprt", text("No copyright, use freely")),
+        (b"wtpt", xyz(D50)),
+        (b"rXYZ", xyz(RED)),
+        (b"gXYZ", xyz(GREEN)),
+        (b"bXYZ", xyz(BLUE)),
+        (b"rTRC", curve.clone()),
+        (b"gTRC", curve.clone()),
+        (b"bTRC", curve),
+    ];
+
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let data_start = 128 + 4 + 12 * tags.len();
+    for (signature, tag) in &tags {
+        table.extend_from_slice(*signature);
+        table.extend_from_slice(&((data_start + data.len()) as u32).to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        data.extend_from_slice(tag);
+        // Tags start on four-byte boundaries.
+        data.resize(data.len().next_multiple_of(4), 0);
+    }
+
+    let mut profile = Vec::with_capacity(data_start + data.len());
+    profile.extend_from_slice(&((data_start + data.len()) as u32).to_be_bytes());
+    profile.extend_from_slice(&[0; 4]);
+    profile.extend_from_slice(&[2, 0x10, 0, 0]);
+    profile.extend_from_slice(b"mntrRGB XYZ ");
+    // The creation date, 2024-01-01, fixed so that output is reproducible.
+    for field in [2024u16, 1, 1, 0, 0, 0] {
+        profile.extend_from_slice(&field.to_be_bytes());
+    }
+    profile.extend_from_slice(b"acsp");
+    profile.extend_from_slice(&[0; 28]);
+    profile.extend_from_slice(&xyz(D50)[8..]);
+    profile.resize(128, 0);
+    profile.extend(table);
+    profile.extend(data);
+    profile
+}
+
+/// Encodes a number as `s15Fixed16Number`.
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
+
+/// An `XYZType` tag.
+fn xyz(value: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    for component in value {
+        tag.extend_from_slice(&s15_fixed16(component));
+    }
+    tag
+}
+
+/// A `curveType` tag tabulating the sRGB transfer function.
+fn curve() -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(CURVE_POINTS as u32).to_be_bytes());
+    for i in 0..CURVE_POINTS {
+        let v = i as f64 / (CURVE_POINTS - 1) as f64;
+        let linear = if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        };
+        tag.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+    tag
+}
+
+/// A `textDescriptionType` tag, with an ASCII description only.
+fn description(ascii: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(ascii.len() as u32 + 1).to_be_bytes());
+    tag.extend_from_slice(ascii.as_bytes());
+    tag.push(0);
+    // Empty Unicode and ScriptCode descriptions.
+    tag.extend_from_slice(&[0; 8]);
+    tag.extend_from_slice(&[0; 3]);
+    tag.extend_from_slice(&[0; 67]);
+    tag
+}
+
+/// A `textType` tag.
+fn text(ascii: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend_from_slice(ascii.as_bytes());
+    tag.push(0);
+    tag
+}
+
+#[test]
+fn test_srgb_profile() {
+    let profile = srgb_profile();
+    assert_eq!(
+        u32::from_be_bytes(profile[..4].try_into().unwrap()) as usize,
+        profile.len()
+    );
+    assert_eq!(&profile[12..20], b"mntrRGB ");
+    assert_eq!(&profile[36..40], b"acsp");
+    // The colorants add up to the white point.
+    for i in 0..3 {
+        assert!((RED[i] + GREEN[i] + BLUE[i] - D50[i]).abs() < 1e-3);
+    }
+    assert_eq!(curve().len(), 12 + 2 * CURVE_POINTS);
+}
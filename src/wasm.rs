@@ -0,0 +1,16 @@
+//! A JavaScript binding of the conversion with `wasm-bindgen`, enabled with
+//! the `wasm` feature on `wasm32` targets.
+//!
+//! Only conversions from bytes work in the browser: there is no file system,
+//! so the file-based functions fail with I/O errors. Without a clock, PDFs
+//! are dated like deterministic ones.
+
+use wasm_bindgen::prelude::*;
+
+/// Converts an image to a PDF with the default options, like
+/// [`img2pdf_from_bytes`](crate::img2pdf_from_bytes). The image and the PDF
+/// are `Uint8Array`s in JavaScript, and errors are thrown with their message.
+#[wasm_bindgen]
+pub fn wasm_convert(data: &[u8]) -> Result<Vec<u8>, JsError> {
+    crate::img2pdf_from_bytes(data).map_err(|e| JsError::new(&e.to_string()))
+}
@@ -0,0 +1,130 @@
+//! XMP metadata packets (ISO 16684-1) mirroring the document information
+//! dictionary.
+//!
+//! Each `/Info` entry has an XMP property that PDF/A requires to hold the
+//! same value: `/Title` is `dc:title`, `/Author` is `dc:creator`, `/Subject`
+//! is `dc:description`, `/Keywords` is `pdf:Keywords`, `/Producer` is
+//! `pdf:Producer` and `/Creator` is `xmp:CreatorTool`. The dates are
+//! `xmp:CreateDate` and `xmp:ModifyDate`.
+
+use crate::Info;
+
+/// Writes an XMP packet with the fields of `info` and the date `date`, in
+/// ISO 8601 form, as both creation and modification date. `pdfa` adds the
+/// PDF/A identification: the part and the conformance level.
+pub(crate) fn packet(info: &Info, date: &str, pdfa: Option<(u8, char)>) -> String {
+    let mut descriptions = Vec::new();
+    if let Some((part, conformance)) = pdfa {
+        descriptions.push(description(
+            "pdfaid=\"http://www.aiim.org/pdfa/ns/id/\"",
+            &format!(
+                "<pdfaid:part>{}</pdfaid:part><pdfaid:conformance>{}</pdfaid:conformance>",
+                part, conformance
+            ),
+        ));
+    }
+
+    let mut dc = String::from("<dc:format>application/pdf</dc:format>");
+    if let Some(title) = &info.title {
+        dc.push_str(&alternative("dc:title", title));
+    }
+    if let Some(author) = &info.author {
+        dc.push_str(&format!(
+            "<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>",
+            escape(author)
+        ));
+    }
+    if let Some(subject) = &info.subject {
+        dc.push_str(&alternative("dc:description", subject));
+    }
+    descriptions.push(description("dc=\"http://purl.org/dc/elements/1.1/\"", &dc));
+
+    let mut pdf = String::new();
+    if let Some(keywords) = &info.keywords {
+        pdf.push_str(&format!(
+            "<pdf:Keywords>{}</pdf:Keywords>",
+            escape(keywords)
+        ));
+    }
+    pdf.push_str(&format!(
+        "<pdf:Producer>{}</pdf:Producer>",
+        escape(info.producer())
+    ));
+    descriptions.push(description("pdf=\"http://ns.adobe.com/pdf/1.3/\"", &pdf));
+
+    let mut xmp = String::new();
+    if let Some(creator) = &info.creator {
+        xmp.push_str(&format!(
+            "<xmp:CreatorTool>{}</xmp:CreatorTool>",
+            escape(creator)
+        ));
+    }
+    xmp.push_str(&format!(
+        "<xmp:CreateDate>{0}</xmp:CreateDate><xmp:ModifyDate>{0}</xmp:ModifyDate>",
+        date
+    ));
+    descriptions.push(description("xmp=\"http://ns.adobe.com/xap/1.0/\"", &xmp));
+
+    format!(
+        "<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+         {}\
+         </rdf:RDF>\n\
+         </x:xmpmeta>\n\
+         <?xpacket end=\"w\"?>",
+        descriptions.concat()
+    )
+}
+
+/// An `rdf:Description` of the properties in one namespace.
+fn description(namespace: &str, properties: &str) -> String {
+    format!(
+        "<rdf:Description rdf:about=\"\" xmlns:{}>{}</rdf:Description>\n",
+        namespace, properties
+    )
+}
+
+/// A language alternative property with only a default value.
+fn alternative(property: &str, value: &str) -> String {
+    format!(
+        "<{0}><rdf:Alt><rdf:li xml:lang=\"x-default\">{1}</rdf:li></rdf:Alt></{0}>",
+        property,
+        escape(value)
+    )
+}
+
+/// Escapes the characters with a meaning in XML.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[test]
+fn test_packet() {
+    let info = Info {
+        title: Some("Scans & <notes>".to_string()),
+        author: Some("Archive".to_string()),
+        ..Info::default()
+    };
+    let packet = packet(&info, "2024-02-29T13:05:09Z", Some((1, 'B')));
+    assert!(packet.starts_with("<?xpacket begin=\"\u{FEFF}\""));
+    assert!(packet.ends_with("<?xpacket end=\"w\"?>"));
+    assert!(packet.contains("<pdfaid:part>1</pdfaid:part><pdfaid:conformance>B"));
+    assert!(packet.contains(
+        "<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">Scans &amp; &lt;notes&gt;</rdf:li>"
+    ));
+    assert!(packet.contains("<rdf:Seq><rdf:li>Archive</rdf:li></rdf:Seq>"));
+    assert!(packet.contains("<xmp:CreateDate>2024-02-29T13:05:09Z</xmp:CreateDate>"));
+    assert!(!packet.contains("pdf:Keywords"));
+    assert!(packet.contains("<pdf:Producer>img2pdf (rust) "));
+}